@@ -0,0 +1,30 @@
+-- The post index, see src/post_index.rs. Runs on D1 and SQLite.
+
+-- Post, repost and like records of tracked DIDs.
+CREATE TABLE IF NOT EXISTS records (
+    uri TEXT PRIMARY KEY,
+    did TEXT NOT NULL,
+    -- post, repost or like
+    kind TEXT NOT NULL,
+    -- RFC 3339 in UTC with microseconds, so that it sorts by time
+    created_at TEXT NOT NULL,
+    -- the post that a repost or like refers to
+    subject TEXT,
+    -- the flags of a post: 1 reply, 2 media, 4 links
+    flags INTEGER,
+    -- the languages of a post, comma-separated
+    langs TEXT
+);
+
+CREATE INDEX IF NOT EXISTS records_by_did ON records (did, kind, created_at);
+
+-- DIDs whose records are read from the Jetstream.
+CREATE TABLE IF NOT EXISTS tracked_dids (
+    did TEXT PRIMARY KEY
+);
+
+-- The Jetstream time in microseconds that events were read up to.
+CREATE TABLE IF NOT EXISTS jetstream_cursor (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    time_us INTEGER NOT NULL
+);
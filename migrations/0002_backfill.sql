@@ -0,0 +1,6 @@
+-- Backfills of tracked DIDs from their repositories, see src/backfill.rs.
+
+-- 1 if the DID's records from before it was tracked should be backfilled
+ALTER TABLE tracked_dids ADD COLUMN backfill INTEGER NOT NULL DEFAULT 0;
+-- the repository revision that the backfill completed at
+ALTER TABLE tracked_dids ADD COLUMN backfill_rev TEXT;
@@ -0,0 +1,8 @@
+-- Attempts of backfills, so that failing ones are retried with backoff and given up.
+
+-- the number of backfill attempts started
+ALTER TABLE tracked_dids ADD COLUMN backfill_attempts INTEGER NOT NULL DEFAULT 0;
+-- when the last backfill attempt started, RFC 3339 as in records.created_at
+ALTER TABLE tracked_dids ADD COLUMN backfill_attempted_at TEXT;
+-- the time before which the backfill is not retried
+ALTER TABLE tracked_dids ADD COLUMN backfill_retry_at TEXT;
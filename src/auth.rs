@@ -9,12 +9,14 @@ pub struct JwtPayload {
     pub iss: String,
     pub aud: String,
     pub exp: i64,
+    pub lxm: Option<String>,
 }
 
 #[derive(Debug)]
 pub enum JwtError {
     Bad,
     BadAudience,
+    BadLexiconMethod,
     Expired,
 }
 
@@ -52,6 +54,7 @@ pub trait SigningKeyProvider {
 pub async fn verify_jwt<S: SigningKeyProvider>(
     jwt: &str,
     did: Option<&str>,
+    lxm: Option<&str>,
     signing_key_provider: S,
 ) -> Result<JwtPayload> {
     let parts = jwt.splitn(3, '.').collect::<Vec<_>>();
@@ -80,6 +83,23 @@ pub async fn verify_jwt<S: SigningKeyProvider>(
             ));
         }
     }
+    if let Some(lxm) = lxm {
+        match &payload.lxm {
+            None => {
+                return Err(Error::AuthRequiredError(
+                    JwtError::BadLexiconMethod,
+                    String::from("missing jwt lexicon method (\"lxm\")"),
+                ))
+            }
+            Some(got) if got != lxm => {
+                return Err(Error::AuthRequiredError(
+                    JwtError::BadLexiconMethod,
+                    format!("bad jwt lexicon method (\"lxm\"). must match: {lxm}"),
+                ))
+            }
+            _ => {}
+        }
+    }
     let msg = [parts[0], parts[1]].join(".");
     let msg_bytes = msg.as_bytes();
     let sig_bytes = URL_SAFE_NO_PAD
@@ -97,7 +117,6 @@ fn parse_payload(b64: &str) -> Result<JwtPayload> {
     let decoded = URL_SAFE_NO_PAD
         .decode(b64.as_bytes())
         .map_err(Error::Base64Decode)?;
-    println!("decoded: {:#?}", String::from_utf8_lossy(&decoded));
     serde_json::from_slice(&decoded)
         .map_err(|_| Error::AuthRequiredError(JwtError::Bad, String::from("poorly formatted jwt")))
 }
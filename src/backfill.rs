@@ -0,0 +1,117 @@
+//! Backfilling the post index from a repository export, for the records a viewer made
+//! before the Jetstream was read for them.
+use crate::post_index::{self, Change, IndexedRecord, PostIndex, RecordKind};
+use crate::repo::{self, Repo};
+use atrium_api::client::AtpServiceClient;
+use atrium_api::com::atproto::sync::get_repo;
+use atrium_api::xrpc::XrpcClient;
+use chrono::{DateTime, Duration, Utc};
+
+/// Records applied to the index at once.
+const BATCH_SIZE: usize = 100;
+
+/// The delay before a backfill is retried after its first attempt, doubled for every
+/// further one.
+const RETRY_DELAY_HOURS: i64 = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidDid(String),
+    GetRepo(atrium_api::xrpc::error::Error<get_repo::Error>),
+    Repo(repo::Error),
+    /// The PDS returned the repository of another DID.
+    WrongRepo(String),
+    Index(post_index::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::InvalidDid(did) => write!(f, "invalid DID: {did}"),
+            Error::GetRepo(err) => write!(f, "failed to get repo: {err}"),
+            Error::Repo(err) => write!(f, "{err}"),
+            Error::WrongRepo(did) => write!(f, "got the repo of {did}"),
+            Error::Index(err) => write!(f, "failed to write post index: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackfillStats {
+    pub rev: String,
+    /// Records of the repository, indexed or not.
+    pub records: usize,
+    pub indexed: usize,
+    /// Records of an indexed kind that could not be read.
+    pub invalid: usize,
+}
+
+/// Download the repository of `did` from its PDS, verify that its commit is signed by
+/// `signing_key` (a `did:key`) and index its posts, reposts and likes.
+pub async fn backfill<T, I>(
+    pds: &AtpServiceClient<T>,
+    index: &I,
+    did: &str,
+    signing_key: &str,
+) -> Result<BackfillStats>
+where
+    T: XrpcClient + Send + Sync,
+    I: PostIndex,
+{
+    let car = pds
+        .service
+        .com
+        .atproto
+        .sync
+        .get_repo(get_repo::Parameters {
+            did: did.parse().map_err(|_| Error::InvalidDid(did.into()))?,
+            since: None,
+        })
+        .await
+        .map_err(Error::GetRepo)?;
+    let repo = Repo::from_car(&car).map_err(Error::Repo)?;
+    if repo.did != did {
+        return Err(Error::WrongRepo(repo.did));
+    }
+    repo.verify_signature(signing_key).map_err(Error::Repo)?;
+    let entries = repo.entries().map_err(Error::Repo)?;
+    let mut stats = BackfillStats {
+        rev: repo.rev.clone(),
+        records: entries.len(),
+        ..Default::default()
+    };
+    let mut changes = Vec::new();
+    for entry in entries {
+        if RecordKind::from_collection(entry.collection()).is_none() {
+            continue;
+        }
+        let record = repo.record(&entry.cid).map_err(Error::Repo)?;
+        match IndexedRecord::parse(did, entry.collection(), entry.rkey(), record) {
+            Some(record) => changes.push(Change::Put(record)),
+            None => stats.invalid += 1,
+        }
+        if changes.len() >= BATCH_SIZE {
+            stats.indexed += changes.len();
+            index.apply(&changes, None).await.map_err(Error::Index)?;
+            changes.clear();
+        }
+    }
+    stats.indexed += changes.len();
+    index.apply(&changes, None).await.map_err(Error::Index)?;
+    index
+        .complete_backfill(did, &repo.rev)
+        .await
+        .map_err(Error::Index)?;
+    Ok(stats)
+}
+
+/// When to retry a backfill whose attempt number `attempt` (from 1) started at `at`, if it
+/// does not complete.
+pub fn retry_at(attempt: u32, at: DateTime<Utc>) -> DateTime<Utc> {
+    let hours = RETRY_DELAY_HOURS.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    at + Duration::hours(hours)
+}
@@ -0,0 +1,63 @@
+use crate::http::RetryingClient;
+use crate::identity::did_resolver;
+use crate::{Args, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use bsky_timemachine::auth::{self, verify_jwt, SigningKeyProvider};
+use bsky_timemachine::identity::did::did_resolver::{self as resolver, DidResolver, Resolver};
+use std::collections::HashMap;
+
+/// Signing keys resolved live, or read from a JSON file mapping issuer DIDs to `did:key`s.
+pub enum KeyProvider {
+    Live(Box<DidResolver<RetryingClient>>),
+    File(HashMap<String, String>),
+}
+
+impl KeyProvider {
+    pub fn new(keys: Option<&str>) -> Result<Self> {
+        Ok(match keys {
+            Some(path) => Self::File(serde_json::from_slice(&std::fs::read(path)?)?),
+            None => Self::Live(Box::new(did_resolver()?)),
+        })
+    }
+}
+
+impl SigningKeyProvider for &KeyProvider {
+    async fn get_signing_key(&self, iss: &str, force_refresh: bool) -> auth::Result<String> {
+        match self {
+            KeyProvider::Live(did_resolver) => did_resolver
+                .resolve_atproto_key(iss, force_refresh)
+                .await
+                .map_err(auth::Error::DidResolver),
+            KeyProvider::File(keys) => keys.get(iss).cloned().ok_or_else(|| {
+                auth::Error::DidResolver(resolver::Error::DidNotFoundError(iss.into()))
+            }),
+        }
+    }
+}
+
+pub async fn verify(args: &Args) -> Result<()> {
+    let jwt = args.positional(1, "jwt")?;
+    let aud = args.positional(2, "audience")?;
+    for (name, part) in ["header", "payload"].iter().zip(jwt.split('.')) {
+        match URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| {
+                serde_json::from_slice::<serde_json::Value>(&bytes).map_err(|e| e.to_string())
+            }) {
+            Ok(value) => println!("{name}: {}", serde_json::to_string_pretty(&value)?),
+            Err(err) => println!("{name}: - ({err})"),
+        }
+    }
+    let key_provider = KeyProvider::new(args.option("keys"))?;
+    match verify_jwt(jwt, Some(aud), args.option("lxm"), &key_provider).await {
+        Ok(payload) => {
+            println!("verified: {payload:?}");
+            Ok(())
+        }
+        Err(err) => {
+            println!("error: {err}\n       {err:?}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::auth::KeyProvider;
+use crate::generator::Client;
+use crate::http::caching_client;
+use crate::identity::{did_resolver, resolve_identifier};
+use crate::{load_manifest, Args, Config, Result};
+use atrium_api::client::AtpServiceClient;
+use atrium_api::records::{KnownRecord, Record};
+use atrium_xrpc_client::reqwest::ReqwestClient;
+use bsky_timemachine::auth::verify_jwt;
+use bsky_timemachine::config::{ProcessEnv, UpstreamConfig};
+use bsky_timemachine::filter;
+use bsky_timemachine::identity::did::did_resolver::Resolver;
+use bsky_timemachine::manifest::Source;
+use bsky_timemachine::post_index::MemoryIndex;
+use bsky_timemachine::post_status::MemoryStatusCache;
+use bsky_timemachine::skeleton::{self, SkeletonQuery};
+use chrono::Utc;
+
+/// Obtain the viewer DID the worker would see, from a given or freshly minted token.
+async fn viewer_did(config: &Config, args: &Args) -> Result<String> {
+    let token = match (args.option("token"), args.flag("mint")) {
+        (Some(token), _) => Some(token.to_string()),
+        (None, true) => {
+            let client = Client::login(config).await?;
+            let output = client
+                .agent
+                .api
+                .com
+                .atproto
+                .server
+                .get_service_auth(
+                    atrium_api::com::atproto::server::get_service_auth::Parameters {
+                        aud: Config::required(&config.service_did, "SERVICE_DID")?.parse()?,
+                    },
+                )
+                .await?;
+            Some(output.token)
+        }
+        (None, false) => None,
+    };
+    let identifier = args.positional(1, "did or handle")?;
+    match token {
+        Some(token) => {
+            let payload = verify_jwt(
+                &token,
+                Some(Config::required(&config.service_did, "SERVICE_DID")?),
+                None,
+                &KeyProvider::new(args.option("keys"))?,
+            )
+            .await?;
+            let did = resolve_identifier(identifier).await?;
+            if payload.iss != did {
+                eprintln!("warning: token issued by {}, not {did}", payload.iss);
+            }
+            Ok(payload.iss)
+        }
+        None => resolve_identifier(identifier).await,
+    }
+}
+
+pub async fn simulate(config: &Config, args: &Args) -> Result<()> {
+    let manifest = load_manifest(args)?;
+    let feed = match args.option("feed") {
+        Some(rkey) => manifest
+            .feed(rkey)
+            .ok_or_else(|| format!("feed not in manifest: {rkey}"))?,
+        None => &manifest.feeds[0],
+    };
+    let did = viewer_did(config, args).await?;
+    let history = did_resolver()?
+        .resolve_history(&did)
+        .await?
+        .unwrap_or_default();
+    let upstream = UpstreamConfig::load(&ProcessEnv)?;
+    let appview = AtpServiceClient::new(caching_client(
+        &upstream.appview_url,
+        upstream.cache.clone(),
+    ));
+    let query = SkeletonQuery {
+        did,
+        limit: match args.option("limit") {
+            Some(limit) => Some(limit.parse::<u8>()?.try_into()?),
+            None => None,
+        },
+        cursor: args.option("cursor").map(String::from),
+        now: Utc::now(),
+        rule: feed.rule.clone(),
+        lang: filter::search_lang(&feed.filters),
+    };
+    let pds = match feed.source == Source::Likes || feed.include_reposts {
+        true => Some(AtpServiceClient::new(ReqwestClient::new(
+            did_resolver()?
+                .ensure_resolve(&query.did, false)
+                .await?
+                .get_pds_endpoint()
+                .ok_or("no PDS endpoint in DID document")?,
+        ))),
+        false => None,
+    };
+    let relationships = caching_client(&upstream.appview_url, upstream.cache.clone());
+    let upstream = skeleton::Upstream {
+        appview: &appview,
+        relationships: &relationships,
+        pds: pds.as_ref(),
+        index: None::<&MemoryIndex>,
+        statuses: &MemoryStatusCache::new(1000),
+    };
+    let skeleton = skeleton::assemble(&upstream, feed, query, &history).await?;
+    for item in &skeleton.items {
+        println!(
+            "{}\t{}\t{}{}",
+            item.created_at.to_rfc3339(),
+            item.uri,
+            item.feed_context.as_deref().unwrap_or("-"),
+            item.repost
+                .as_deref()
+                .map(|repost| format!("\treposted in {repost}"))
+                .unwrap_or_default()
+        );
+    }
+    println!("cursor: {}", skeleton.cursor.as_deref().unwrap_or("-"));
+    if args.flag("hydrate") {
+        for uris in skeleton.items.chunks(25) {
+            let output = appview
+                .service
+                .app
+                .bsky
+                .feed
+                .get_posts(atrium_api::app::bsky::feed::get_posts::Parameters {
+                    uris: uris.iter().map(|item| item.uri.clone()).collect(),
+                })
+                .await?;
+            for post in output.posts {
+                if let Record::Known(KnownRecord::AppBskyFeedPost(record)) = &post.record {
+                    println!(
+                        "\n{} @{}\n{}",
+                        post.uri,
+                        post.author.handle.as_str(),
+                        record.text
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use crate::{load_manifest, Args, Config, Result};
+use atrium_api::agent::{store::MemorySessionStore, AtpAgent};
+use atrium_api::records::{KnownRecord, Record};
+use atrium_api::types::string::{AtIdentifier, Datetime, Did, Nsid};
+use atrium_api::types::Collection;
+use atrium_api::xrpc::error::{Error, ErrorResponseBody, XrpcError, XrpcErrorKind};
+use atrium_xrpc_client::reqwest::ReqwestClient;
+use std::env;
+use std::str::FromStr;
+
+pub struct Client {
+    pub agent: AtpAgent<MemorySessionStore, ReqwestClient>,
+    pub did: Did,
+}
+
+impl Client {
+    pub async fn login(config: &Config) -> Result<Self> {
+        let agent = AtpAgent::new(
+            ReqwestClient::new(config.service.as_deref().unwrap_or("https://bsky.social")),
+            MemorySessionStore::default(),
+        );
+        let session = agent
+            .login(
+                Config::required(&config.identifier, "BLUESKY_IDENTIFIER")?,
+                Config::required(&config.password, "BLUESKY_PASSWORD")?,
+            )
+            .await?;
+        Ok(Self {
+            agent,
+            did: session.did,
+        })
+    }
+    async fn get_generator(
+        &self,
+        rkey: &str,
+    ) -> Result<Option<atrium_api::com::atproto::repo::get_record::Output>> {
+        match self
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .get_record(atrium_api::com::atproto::repo::get_record::Parameters {
+                cid: None,
+                collection: Nsid::from_str(atrium_api::app::bsky::feed::Generator::NSID)?,
+                repo: AtIdentifier::Did(self.did.clone()),
+                rkey: rkey.into(),
+            })
+            .await
+        {
+            Ok(output) => Ok(Some(output)),
+            Err(Error::XrpcResponse(XrpcError {
+                error: Some(XrpcErrorKind::Undefined(ErrorResponseBody { error, .. })),
+                ..
+            })) if error.as_deref() == Some("RecordNotFound") => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Create or update the generator records of the manifest's feeds, or only of `<rkey>`.
+pub async fn publish(config: &Config, args: &Args) -> Result<()> {
+    let manifest = load_manifest(args)?;
+    let feeds = match args.positional.get(1) {
+        Some(rkey) => vec![manifest
+            .feed(rkey)
+            .ok_or_else(|| format!("feed not in manifest: {rkey}"))?],
+        None => manifest.feeds.iter().collect(),
+    };
+    let service_did = Config::required(&config.service_did, "SERVICE_DID")?;
+    let client = Client::login(config).await?;
+    if let Some(publisher) = manifest.publisher.as_deref() {
+        if publisher != client.did.as_str() {
+            return Err(format!(
+                "manifest publisher is {publisher}, logged in as {}",
+                client.did.as_str()
+            )
+            .into());
+        }
+    }
+    for feed in feeds {
+        let existing = match client.get_generator(&feed.rkey).await? {
+            Some(atrium_api::com::atproto::repo::get_record::Output {
+                value: Record::Known(KnownRecord::AppBskyFeedGenerator(record)),
+                ..
+            }) => Some(record),
+            _ => None,
+        };
+        let avatar = if let Some(path) = &feed.avatar {
+            let output = client
+                .agent
+                .api
+                .com
+                .atproto
+                .repo
+                .upload_blob(std::fs::read(path)?)
+                .await?;
+            Some(output.blob)
+        } else {
+            existing.as_ref().and_then(|record| record.avatar.clone())
+        };
+        let record = atrium_api::app::bsky::feed::generator::Record {
+            accepts_interactions: None,
+            avatar,
+            created_at: existing
+                .as_ref()
+                .map_or_else(Datetime::now, |record| record.created_at.clone()),
+            description: feed.description.clone(),
+            description_facets: None,
+            did: service_did.parse()?,
+            display_name: feed.display_name.clone(),
+            labels: None,
+        };
+        let input = atrium_api::com::atproto::repo::put_record::Input {
+            collection: Nsid::from_str(atrium_api::app::bsky::feed::Generator::NSID)?,
+            record: Record::Known(KnownRecord::AppBskyFeedGenerator(Box::new(record))),
+            repo: AtIdentifier::Did(client.did.clone()),
+            rkey: feed.rkey.clone(),
+            swap_commit: None,
+            swap_record: None,
+            validate: None,
+        };
+        let output = client.agent.api.com.atproto.repo.put_record(input).await?;
+        println!("{}", output.uri);
+    }
+    Ok(())
+}
+
+pub async fn list(config: &Config) -> Result<()> {
+    let client = Client::login(config).await?;
+    let mut cursor = None;
+    loop {
+        let output = client
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .list_records(atrium_api::com::atproto::repo::list_records::Parameters {
+                collection: Nsid::from_str(atrium_api::app::bsky::feed::Generator::NSID)?,
+                cursor,
+                limit: None,
+                repo: AtIdentifier::Did(client.did.clone()),
+                reverse: None,
+                rkey_end: None,
+                rkey_start: None,
+            })
+            .await?;
+        for record in &output.records {
+            match &record.value {
+                Record::Known(KnownRecord::AppBskyFeedGenerator(generator)) => {
+                    println!("{}\t{}", record.uri, generator.display_name)
+                }
+                _ => println!("{}", record.uri),
+            }
+        }
+        if output.records.is_empty() || output.cursor.is_none() {
+            break;
+        }
+        cursor = output.cursor;
+    }
+    Ok(())
+}
+
+pub async fn show(config: &Config, args: &Args) -> Result<()> {
+    let rkey = args.positional(1, "rkey")?;
+    let client = Client::login(config).await?;
+    match client.get_generator(rkey).await? {
+        Some(output) => println!("{}", serde_json::to_string_pretty(&output)?),
+        None => return Err(format!("record not found: {rkey}").into()),
+    }
+    Ok(())
+}
+
+pub async fn delete(config: &Config, args: &Args) -> Result<()> {
+    let rkey = match args.positional.get(1) {
+        Some(rkey) => rkey.clone(),
+        None => env::var("RECORD_KEY")?,
+    };
+    let client = Client::login(config).await?;
+    let input = atrium_api::com::atproto::repo::delete_record::Input {
+        collection: Nsid::from_str(atrium_api::app::bsky::feed::Generator::NSID)?,
+        repo: AtIdentifier::Did(client.did.clone()),
+        rkey,
+        swap_commit: None,
+        swap_record: None,
+    };
+    println!(
+        "{:?}",
+        client
+            .agent
+            .api
+            .com
+            .atproto
+            .repo
+            .delete_record(input)
+            .await?
+    );
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use async_trait::async_trait;
+use atrium_api::xrpc::HttpClient;
+use atrium_xrpc_client::reqwest::ReqwestClient;
+use bsky_timemachine::http_client::{
+    MaxResponseSize, RequestTimeout, ResponseTooLarge, RetryingHttpClient, Sleep,
+};
+use bsky_timemachine::response_cache::{CacheOptions, CachingHttpClient, MemoryCache};
+use http::{Request, Response};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// A cloneable `HttpClient` for the library's resolvers, honoring `RequestTimeout` and
+/// `MaxResponseSize`.
+#[derive(Clone, Default)]
+pub struct SharedClient(reqwest::Client);
+
+impl SharedClient {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, BoxError> {
+        let max_size = request
+            .extensions()
+            .get::<MaxResponseSize>()
+            .map(|MaxResponseSize(max_size)| *max_size);
+        let mut response = self.0.execute(request.try_into()?).await?;
+        let mut builder = Response::builder().status(response.status());
+        for (name, value) in response.headers() {
+            builder = builder.header(name, value);
+        }
+        let Some(max_size) = max_size else {
+            return Ok(builder.body(response.bytes().await?.to_vec())?);
+        };
+        let declared = response
+            .content_length()
+            .and_then(|length| length.try_into().ok());
+        if let Some(declared) = declared.filter(|declared| *declared > max_size) {
+            return Err(ResponseTooLarge(declared).into());
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() > max_size {
+                return Err(ResponseTooLarge(body.len()).into());
+            }
+        }
+        Ok(builder.body(body)?)
+    }
+}
+
+#[async_trait]
+impl HttpClient for SharedClient {
+    async fn send_http(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, BoxError> {
+        match request.extensions().get::<RequestTimeout>().copied() {
+            Some(RequestTimeout(timeout)) => {
+                tokio::time::timeout(timeout, self.send(request)).await?
+            }
+            None => self.send(request).await,
+        }
+    }
+}
+
+/// `SharedClient` retrying transient failures.
+pub type RetryingClient = RetryingHttpClient<SharedClient, TokioSleep>;
+
+/// Sleeps on the tokio timer.
+#[derive(Clone, Copy)]
+pub struct TokioSleep;
+
+#[async_trait]
+impl Sleep for TokioSleep {
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// An XRPC client caching query responses in memory for the run, as the worker does in the
+/// Cache API, e.g. posts hydrated for filtering and again for `--hydrate`.
+pub type CachingClient = CachingHttpClient<ReqwestClient, MemoryCache>;
+
+/// Responses kept by a `CachingClient`.
+const CACHE_CAPACITY: usize = 256;
+
+pub fn caching_client(base_uri: impl AsRef<str>, options: CacheOptions) -> CachingClient {
+    CachingHttpClient::with_options(
+        ReqwestClient::new(base_uri),
+        MemoryCache::new(CACHE_CAPACITY),
+        options,
+    )
+}
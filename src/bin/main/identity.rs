@@ -0,0 +1,104 @@
+use crate::http::{RetryingClient, SharedClient, TokioSleep};
+use crate::{Args, Result};
+use atrium_api::client::AtpServiceClient;
+use atrium_xrpc_client::reqwest::ReqwestClient;
+use bsky_timemachine::config::{self, ProcessEnv, ResolverConfig, UpstreamConfig};
+use bsky_timemachine::crypto::{did, utils};
+use bsky_timemachine::http_client::RetryingHttpClient;
+use bsky_timemachine::identity::did::atproto_data;
+use bsky_timemachine::identity::did::did_cache::NoCache;
+use bsky_timemachine::identity::did::did_resolver::{DidResolver, DidResolverOptions, Resolver};
+use std::collections::HashMap;
+use std::env;
+
+pub fn did_resolver() -> Result<DidResolver<RetryingClient>> {
+    let upstream = UpstreamConfig::load(&ProcessEnv)?;
+    let resolver = ResolverConfig::load(&ProcessEnv)?;
+    Ok(DidResolver::with_options(
+        RetryingHttpClient::with_options(SharedClient::default(), TokioSleep, upstream.retry),
+        DidResolverOptions {
+            plc_url: upstream.plc_url,
+            web_allowed_hosts: resolver.web_allowed_hosts,
+            timeout: resolver.timeout,
+            max_document_size: resolver.max_document_size,
+            cache: NoCache,
+        },
+    ))
+}
+
+/// Resolve a handle to a DID, passing DIDs through unchanged.
+pub async fn resolve_identifier(identifier: &str) -> Result<String> {
+    if identifier.starts_with("did:") {
+        return Ok(identifier.into());
+    }
+    let client = AtpServiceClient::new(ReqwestClient::new(
+        UpstreamConfig::load(&ProcessEnv)?.appview_url,
+    ));
+    let output = client
+        .service
+        .com
+        .atproto
+        .identity
+        .resolve_handle(
+            atrium_api::com::atproto::identity::resolve_handle::Parameters {
+                handle: identifier.parse()?,
+            },
+        )
+        .await?;
+    Ok(output.did.to_string())
+}
+
+pub async fn resolve(args: &Args) -> Result<()> {
+    let did = resolve_identifier(args.positional(2, "did or handle")?).await?;
+    let did_doc = did_resolver()?.ensure_resolve(&did, true).await?;
+    println!("did:      {}", did_doc.get_did());
+    println!(
+        "handle:   {}",
+        did_doc.get_handle().as_deref().unwrap_or("-")
+    );
+    println!(
+        "pds:      {}",
+        did_doc.get_pds_endpoint().as_deref().unwrap_or("-")
+    );
+    match atproto_data::ensure_atproto_key(&did_doc) {
+        Ok(did_key) => {
+            println!("did:key:  {did_key}");
+            println!("multikey: {}", utils::extract_multikey(&did_key)?);
+        }
+        Err(err) => println!("did:key:  - ({err})"),
+    }
+    println!("{}", serde_json::to_string_pretty(&did_doc)?);
+    Ok(())
+}
+
+pub fn decode_did_key(args: &Args) -> Result<()> {
+    let did_key = args.positional(2, "did:key")?;
+    let parsed = did::parse_did_key(did_key)?;
+    println!("curve:        {:?}", parsed.jwt_alg);
+    println!(
+        "uncompressed: {}",
+        parsed
+            .key
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    );
+    Ok(())
+}
+
+pub fn did_doc(args: &Args) -> Result<()> {
+    let mut vars = env::vars().collect::<HashMap<_, _>>();
+    for name in [
+        "SERVICE_DID",
+        "SERVICE_ENDPOINT",
+        "SERVICE_VERIFICATION_METHODS",
+        "SERVICE_ALSO_KNOWN_AS",
+    ] {
+        if let Some(value) = args.option(&name.to_lowercase().replace('_', "-")) {
+            vars.insert(name.into(), value.into());
+        }
+    }
+    let identity = config::load_service_identity(&vars)?;
+    println!("{}", serde_json::to_string_pretty(&identity.document()?)?);
+    Ok(())
+}
@@ -0,0 +1,159 @@
+mod auth;
+mod feed;
+mod generator;
+mod http;
+mod identity;
+
+use bsky_timemachine::manifest::Manifest;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const USAGE: &str = "\
+Usage: main [--config <path>] [--manifest <path>] <command> [args]
+
+Commands:
+  publish [<rkey>] Create or update the feed generator records of the manifest
+  list             List the feed generator records of the account
+  show <rkey>      Show a feed generator record
+  delete [<rkey>]  Delete a feed generator record (defaults to RECORD_KEY)
+  did resolve <did or handle>
+                   Print the DID document, handle, PDS endpoint and atproto signing key
+  did-key decode <did:key>
+                   Print the curve and uncompressed public key of a did:key
+  did-doc [--service-did <did>] [--service-endpoint <url>]
+          [--service-verification-methods <id=did:key,...>]
+          [--service-also-known-as <uri,...>]
+                   Print the did:web document served at /.well-known/did.json,
+                   defaulting to the SERVICE_* environment variables
+  verify-jwt <jwt> <audience> [--lxm <nsid>] [--keys <path>]
+                   Verify a service JWT, resolving the signing key live or from a
+                   JSON file mapping issuer DIDs to did:keys
+  simulate <did or handle> [--token <jwt> | --mint] [--keys <path>]
+           [--feed <rkey>] [--limit <n>] [--cursor <cursor>] [--hydrate]
+                   Build the feed skeleton the worker would serve to the user
+
+Credentials are read from BLUESKY_IDENTIFIER, BLUESKY_PASSWORD, BLUESKY_SERVICE
+and SERVICE_DID, falling back to the JSON file given by --config or BLUESKY_CONFIG.
+Feeds are defined by the TOML or JSON manifest given by --manifest or FEEDS_MANIFEST,
+falling back to the manifest built into the worker.";
+
+/// Contents of the config file. Environment variables take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Config {
+    identifier: Option<String>,
+    password: Option<String>,
+    service: Option<String>,
+    service_did: Option<String>,
+}
+
+impl Config {
+    fn load(path: Option<&str>) -> Result<Self> {
+        let mut config = match path.map(String::from).or(env::var("BLUESKY_CONFIG").ok()) {
+            Some(path) => serde_json::from_slice(&std::fs::read(path)?)?,
+            None => Self::default(),
+        };
+        for (name, field) in [
+            ("BLUESKY_IDENTIFIER", &mut config.identifier),
+            ("BLUESKY_PASSWORD", &mut config.password),
+            ("BLUESKY_SERVICE", &mut config.service),
+            ("SERVICE_DID", &mut config.service_did),
+        ] {
+            if let Ok(value) = env::var(name) {
+                *field = Some(value);
+            }
+        }
+        Ok(config)
+    }
+    fn required<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str> {
+        value
+            .as_deref()
+            .ok_or_else(|| format!("{name} is not configured").into())
+    }
+}
+
+/// The feed manifest from `--manifest <path>`, FEEDS_MANIFEST, or the embedded default.
+fn load_manifest(args: &Args) -> Result<Manifest> {
+    Ok(match args.option("manifest") {
+        Some(path) => Manifest::parse(&std::fs::read_to_string(path)?)?,
+        None => match env::var("FEEDS_MANIFEST") {
+            Ok(text) => Manifest::parse(&text)?,
+            Err(_) => Manifest::embedded()?,
+        },
+    })
+}
+
+/// Options that take no value.
+const FLAGS: &[&str] = &["mint", "hydrate"];
+
+/// Positional arguments, `--key value` options and `--flag`s.
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut flags = Vec::new();
+        while let Some(arg) = args.next() {
+            if let Some(flag) = arg.strip_prefix("--").filter(|key| FLAGS.contains(key)) {
+                flags.push(flag.to_string());
+            } else if let Some(key) = arg.strip_prefix("--") {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for --{key}"))?;
+                options.insert(key.to_string(), value);
+            } else {
+                positional.push(arg);
+            }
+        }
+        Ok(Self {
+            positional,
+            options,
+            flags,
+        })
+    }
+    fn option(&self, key: &str) -> Option<&str> {
+        self.options.get(key).map(String::as_str)
+    }
+    fn flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+    fn positional(&self, index: usize, name: &str) -> Result<&str> {
+        self.positional
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| format!("missing argument <{name}>\n\n{USAGE}").into())
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let args = Args::parse(env::args().skip(1))?;
+    let config = Config::load(args.option("config"))?;
+    match args.positional.first().map(String::as_str) {
+        Some("publish") => generator::publish(&config, &args).await,
+        Some("list") => generator::list(&config).await,
+        Some("show") => generator::show(&config, &args).await,
+        Some("delete") => generator::delete(&config, &args).await,
+        Some("did-doc") => identity::did_doc(&args),
+        Some("simulate") => feed::simulate(&config, &args).await,
+        Some("verify-jwt") => auth::verify(&args).await,
+        Some("did") if args.positional.get(1).is_some_and(|s| s == "resolve") => {
+            identity::resolve(&args).await
+        }
+        Some("did-key") if args.positional.get(1).is_some_and(|s| s == "decode") => {
+            identity::decode_did_key(&args)
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! The precomputed first pages of `skeleton_cache` and the records of active viewers, and
+//! when they are refreshed.
+use atrium_api::app::bsky::feed::get_feed_skeleton;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedPage {
+    pub generated_at: DateTime<Utc>,
+    pub output: get_feed_skeleton::Output,
+}
+
+impl CachedPage {
+    /// Seconds left until the page is older than `max_age`, if any.
+    pub fn remaining(&self, max_age: u64, now: DateTime<Utc>) -> Option<u64> {
+        let age = u64::try_from((now - self.generated_at).num_seconds()).unwrap_or_default();
+        max_age.checked_sub(age).filter(|remaining| *remaining > 0)
+    }
+    /// Whether the page is more than half its `max_age` old, so that scheduled runs
+    /// refresh it before it expires.
+    pub fn is_stale(&self, max_age: u64, now: DateTime<Utc>) -> bool {
+        self.remaining(max_age, now)
+            .is_none_or(|remaining| remaining <= max_age / 2)
+    }
+}
+
+/// The key of the first page of the feed `rkey` for `did`, with `limit` items or the
+/// default number.
+pub fn page_key(rkey: &str, did: &str, limit: Option<u8>) -> String {
+    match limit {
+        Some(limit) => format!("page/{rkey}/{limit}/{did}"),
+        None => format!("page/{rkey}/default/{did}"),
+    }
+}
+
+/// The metadata of the key recording that a viewer is active.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity {
+    pub limit: Option<u8>,
+    /// When the key was written, absent from keys written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_at: Option<DateTime<Utc>>,
+}
+
+impl Activity {
+    /// Whether a request with `limit` at `now` should write the key again: if the limit
+    /// changed, or the key is more than half its `active_ttl` old, so that it does not
+    /// expire while the viewer stays active. KV allows a write per second per key and few
+    /// writes a day, so requests in between only read it.
+    pub fn is_due(&self, limit: Option<u8>, active_ttl: u64, now: DateTime<Utc>) -> bool {
+        let Some(recorded_at) = self.recorded_at else {
+            return true;
+        };
+        let age = u64::try_from((now - recorded_at).num_seconds()).unwrap_or_default();
+        self.limit != limit || age >= active_ttl / 2
+    }
+}
@@ -1,19 +1,72 @@
+//! `HttpClient`s on the Fetch and Cache APIs of the worker runtime.
+use crate::config::UpstreamConfig;
+use crate::http_client::{
+    MaxResponseSize, RequestTimeout, ResponseTooLarge, RetryOptions, RetryingHttpClient, Sleep,
+};
+use crate::response_cache::{self, CachingHttpClient, ResponseCache};
 use async_trait::async_trait;
 use atrium_api::xrpc::{HttpClient, XrpcClient};
-use http::{Request, Response};
+use futures_util::future::{select, Either};
+use futures_util::StreamExt;
+use http::{HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode};
+use std::pin::pin;
+use std::time::Duration;
 use worker::js_sys::Uint8Array;
 use worker::wasm_bindgen::JsValue;
-use worker::{Fetch, Headers, Method, RequestInit};
+use worker::{AbortController, Cache, Delay, Fetch, Headers, Method, RequestInit};
 
+/// The error of `FetchHttpClient`, boxed by `send_http` so callers can downcast to it.
+#[derive(Debug)]
+pub enum Error {
+    /// The Fetch API rejected the request.
+    Request(String),
+    /// A request header could not be passed to the Fetch API.
+    Header(String),
+    /// The request failed before a response arrived.
+    Network(String),
+    Timeout(Duration),
+    /// The response status is not a valid HTTP status code.
+    Status(u16),
+    /// The response body could not be read.
+    Body(String),
+    /// The response could not be built from the parts that Fetch returned.
+    Response(http::Error),
+    /// The response body is over the `MaxResponseSize` of the request.
+    TooLarge(ResponseTooLarge),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Request(err) => write!(f, "invalid request: {err}"),
+            Error::Header(name) => write!(f, "invalid header value: {name}"),
+            Error::Network(err) => write!(f, "network error: {err}"),
+            Error::Timeout(timeout) => write!(f, "request timed out after {timeout:?}"),
+            Error::Status(status) => write!(f, "invalid status code: {status}"),
+            Error::Body(err) => write!(f, "failed to read response body: {err}"),
+            Error::Response(err) => write!(f, "invalid response: {err}"),
+            Error::TooLarge(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::TooLarge(err) => Some(err),
+            Error::Response(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy)]
 pub struct FetchHttpClient;
 
-#[async_trait(?Send)]
-impl HttpClient for FetchHttpClient {
-    async fn send_http(
-        &self,
-        request: Request<Vec<u8>>,
-    ) -> Result<Response<Vec<u8>>, Box<dyn std::error::Error + Send + Sync + 'static>> {
-        let uri = request.uri().to_string();
+impl FetchHttpClient {
+    async fn fetch(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
         let init = RequestInit {
             body: if request.body().is_empty() {
                 None
@@ -22,42 +75,190 @@ impl HttpClient for FetchHttpClient {
                 u8array.copy_from(request.body());
                 Some(JsValue::from(u8array))
             },
-            headers: Headers::from_iter(request.headers().iter().map(|(k, v)| {
-                (
-                    k.to_string(),
-                    v.to_str()
-                        .expect("failed to convert header value")
-                        .to_string(),
-                )
-            })),
+            headers: to_headers(request.headers())?,
             method: Method::from(request.method().to_string()),
             ..Default::default()
         };
-        let mut response =
-            Fetch::Request(worker::Request::new_with_init(&uri, &init).map_err(|e| e.to_string())?)
-                .send()
+        let fetch = Fetch::Request(
+            worker::Request::new_with_init(&request.uri().to_string(), &init)
+                .map_err(|e| Error::Request(e.to_string()))?,
+        );
+        let max_size = request
+            .extensions()
+            .get::<MaxResponseSize>()
+            .map(|MaxResponseSize(max_size)| *max_size);
+        let mut response = if let Some(RequestTimeout(timeout)) = request.extensions().get() {
+            let controller = AbortController::default();
+            let signal = controller.signal();
+            let result = match select(
+                pin!(fetch.send_with_signal(&signal)),
+                pin!(Delay::from(*timeout)),
+            )
+            .await
+            {
+                Either::Left((result, _)) => result,
+                Either::Right(_) => {
+                    controller.abort();
+                    return Err(Error::Timeout(*timeout));
+                }
+            };
+            result
+        } else {
+            fetch.send().await
+        }
+        .map_err(|e| Error::Network(e.to_string()))?;
+        from_response(&mut response, max_size).await
+    }
+}
+
+#[async_trait(?Send)]
+impl HttpClient for FetchHttpClient {
+    async fn send_http(
+        &self,
+        request: Request<Vec<u8>>,
+    ) -> std::result::Result<Response<Vec<u8>>, Box<dyn std::error::Error + Send + Sync + 'static>>
+    {
+        Ok(self.fetch(request).await?)
+    }
+}
+
+fn to_headers(map: &HeaderMap) -> Result<Headers> {
+    let mut headers = Headers::new();
+    for (name, value) in map {
+        headers
+            .append(name.as_str(), &to_byte_string(value.as_bytes()))
+            .map_err(|_| Error::Header(name.to_string()))?;
+    }
+    Ok(headers)
+}
+
+/// Convert `response`, reading no more than `max_size` bytes of its body.
+async fn from_response(
+    response: &mut worker::Response,
+    max_size: Option<usize>,
+) -> Result<Response<Vec<u8>>> {
+    let status = response.status_code();
+    let mut builder = Response::builder()
+        .status(StatusCode::from_u16(status).map_err(|_| Error::Status(status))?);
+    for (name, value) in response.headers() {
+        // a header that `http` cannot represent is left out rather than failing the response
+        let header = HeaderName::from_bytes(name.as_bytes())
+            .ok()
+            .zip(from_byte_string(&value).and_then(|value| HeaderValue::from_bytes(&value).ok()));
+        match header {
+            Some((name, value)) => builder = builder.header(name, value),
+            None => worker::console_warn!("skipping invalid response header: {name}"),
+        }
+    }
+    let body = match max_size {
+        Some(max_size) => read_limited(response, max_size).await?,
+        None => response
+            .bytes()
+            .await
+            .map_err(|e| Error::Body(e.to_string()))?,
+    };
+    builder.body(body).map_err(Error::Response)
+}
+
+/// Read the body of `response`, failing as soon as it is known to be over `max_size`.
+async fn read_limited(response: &mut worker::Response, max_size: usize) -> Result<Vec<u8>> {
+    let declared = response
+        .headers()
+        .get("content-length")
+        .ok()
+        .flatten()
+        .and_then(|length| length.parse::<usize>().ok());
+    if let Some(declared) = declared.filter(|declared| *declared > max_size) {
+        return Err(Error::TooLarge(ResponseTooLarge(declared)));
+    }
+    let mut stream = response.stream().map_err(|e| Error::Body(e.to_string()))?;
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        body.extend(chunk.map_err(|e| Error::Body(e.to_string()))?);
+        if body.len() > max_size {
+            return Err(Error::TooLarge(ResponseTooLarge(body.len())));
+        }
+    }
+    Ok(body)
+}
+
+/// Header values are ByteStrings in the Fetch API, one character per byte.
+fn to_byte_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+fn from_byte_string(value: &str) -> Option<Vec<u8>> {
+    value.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+/// Sleeps on the worker's timers.
+#[derive(Clone, Copy)]
+pub struct WorkerSleep;
+
+#[async_trait(?Send)]
+impl Sleep for WorkerSleep {
+    async fn sleep(&self, duration: Duration) {
+        Delay::from(duration).await
+    }
+}
+
+/// `FetchHttpClient` retrying transient failures.
+pub type RetryingFetchHttpClient = RetryingHttpClient<FetchHttpClient, WorkerSleep>;
+
+pub fn retrying_fetch_http_client(options: RetryOptions) -> RetryingFetchHttpClient {
+    RetryingHttpClient::with_options(FetchHttpClient, WorkerSleep, options)
+}
+
+/// The default cache of the Workers Cache API, local to the data center.
+#[derive(Clone, Copy)]
+pub struct WorkerCache;
+
+#[async_trait(?Send)]
+impl ResponseCache for WorkerCache {
+    async fn get(&self, key: &str) -> response_cache::Result<Option<Response<Vec<u8>>>> {
+        match Cache::default().get(key, false).await {
+            Ok(Some(mut response)) => from_response(&mut response, None)
                 .await
-                .map_err(|e| e.to_string())?;
-        let mut builder = http::Response::builder().status(response.status_code());
-        for (k, v) in response.headers() {
-            builder = builder.header(k, v);
+                .map(Some)
+                .map_err(|e| response_cache::Error::Backend(e.to_string())),
+            Ok(None) => Ok(None),
+            Err(e) => Err(response_cache::Error::Backend(e.to_string())),
         }
-        Ok(builder
-            .body(response.bytes().await.map_err(|e| e.to_string())?)
-            .map_err(|e| e.to_string())?)
+    }
+    async fn put(
+        &self,
+        key: &str,
+        response: &Response<Vec<u8>>,
+        ttl: Duration,
+    ) -> response_cache::Result<()> {
+        let response = response_cache::with_max_age(response, ttl);
+        let headers = to_headers(response.headers())
+            .map_err(|e| response_cache::Error::Backend(e.to_string()))?;
+        let response = worker::Response::from_bytes(response.body().clone())
+            .map_err(|e| response_cache::Error::Backend(e.to_string()))?
+            .with_status(response.status().as_u16())
+            .with_headers(headers);
+        Cache::default()
+            .put(key, response)
+            .await
+            .map_err(|e| response_cache::Error::Backend(e.to_string()))
     }
 }
 
 pub struct FetchClient {
     base_uri: String,
-    http_client: FetchHttpClient,
+    http_client: CachingHttpClient<RetryingFetchHttpClient, WorkerCache>,
 }
 
 impl FetchClient {
-    pub fn new(base_uri: impl AsRef<str>) -> Self {
+    pub fn new(base_uri: impl AsRef<str>, upstream: &UpstreamConfig) -> Self {
         Self {
             base_uri: base_uri.as_ref().to_string(),
-            http_client: FetchHttpClient,
+            http_client: CachingHttpClient::with_options(
+                retrying_fetch_http_client(upstream.retry.clone()),
+                WorkerCache,
+                upstream.cache.clone(),
+            ),
         }
     }
 }
@@ -67,7 +268,8 @@ impl HttpClient for FetchClient {
     async fn send_http(
         &self,
         request: Request<Vec<u8>>,
-    ) -> Result<Response<Vec<u8>>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    ) -> std::result::Result<Response<Vec<u8>>, Box<dyn std::error::Error + Send + Sync + 'static>>
+    {
         self.http_client.send_http(request).await
     }
 }
@@ -78,3 +280,38 @@ impl XrpcClient for FetchClient {
         self.base_uri.clone()
     }
 }
+
+/// An XRPC client for downloads such as repository exports, which take longer than the
+/// upstream requests of a page: retried with `retry` rather than the upstream options, and
+/// never cached.
+pub struct DownloadClient {
+    base_uri: String,
+    http_client: RetryingFetchHttpClient,
+}
+
+impl DownloadClient {
+    pub fn new(base_uri: impl AsRef<str>, retry: RetryOptions) -> Self {
+        Self {
+            base_uri: base_uri.as_ref().to_string(),
+            http_client: retrying_fetch_http_client(retry),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl HttpClient for DownloadClient {
+    async fn send_http(
+        &self,
+        request: Request<Vec<u8>>,
+    ) -> std::result::Result<Response<Vec<u8>>, Box<dyn std::error::Error + Send + Sync + 'static>>
+    {
+        self.http_client.send_http(request).await
+    }
+}
+
+#[async_trait(?Send)]
+impl XrpcClient for DownloadClient {
+    fn base_uri(&self) -> String {
+        self.base_uri.clone()
+    }
+}
@@ -31,17 +31,17 @@ impl DidDocument {
         self.get_verification_material("atproto")
     }
     pub fn get_verification_material(&self, key_id: &str) -> Option<(String, String)> {
+        self.get_verification_method(key_id).and_then(|key| {
+            key.public_key_multibase
+                .as_ref()
+                .map(|multibase| (key.r#type.clone(), multibase.clone()))
+        })
+    }
+    pub fn get_verification_method(&self, key_id: &str) -> Option<&VerificationMethod> {
         let did = self.get_did();
         if let Some(keys) = &self.verification_method {
             keys.iter()
                 .find(|key| key.id == format!("#{key_id}") || key.id == format!("{did}#{key_id}"))
-                .filter(|key| key.public_key_multibase.is_some())
-                .map(|key| {
-                    (
-                        key.r#type.clone(),
-                        key.public_key_multibase.clone().unwrap(),
-                    )
-                })
         } else {
             None
         }
@@ -72,6 +72,18 @@ pub struct VerificationMethod {
     pub controller: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub public_key_multibase: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key_jwk: Option<Jwk>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
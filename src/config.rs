@@ -0,0 +1,408 @@
+//! Typed configuration, loaded from worker `Env` vars or the process environment.
+use crate::crypto::did;
+use crate::did_doc::ServiceIdentity;
+use crate::http_client::RetryOptions;
+use crate::manifest::{FeedDefinition, Manifest};
+use crate::response_cache::CacheOptions;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Missing(&'static str),
+    Invalid(&'static str, String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Missing(key) => write!(f, "missing required config: {key}"),
+            Error::Invalid(key, reason) => write!(f, "invalid config {key}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub trait ConfigSource {
+    fn var(&self, key: &str) -> Option<String>;
+}
+
+/// The environment variables of the current process.
+pub struct ProcessEnv;
+
+impl ConfigSource for ProcessEnv {
+    fn var(&self, key: &str) -> Option<String> {
+        std::env::var(key).ok()
+    }
+}
+
+impl ConfigSource for HashMap<String, String> {
+    fn var(&self, key: &str) -> Option<String> {
+        self.get(key).cloned()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl ConfigSource for worker::Env {
+    fn var(&self, key: &str) -> Option<String> {
+        worker::Env::var(self, key).ok().map(|var| var.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub service: ServiceIdentity,
+    /// The account that the feed generator records are published under, from the manifest.
+    /// Feeds are only served once it is set; see [`Config::publisher`].
+    pub publisher: Option<String>,
+    pub upstream: UpstreamConfig,
+    pub resolver: ResolverConfig,
+    pub manifest: Manifest,
+    /// KV namespace binding holding a manifest under the `manifest` key, which replaces
+    /// `manifest` once loaded.
+    pub manifest_kv: Option<String>,
+    pub auth: AuthPolicy,
+    pub skeleton_cache: Option<SkeletonCacheConfig>,
+    pub index: Option<IndexConfig>,
+}
+
+impl Config {
+    pub fn load(source: &impl ConfigSource) -> Result<Self> {
+        let service = load_service_identity(source)?;
+        let manifest = match optional_var(source, "FEEDS_MANIFEST") {
+            Some(text) => Manifest::parse(&text),
+            None => Manifest::embedded(),
+        }
+        .map_err(|err| Error::Invalid("FEEDS_MANIFEST", err.to_string()))?;
+        Ok(Self {
+            publisher: publisher(&manifest, &service, "FEEDS_MANIFEST")?,
+            service,
+            upstream: UpstreamConfig::load(source)?,
+            resolver: ResolverConfig::load(source)?,
+            manifest,
+            manifest_kv: optional_var(source, "FEEDS_MANIFEST_KV"),
+            auth: AuthPolicy::load(source)?,
+            skeleton_cache: SkeletonCacheConfig::load(source)?,
+            index: IndexConfig::load(source)?,
+        })
+    }
+    /// Replace the manifest with one loaded from `key`, e.g. out of KV.
+    pub fn with_manifest(self, manifest: Manifest, key: &'static str) -> Result<Self> {
+        Ok(Self {
+            publisher: publisher(&manifest, &self.service, key)?,
+            manifest,
+            ..self
+        })
+    }
+    /// The publisher, which the routes serving feeds require since their URIs are its
+    /// records.
+    pub fn publisher(&self) -> Result<&str> {
+        self.publisher.as_deref().ok_or(Error::Missing("publisher"))
+    }
+    pub fn feed(&self, rkey: &str) -> Option<&FeedDefinition> {
+        self.manifest.feed(rkey)
+    }
+    /// The feed at `uri`, which must be a generator record of the publisher.
+    pub fn feed_by_uri(&self, uri: &str) -> Option<&FeedDefinition> {
+        let (did, rkey) = uri
+            .strip_prefix("at://")?
+            .split_once("/app.bsky.feed.generator/")?;
+        (Some(did) == self.publisher.as_deref())
+            .then(|| self.feed(rkey))
+            .flatten()
+    }
+}
+
+/// The publisher of `manifest`, if any, which must be an account with a repository for the
+/// feed generator records, so not the did:web of the service.
+fn publisher(
+    manifest: &Manifest,
+    service: &ServiceIdentity,
+    key: &'static str,
+) -> Result<Option<String>> {
+    match manifest.publisher.as_deref() {
+        Some(publisher) if publisher == service.did => Err(Error::Invalid(
+            key,
+            format!("publisher is the service DID, which has no repository: {publisher}"),
+        )),
+        publisher => Ok(publisher.map(String::from)),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UpstreamConfig {
+    pub appview_url: String,
+    pub plc_url: String,
+    pub retry: RetryOptions,
+    pub cache: CacheOptions,
+}
+
+impl UpstreamConfig {
+    pub fn load(source: &impl ConfigSource) -> Result<Self> {
+        Ok(Self {
+            appview_url: url_var(source, "APPVIEW_URL")?
+                .unwrap_or(String::from("https://api.bsky.app")),
+            plc_url: url_var(source, "PLC_URL")?.unwrap_or(String::from("https://plc.directory")),
+            retry: load_retry_options(source)?,
+            cache: load_cache_options(source)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolverConfig {
+    /// Hosts that may be resolved as `did:web`; `None` allows any host.
+    pub web_allowed_hosts: Option<Vec<String>>,
+    pub timeout: Option<Duration>,
+    pub max_document_size: Option<usize>,
+    pub cache: Option<CacheConfig>,
+}
+
+impl ResolverConfig {
+    pub fn load(source: &impl ConfigSource) -> Result<Self> {
+        Ok(Self {
+            web_allowed_hosts: optional_var(source, "DID_WEB_ALLOWED_HOSTS")
+                .map(|hosts| split_list(&hosts)),
+            timeout: match parse_var::<u64>(source, "DID_RESOLVER_TIMEOUT_MS")? {
+                Some(0) => None,
+                Some(ms) => Some(Duration::from_millis(ms)),
+                None => Some(Duration::from_secs(3)),
+            },
+            max_document_size: match parse_var::<usize>(source, "DID_MAX_DOCUMENT_SIZE")? {
+                Some(0) => None,
+                Some(size) => Some(size),
+                None => Some(64 * 1024),
+            },
+            cache: match optional_var(source, "DID_CACHE_KV") {
+                Some(binding) => Some(CacheConfig {
+                    binding,
+                    ttl: parse_var(source, "DID_CACHE_TTL")?.unwrap_or(3600),
+                }),
+                None => None,
+            },
+        })
+    }
+}
+
+/// A KV namespace binding used as a cache.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub binding: String,
+    pub ttl: u64,
+}
+
+/// A KV namespace holding first pages precomputed for recently active viewers.
+#[derive(Debug, Clone)]
+pub struct SkeletonCacheConfig {
+    pub binding: String,
+    /// Seconds after their last request that viewers are no longer precomputed for.
+    pub active_ttl: u64,
+    /// Pages precomputed per scheduled run, bounding its upstream requests.
+    pub max_per_run: usize,
+}
+
+impl SkeletonCacheConfig {
+    pub fn load(source: &impl ConfigSource) -> Result<Option<Self>> {
+        let Some(binding) = optional_var(source, "SKELETON_CACHE_KV") else {
+            return Ok(None);
+        };
+        let active_ttl = parse_var(source, "SKELETON_ACTIVE_TTL")?.unwrap_or(24 * 3600);
+        if active_ttl < 60 {
+            // the minimum expiration TTL of KV
+            return Err(Error::Invalid(
+                "SKELETON_ACTIVE_TTL",
+                String::from("must be at least 60"),
+            ));
+        }
+        Ok(Some(Self {
+            binding,
+            active_ttl,
+            max_per_run: parse_var(source, "SKELETON_PRECOMPUTE_LIMIT")?.unwrap_or(20),
+        }))
+    }
+}
+
+/// A D1 database holding the post index, fed from the Jetstream on the cron trigger.
+#[derive(Debug, Clone)]
+pub struct IndexConfig {
+    pub binding: String,
+    pub jetstream_url: String,
+    /// How long each scheduled run reads the Jetstream for.
+    pub ingest_duration: Duration,
+    /// Repositories downloaded for backfills in each scheduled run.
+    pub backfills_per_run: usize,
+    /// How long downloading a repository for a backfill may take, retries included.
+    pub backfill_timeout: Duration,
+}
+
+impl IndexConfig {
+    pub fn load(source: &impl ConfigSource) -> Result<Option<Self>> {
+        let Some(binding) = optional_var(source, "INDEX_D1") else {
+            return Ok(None);
+        };
+        let jetstream_url = match optional_var(source, "JETSTREAM_URL") {
+            Some(url) if url.starts_with("wss://") || url.starts_with("ws://") => {
+                url.trim_end_matches('/').to_string()
+            }
+            Some(url) => {
+                return Err(Error::Invalid(
+                    "JETSTREAM_URL",
+                    format!("not a ws(s) URL: {url}"),
+                ))
+            }
+            None => String::from("wss://jetstream2.us-east.bsky.network"),
+        };
+        Ok(Some(Self {
+            binding,
+            jetstream_url,
+            ingest_duration: Duration::from_secs(
+                parse_var(source, "INDEX_INGEST_SECONDS")?.unwrap_or(20),
+            ),
+            backfills_per_run: parse_var(source, "INDEX_BACKFILL_LIMIT")?.unwrap_or(2),
+            backfill_timeout: Duration::from_secs(
+                parse_var(source, "INDEX_BACKFILL_TIMEOUT_SECONDS")?.unwrap_or(120),
+            ),
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthPolicy {
+    /// The lexicon method (`lxm`) that service JWTs must be scoped to, if any.
+    pub lxm: Option<String>,
+}
+
+impl AuthPolicy {
+    pub fn load(source: &impl ConfigSource) -> Result<Self> {
+        Ok(Self {
+            lxm: optional_var(source, "AUTH_LXM"),
+        })
+    }
+}
+
+fn load_retry_options(source: &impl ConfigSource) -> Result<RetryOptions> {
+    let defaults = RetryOptions::default();
+    Ok(RetryOptions {
+        max_attempts: match parse_var(source, "HTTP_MAX_ATTEMPTS")? {
+            Some(0) => {
+                return Err(Error::Invalid(
+                    "HTTP_MAX_ATTEMPTS",
+                    String::from("must be at least 1"),
+                ))
+            }
+            Some(attempts) => attempts,
+            None => defaults.max_attempts,
+        },
+        deadline: match parse_var::<u64>(source, "HTTP_DEADLINE_MS")? {
+            Some(0) => None,
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => defaults.deadline,
+        },
+        ..defaults
+    })
+}
+
+/// `HTTP_CACHE_MIN_TTL` is a list of `<nsid>=<seconds>`.
+fn load_cache_options(source: &impl ConfigSource) -> Result<CacheOptions> {
+    let Some(value) = optional_var(source, "HTTP_CACHE_MIN_TTL") else {
+        return Ok(CacheOptions {
+            min_ttl: HashMap::from([(
+                String::from("app.bsky.feed.searchPosts"),
+                Duration::from_secs(60),
+            )]),
+        });
+    };
+    let mut min_ttl = HashMap::new();
+    for entry in split_list(&value) {
+        let ttl = entry
+            .split_once('=')
+            .and_then(|(nsid, seconds)| Some((nsid.trim(), seconds.trim().parse().ok()?)))
+            .filter(|(nsid, _)| !nsid.is_empty());
+        let Some((nsid, seconds)) = ttl else {
+            return Err(Error::Invalid(
+                "HTTP_CACHE_MIN_TTL",
+                format!("expected <nsid>=<seconds>: {entry}"),
+            ));
+        };
+        min_ttl.insert(nsid.to_string(), Duration::from_secs(seconds));
+    }
+    Ok(CacheOptions { min_ttl })
+}
+
+/// The service identity alone, for tools that do not serve feeds.
+pub fn load_service_identity(source: &impl ConfigSource) -> Result<ServiceIdentity> {
+    let did = required_var(source, "SERVICE_DID")?;
+    if !did.starts_with("did:") {
+        return Err(Error::Invalid("SERVICE_DID", format!("not a DID: {did}")));
+    }
+    let verification_methods = optional_var(source, "SERVICE_VERIFICATION_METHODS")
+        .map(|value| ServiceIdentity::parse_verification_methods(&value))
+        .transpose()
+        .map_err(|entry| {
+            Error::Invalid(
+                "SERVICE_VERIFICATION_METHODS",
+                format!("expected <id>=<did:key>: {entry}"),
+            )
+        })?
+        .unwrap_or_default();
+    for (id, did_key) in &verification_methods {
+        // converted as `ServiceIdentity::document` does, so that keys it cannot publish (e.g.
+        // P-256) fail here rather than every request for the DID document
+        did::parse_did_key(did_key)
+            .and_then(|parsed| did::format_multikey(parsed.jwt_alg, &parsed.key))
+            .map_err(|err| {
+                Error::Invalid("SERVICE_VERIFICATION_METHODS", format!("#{id}: {err}"))
+            })?;
+    }
+    Ok(ServiceIdentity {
+        did,
+        service_endpoint: url_var(source, "SERVICE_ENDPOINT")?
+            .ok_or(Error::Missing("SERVICE_ENDPOINT"))?,
+        verification_methods,
+        also_known_as: optional_var(source, "SERVICE_ALSO_KNOWN_AS")
+            .map(|value| ServiceIdentity::parse_also_known_as(&value))
+            .unwrap_or_default(),
+    })
+}
+
+fn optional_var(source: &impl ConfigSource, key: &str) -> Option<String> {
+    source.var(key).filter(|s| !s.is_empty())
+}
+
+fn required_var(source: &impl ConfigSource, key: &'static str) -> Result<String> {
+    optional_var(source, key).ok_or(Error::Missing(key))
+}
+
+fn parse_var<T: std::str::FromStr>(
+    source: &impl ConfigSource,
+    key: &'static str,
+) -> Result<Option<T>> {
+    optional_var(source, key)
+        .map(|s| {
+            s.parse()
+                .map_err(|_| Error::Invalid(key, format!("cannot parse: {s}")))
+        })
+        .transpose()
+}
+
+fn url_var(source: &impl ConfigSource, key: &'static str) -> Result<Option<String>> {
+    match optional_var(source, key) {
+        Some(url) if url.starts_with("https://") || url.starts_with("http://") => {
+            Ok(Some(url.trim_end_matches('/').into()))
+        }
+        Some(url) => Err(Error::Invalid(key, format!("not an http(s) URL: {url}"))),
+        None => Ok(None),
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -11,9 +11,9 @@ pub struct ParsedMultikey {
 }
 
 fn find_plugin(prefix: &[u8]) -> Result<Box<dyn DidKeyPlugin>> {
-    match &prefix[0..2] {
-        [0x80, 0x24] => Ok(Box::new(P256Plugin)),
-        [0xe7, 0x01] => Ok(Box::new(Secp256k1Plugin)),
+    match prefix.get(0..2) {
+        Some([0x80, 0x24]) => Ok(Box::new(P256Plugin)),
+        Some([0xe7, 0x01]) => Ok(Box::new(Secp256k1Plugin)),
         _ => Err(Error::UnsupportedKeyType),
     }
 }
@@ -1,9 +1,12 @@
+use super::consts::JwtAlg;
+
 #[derive(Debug)]
 pub enum Error {
     IncorrectDidKeyPrefix(String),
     IncorrectMultikeyPrefix(String),
     UnsupportedMultibase(String),
     UnsupportedKeyType,
+    Unimplemented(JwtAlg),
     Base58(bs58::decode::Error),
     ECDSA(ecdsa::elliptic_curve::Error),
     Signature(ecdsa::signature::Error),
@@ -18,6 +21,7 @@ impl std::fmt::Display for Error {
             }
             Error::UnsupportedMultibase(mb) => write!(f, "Unsupported multibase: {mb}"),
             Error::UnsupportedKeyType => write!(f, "Unsupported key type"),
+            Error::Unimplemented(jwt_alg) => write!(f, "Not implemented for {jwt_alg:?}"),
             Error::Base58(err) => write!(f, "Base58 decoding error: {err}"),
             Error::ECDSA(err) => write!(f, "ECDSA elliptic_curve error: {err}"),
             Error::Signature(err) => write!(f, "ECDSA signature error: {err}"),
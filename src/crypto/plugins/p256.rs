@@ -1,4 +1,5 @@
-use super::super::{consts::JwtAlg, error::Result, DidKeyPlugin};
+use super::super::error::{Error, Result};
+use super::super::{consts::JwtAlg, DidKeyPlugin};
 
 pub struct P256Plugin;
 
@@ -6,13 +7,13 @@ impl DidKeyPlugin for P256Plugin {
     fn jwt_alg(&self) -> JwtAlg {
         JwtAlg::P256
     }
-    fn compress_pubkey(&self, uncompressed: &[u8]) -> Result<Vec<u8>> {
-        unimplemented!()
+    fn compress_pubkey(&self, _uncompressed: &[u8]) -> Result<Vec<u8>> {
+        Err(Error::Unimplemented(self.jwt_alg()))
     }
-    fn decompress_pubkey(&self, compressed: &[u8]) -> Result<Vec<u8>> {
-        unimplemented!()
+    fn decompress_pubkey(&self, _compressed: &[u8]) -> Result<Vec<u8>> {
+        Err(Error::Unimplemented(self.jwt_alg()))
     }
-    fn verify_signature(&self, did: &str, msg: &[u8], sig: &[u8]) -> Result<()> {
-        unimplemented!()
+    fn verify_signature(&self, _did: &str, _msg: &[u8], _sig: &[u8]) -> Result<()> {
+        Err(Error::Unimplemented(self.jwt_alg()))
     }
 }
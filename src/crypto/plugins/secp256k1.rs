@@ -24,7 +24,7 @@ impl DidKeyPlugin for Secp256k1Plugin {
     }
     fn verify_signature(&self, did: &str, msg: &[u8], sig: &[u8]) -> Result<()> {
         let prefix = utils::extract_prefixed_bytes(utils::extract_multikey(did)?)?;
-        VerifyingKey::from_sec1_bytes(&prefix[2..])
+        VerifyingKey::from_sec1_bytes(prefix.get(2..).ok_or(Error::UnsupportedKeyType)?)
             .map_err(Error::Signature)?
             .verify(
                 msg,
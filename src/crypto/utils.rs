@@ -12,7 +12,7 @@ pub fn extract_prefixed_bytes(multikey: &str) -> Result<Vec<u8>> {
     if !multikey.starts_with(BASE58_MULTIBASE_PREFIX) {
         return Err(Error::IncorrectMultikeyPrefix(multikey.into()));
     }
-    bs58::decode(multikey[BASE58_MULTIBASE_PREFIX.len()..].as_bytes())
+    bs58::decode(&multikey.as_bytes()[BASE58_MULTIBASE_PREFIX.len()..])
         .into_vec()
         .map_err(Error::Base58)
 }
@@ -0,0 +1,303 @@
+//! The post index in D1, and its ingestion from the Jetstream and backfills on the cron
+//! trigger.
+use crate::backfill;
+use crate::client::DownloadClient;
+use crate::config::Config;
+use crate::http_client::RetryOptions;
+use crate::identity::did::did_resolver::Resolver;
+use crate::index_sql::{self, Row};
+use crate::jetstream;
+use crate::post_index::{
+    self, Change, IndexedRecord, PendingBackfill, PostIndex, RecordKind, MAX_BACKFILL_ATTEMPTS,
+};
+use crate::resolver::did_resolver;
+use crate::skeleton::TimeCursor;
+use async_trait::async_trait;
+use atrium_api::client::AtpServiceClient;
+use chrono::{DateTime, Utc};
+use futures_util::future::{select, Either};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::pin::pin;
+use worker::d1::{D1Database, D1PreparedStatement};
+use worker::wasm_bindgen::JsValue;
+use worker::{console_error, console_log, Delay, Env, Result, WebSocket, WebsocketEvent};
+
+pub struct D1Index(D1Database);
+
+impl D1Index {
+    pub fn new(env: &Env, binding: &str) -> Result<Self> {
+        Ok(Self(env.d1(binding)?))
+    }
+    fn statement(
+        &self,
+        query: &str,
+        values: &[JsValue],
+    ) -> post_index::Result<D1PreparedStatement> {
+        self.0.prepare(query).bind(values).map_err(backend)
+    }
+}
+
+#[async_trait(?Send)]
+impl PostIndex for D1Index {
+    async fn apply(&self, changes: &[Change], cursor: Option<i64>) -> post_index::Result<()> {
+        let mut statements = Vec::with_capacity(changes.len() + 1);
+        for change in changes {
+            statements.push(match change {
+                Change::Put(record) => {
+                    let (flags, langs) = index_sql::meta_columns(record);
+                    self.statement(
+                        index_sql::PUT_RECORD,
+                        &[
+                            JsValue::from_str(&record.uri),
+                            JsValue::from_str(&record.did),
+                            JsValue::from_str(record.kind.as_str()),
+                            JsValue::from_str(&index_sql::timestamp(record.created_at)),
+                            record
+                                .subject
+                                .as_deref()
+                                .map_or(JsValue::NULL, JsValue::from_str),
+                            flags.map_or(JsValue::NULL, |flags| JsValue::from_f64(flags.into())),
+                            langs.as_deref().map_or(JsValue::NULL, JsValue::from_str),
+                        ],
+                    )?
+                }
+                Change::Delete { uri } => {
+                    self.statement(index_sql::DELETE_RECORD, &[JsValue::from_str(uri)])?
+                }
+                Change::DeleteAccount { did } => {
+                    self.statement(index_sql::DELETE_ACCOUNT, &[JsValue::from_str(did)])?
+                }
+            });
+        }
+        if let Some(cursor) = cursor {
+            statements
+                .push(self.statement(index_sql::PUT_CURSOR, &[JsValue::from_f64(cursor as f64)])?);
+        }
+        if statements.is_empty() {
+            return Ok(());
+        }
+        // a batch is a transaction
+        self.0.batch(statements).await.map_err(backend)?;
+        Ok(())
+    }
+    async fn cursor(&self) -> post_index::Result<Option<i64>> {
+        let time_us = self
+            .statement(index_sql::CURSOR, &[])?
+            .first::<f64>(Some("time_us"))
+            .await
+            .map_err(backend)?;
+        Ok(time_us.map(|time_us| time_us as i64))
+    }
+    async fn records(
+        &self,
+        did: &str,
+        kinds: &[RecordKind],
+        position: &TimeCursor,
+        limit: usize,
+    ) -> post_index::Result<Vec<IndexedRecord>> {
+        let mut values = vec![
+            JsValue::from_str(did),
+            JsValue::from_str(&index_sql::timestamp(position.created_at)),
+            JsValue::from_str(&position.uri),
+            JsValue::from_f64(limit as f64),
+        ];
+        values.extend(kinds.iter().map(|kind| JsValue::from_str(kind.as_str())));
+        let rows = self
+            .statement(&index_sql::records(kinds.len()), &values)?
+            .all()
+            .await
+            .map_err(backend)?
+            .results::<Row>()
+            .map_err(backend)?;
+        Ok(rows.into_iter().filter_map(Row::record).collect())
+    }
+    async fn track(&self, did: &str) -> post_index::Result<()> {
+        self.statement(index_sql::TRACK, &[JsValue::from_str(did)])?
+            .run()
+            .await
+            .map_err(backend)?;
+        Ok(())
+    }
+    async fn tracked(&self) -> post_index::Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct Tracked {
+            did: String,
+        }
+        let rows = self
+            .statement(index_sql::TRACKED, &[])?
+            .all()
+            .await
+            .map_err(backend)?
+            .results::<Tracked>()
+            .map_err(backend)?;
+        Ok(rows.into_iter().map(|row| row.did).collect())
+    }
+    async fn request_backfill(&self, did: &str) -> post_index::Result<()> {
+        self.statement(index_sql::REQUEST_BACKFILL, &[JsValue::from_str(did)])?
+            .run()
+            .await
+            .map_err(backend)?;
+        Ok(())
+    }
+    async fn pending_backfills(
+        &self,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> post_index::Result<Vec<PendingBackfill>> {
+        #[derive(Deserialize)]
+        struct Pending {
+            did: String,
+            attempts: u32,
+        }
+        let rows = self
+            .statement(
+                index_sql::PENDING_BACKFILLS,
+                &[
+                    JsValue::from_f64(limit as f64),
+                    JsValue::from_str(&index_sql::timestamp(now)),
+                    JsValue::from_f64(MAX_BACKFILL_ATTEMPTS.into()),
+                ],
+            )?
+            .all()
+            .await
+            .map_err(backend)?
+            .results::<Pending>()
+            .map_err(backend)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| PendingBackfill {
+                did: row.did,
+                attempts: row.attempts,
+            })
+            .collect())
+    }
+    async fn record_backfill_attempt(
+        &self,
+        did: &str,
+        at: DateTime<Utc>,
+        retry_at: DateTime<Utc>,
+    ) -> post_index::Result<()> {
+        self.statement(
+            index_sql::RECORD_BACKFILL_ATTEMPT,
+            &[
+                JsValue::from_str(did),
+                JsValue::from_str(&index_sql::timestamp(at)),
+                JsValue::from_str(&index_sql::timestamp(retry_at)),
+            ],
+        )?
+        .run()
+        .await
+        .map_err(backend)?;
+        Ok(())
+    }
+    async fn complete_backfill(&self, did: &str, rev: &str) -> post_index::Result<()> {
+        self.statement(
+            index_sql::COMPLETE_BACKFILL,
+            &[JsValue::from_str(did), JsValue::from_str(rev)],
+        )?
+        .run()
+        .await
+        .map_err(backend)?;
+        Ok(())
+    }
+}
+
+/// Read the Jetstream for `IndexConfig::ingest_duration` into the index, resuming from
+/// its cursor.
+pub async fn ingest(env: &Env, config: &Config) -> Result<()> {
+    let Some(index_config) = &config.index else {
+        return Ok(());
+    };
+    let index = D1Index::new(env, &index_config.binding)?;
+    let dids = index.tracked().await.map_err(|e| e.to_string())?;
+    if dids.is_empty() {
+        return Ok(());
+    }
+    let cursor = index.cursor().await.map_err(|e| e.to_string())?;
+    let url = jetstream::subscribe_url(&index_config.jetstream_url, cursor);
+    let socket = WebSocket::connect(url.parse()?).await?;
+    socket.accept()?;
+    socket.send(&jetstream::options_update(&dids))?;
+    let mut events = socket.events()?;
+    let mut lines = Vec::new();
+    let mut deadline = pin!(Delay::from(index_config.ingest_duration));
+    loop {
+        match select(events.next(), deadline.as_mut()).await {
+            Either::Left((Some(Ok(WebsocketEvent::Message(message))), _)) => {
+                lines.extend(message.text());
+            }
+            Either::Left((Some(Ok(WebsocketEvent::Close(_))) | None, _)) => break,
+            Either::Left((Some(Err(err)), _)) => {
+                console_error!("jetstream error: {err}");
+                break;
+            }
+            Either::Right(_) => break,
+        }
+    }
+    socket.close(Some(1000), Some("done")).ok();
+    let stats = jetstream::ingest(&index, lines)
+        .await
+        .map_err(|e| e.to_string())?;
+    console_log!("ingested {stats:?}");
+    Ok(())
+}
+
+/// Backfill up to `IndexConfig::backfills_per_run` of the requested repositories from
+/// their PDSes. Failed backfills are retried with backoff, see `backfill::retry_at`, until
+/// `MAX_BACKFILL_ATTEMPTS`.
+pub async fn backfill(env: &Env, config: &Config) -> Result<()> {
+    let Some(index_config) = &config.index else {
+        return Ok(());
+    };
+    let index = D1Index::new(env, &index_config.binding)?;
+    let now = Utc::now();
+    let pending = index
+        .pending_backfills(now, index_config.backfills_per_run)
+        .await
+        .map_err(|e| e.to_string())?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let resolver = did_resolver(env, config)?;
+    // a single attempt may take up to the whole timeout, leaving quick failures retried
+    let retry = RetryOptions {
+        attempt_timeout: Some(index_config.backfill_timeout),
+        deadline: Some(index_config.backfill_timeout),
+        ..config.upstream.retry.clone()
+    };
+    for PendingBackfill { did, attempts } in pending {
+        // recorded before the attempt, so that attempts that never finish count too
+        let retry_at = backfill::retry_at(attempts + 1, now);
+        if let Err(err) = index.record_backfill_attempt(&did, now, retry_at).await {
+            console_error!("failed to record backfill attempt of {did}: {err}");
+            continue;
+        }
+        let (pds, signing_key) = match (
+            resolver.ensure_resolve(&did, false).await,
+            resolver.resolve_atproto_key(&did, false).await,
+        ) {
+            (Ok(did_doc), Ok(signing_key)) => match did_doc.get_pds_endpoint() {
+                Some(pds) => (pds, signing_key),
+                None => {
+                    console_error!("no pds for {did}");
+                    continue;
+                }
+            },
+            (Err(err), _) | (_, Err(err)) => {
+                console_error!("failed to resolve {did}: {err}");
+                continue;
+            }
+        };
+        let pds = AtpServiceClient::new(DownloadClient::new(&pds, retry.clone()));
+        match backfill::backfill(&pds, &index, &did, &signing_key).await {
+            Ok(stats) => console_log!("backfilled {did}: {stats:?}"),
+            Err(err) => console_error!("failed to backfill {did}: {err}"),
+        }
+    }
+    Ok(())
+}
+
+fn backend(err: worker::Error) -> post_index::Error {
+    post_index::Error::Backend(err.to_string())
+}
@@ -0,0 +1,512 @@
+//! DAG-CBOR, the canonical CBOR encoding of the IPLD data model that repository blocks,
+//! commits and PLC operations are signed in, and the CIDs that address its blocks.
+//!
+//! Typed records are converted through their JSON form, where links are `{"$link": cid}`
+//! and bytes are `{"$bytes": base64}` as in the atproto data model.
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine as _};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// The multicodec of DAG-CBOR blocks.
+pub const DAG_CBOR: u64 = 0x71;
+/// The multicodec of raw bytes, such as blobs.
+pub const RAW: u64 = 0x55;
+/// The multicodec of CIDv0 blocks.
+const DAG_PB: u64 = 0x70;
+/// The multihash of sha-256.
+const SHA2_256: u64 = 0x12;
+
+/// How deep values may nest.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub enum Error {
+    Decode(String),
+    /// A JSON value with no DAG-CBOR equivalent.
+    Unsupported(String),
+    InvalidCid(String),
+    UnsupportedHash(u64),
+    /// The block does not hash to its CID.
+    HashMismatch(Cid),
+    SerdeJson(serde_json::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Decode(reason) => write!(f, "invalid DAG-CBOR: {reason}"),
+            Error::Unsupported(reason) => write!(f, "not representable in DAG-CBOR: {reason}"),
+            Error::InvalidCid(reason) => write!(f, "invalid CID: {reason}"),
+            Error::UnsupportedHash(code) => write!(f, "unsupported multihash: {code:#x}"),
+            Error::HashMismatch(cid) => write!(f, "block does not match its CID: {cid}"),
+            Error::SerdeJson(err) => write!(f, "serde_json error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A content identifier, kept in its binary form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cid {
+    bytes: Vec<u8>,
+    codec: u64,
+    hash: u64,
+    /// Where the digest starts in `bytes`.
+    digest: usize,
+}
+
+impl Cid {
+    /// The CIDv1 of `data` encoded with `codec`, hashed with sha-256.
+    pub fn compute(codec: u64, data: &[u8]) -> Self {
+        let mut bytes = Vec::with_capacity(36);
+        for n in [1, codec, SHA2_256, 32] {
+            write_varint(n, &mut bytes);
+        }
+        let digest = bytes.len();
+        bytes.extend_from_slice(&Sha256::digest(data));
+        Self {
+            bytes,
+            codec,
+            hash: SHA2_256,
+            digest,
+        }
+    }
+    /// Read a binary CID from the start of `bytes`, returning it and its length.
+    pub fn read(bytes: &[u8]) -> Result<(Self, usize)> {
+        let mut reader = Reader::new(bytes);
+        // CIDv0 is a bare sha-256 multihash
+        let (codec, hash, len) = if bytes.starts_with(&[0x12, 0x20]) {
+            reader.take(2).map_err(invalid_cid)?;
+            (DAG_PB, SHA2_256, 32)
+        } else {
+            let version = reader.varint().map_err(invalid_cid)?;
+            if version != 1 {
+                return Err(Error::InvalidCid(format!("unsupported version {version}")));
+            }
+            let codec = reader.varint().map_err(invalid_cid)?;
+            let hash = reader.varint().map_err(invalid_cid)?;
+            (codec, hash, reader.varint().map_err(invalid_cid)?)
+        };
+        let digest = reader.pos;
+        let len = usize::try_from(len).map_err(|_| Error::InvalidCid(String::from("too long")))?;
+        reader.take(len).map_err(invalid_cid)?;
+        Ok((
+            Self {
+                bytes: bytes[..reader.pos].to_vec(),
+                codec,
+                hash,
+                digest,
+            },
+            reader.pos,
+        ))
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match Self::read(bytes)? {
+            (cid, len) if len == bytes.len() => Ok(cid),
+            _ => Err(Error::InvalidCid(String::from("trailing bytes"))),
+        }
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+    pub fn version(&self) -> u64 {
+        match self.bytes.first() {
+            Some(0x12) => 0,
+            _ => 1,
+        }
+    }
+    pub fn codec(&self) -> u64 {
+        self.codec
+    }
+    /// Verify that `block` is the data addressed by this CID.
+    pub fn verify(&self, block: &[u8]) -> Result<()> {
+        if self.hash != SHA2_256 {
+            return Err(Error::UnsupportedHash(self.hash));
+        }
+        if Sha256::digest(block).as_slice() != &self.bytes[self.digest..] {
+            return Err(Error::HashMismatch(self.clone()));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Cid {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.version() {
+            0 => write!(f, "{}", bs58::encode(&self.bytes).into_string()),
+            _ => write!(f, "b{}", base32_encode(&self.bytes)),
+        }
+    }
+}
+
+impl std::str::FromStr for Cid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = if s.len() == 46 && s.starts_with("Qm") {
+            bs58::decode(s)
+                .into_vec()
+                .map_err(|err| Error::InvalidCid(err.to_string()))?
+        } else if let Some(base32) = s.strip_prefix('b') {
+            base32_decode(base32).ok_or_else(|| Error::InvalidCid(s.into()))?
+        } else {
+            return Err(Error::InvalidCid(format!("unsupported multibase: {s}")));
+        };
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// A value of the IPLD data model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Bytes(Vec<u8>),
+    String(String),
+    Array(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+    Link(Cid),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(map) => map.get(key),
+            _ => None,
+        }
+    }
+    /// The CID of the block holding this value.
+    pub fn cid(&self) -> Cid {
+        Cid::compute(DAG_CBOR, &encode(self))
+    }
+}
+
+impl From<Value> for serde_json::Value {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(b) => b.into(),
+            Value::Integer(i) => i.into(),
+            Value::Float(f) => f.into(),
+            Value::Bytes(bytes) => serde_json::json!({ "$bytes": STANDARD_NO_PAD.encode(bytes) }),
+            Value::String(s) => s.into(),
+            Value::Array(values) => values.into_iter().map(Self::from).collect(),
+            Value::Map(map) => map.into_iter().map(|(k, v)| (k, Self::from(v))).collect(),
+            Value::Link(cid) => serde_json::json!({ "$link": cid.to_string() }),
+        }
+    }
+}
+
+impl TryFrom<serde_json::Value> for Value {
+    type Error = Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self> {
+        Ok(match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(b),
+            serde_json::Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+                (Some(i), _) => Value::Integer(i),
+                (None, Some(f)) if n.is_f64() => Value::Float(f),
+                _ => return Err(Error::Unsupported(format!("integer out of range: {n}"))),
+            },
+            serde_json::Value::String(s) => Value::String(s),
+            serde_json::Value::Array(values) => Value::Array(
+                values
+                    .into_iter()
+                    .map(Value::try_from)
+                    .collect::<Result<_>>()?,
+            ),
+            serde_json::Value::Object(map) => {
+                if map.len() == 1 {
+                    match map.iter().next() {
+                        Some((key, serde_json::Value::String(link))) if key == "$link" => {
+                            return Ok(Value::Link(link.parse()?));
+                        }
+                        Some((key, serde_json::Value::String(bytes))) if key == "$bytes" => {
+                            return STANDARD_NO_PAD
+                                .decode(bytes.trim_end_matches('='))
+                                .map(Value::Bytes)
+                                .map_err(|err| Error::Unsupported(format!("$bytes: {err}")));
+                        }
+                        _ => {}
+                    }
+                }
+                Value::Map(
+                    map.into_iter()
+                        .map(|(k, v)| Ok((k, Value::try_from(v)?)))
+                        .collect::<Result<_>>()?,
+                )
+            }
+        })
+    }
+}
+
+/// Decode a typed record from a DAG-CBOR block.
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    serde_json::from_value(decode(bytes)?.into()).map_err(Error::SerdeJson)
+}
+
+/// Encode a typed record as a DAG-CBOR block.
+pub fn to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let value = serde_json::to_value(value).map_err(Error::SerdeJson)?;
+    Ok(encode(&Value::try_from(value)?))
+}
+
+/// Decode a single value, which must be in canonical form and fill `bytes`.
+pub fn decode(bytes: &[u8]) -> Result<Value> {
+    let mut reader = Reader::new(bytes);
+    let value = decode_value(&mut reader, 0)?;
+    if reader.pos != bytes.len() {
+        return Err(Error::Decode(String::from("trailing bytes")));
+    }
+    Ok(value)
+}
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_value(value, &mut buf);
+    buf
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| Error::Decode(String::from("unexpected end of data")))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+    /// An unsigned LEB128 varint, as in CIDs.
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::Decode(String::from("varint too long")))
+    }
+    /// The major type and argument of the next item, rejecting non-minimal lengths.
+    fn head(&mut self) -> Result<(u8, u8, u64)> {
+        let head = self.byte()?;
+        let (major, info) = (head >> 5, head & 0x1f);
+        let (arg, min) = match info {
+            0..=23 => (u64::from(info), 0),
+            24 => (u64::from(self.byte()?), 24),
+            25 => (u64::from(u16::from_be_bytes(self.array()?)), 0x100),
+            26 => (u64::from(u32::from_be_bytes(self.array()?)), 0x1_0000),
+            27 => (u64::from_be_bytes(self.array()?), 0x1_0000_0000),
+            _ => return Err(Error::Decode(format!("unsupported item {head:#x}"))),
+        };
+        // floats are always 64-bit
+        if arg < min && major != 7 {
+            return Err(Error::Decode(format!("non-minimal length {arg}")));
+        }
+        Ok((major, info, arg))
+    }
+}
+
+fn decode_value(reader: &mut Reader, depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(Error::Decode(String::from("too deep")));
+    }
+    let (major, info, arg) = reader.head()?;
+    let len = |arg: u64| usize::try_from(arg).map_err(|_| Error::Decode(String::from("too long")));
+    let integer = |arg: u64| {
+        i64::try_from(arg).map_err(|_| Error::Decode(String::from("integer out of range")))
+    };
+    Ok(match major {
+        0 => Value::Integer(integer(arg)?),
+        1 => Value::Integer(-1 - integer(arg)?),
+        2 => Value::Bytes(reader.take(len(arg)?)?.to_vec()),
+        3 => Value::String(text(reader, len(arg)?)?),
+        4 => {
+            let len = len(arg)?;
+            let mut values = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                values.push(decode_value(reader, depth + 1)?);
+            }
+            Value::Array(values)
+        }
+        5 => {
+            let mut map = BTreeMap::new();
+            let mut last = None::<String>;
+            for _ in 0..len(arg)? {
+                let (3, _, key_len) = reader.head()? else {
+                    return Err(Error::Decode(String::from("map key is not a string")));
+                };
+                let key = text(reader, len(key_len)?)?;
+                // keys are sorted by length, then bytewise
+                if last.as_ref().is_some_and(|last| {
+                    (last.len(), last.as_bytes()) >= (key.len(), key.as_bytes())
+                }) {
+                    return Err(Error::Decode(format!("map keys out of order at {key}")));
+                }
+                let value = decode_value(reader, depth + 1)?;
+                map.insert(key.clone(), value);
+                last = Some(key);
+            }
+            Value::Map(map)
+        }
+        6 if arg == 42 => match decode_value(reader, depth + 1)? {
+            Value::Bytes(bytes) if bytes.first() == Some(&0) => {
+                Value::Link(Cid::from_bytes(&bytes[1..])?)
+            }
+            _ => return Err(Error::Decode(String::from("invalid CID link"))),
+        },
+        6 => return Err(Error::Decode(format!("unsupported tag {arg}"))),
+        _ => match (info, arg) {
+            (20, _) => Value::Bool(false),
+            (21, _) => Value::Bool(true),
+            (22, _) => Value::Null,
+            (27, bits) if f64::from_bits(bits).is_finite() => Value::Float(f64::from_bits(bits)),
+            _ => return Err(Error::Decode(format!("unsupported simple value {arg}"))),
+        },
+    })
+}
+
+fn text(reader: &mut Reader, len: usize) -> Result<String> {
+    String::from_utf8(reader.take(len)?.to_vec())
+        .map_err(|_| Error::Decode(String::from("invalid UTF-8")))
+}
+
+fn encode_value(value: &Value, buf: &mut Vec<u8>) {
+    match value {
+        Value::Null => buf.push(0xf6),
+        Value::Bool(b) => buf.push(if *b { 0xf5 } else { 0xf4 }),
+        Value::Integer(i) if *i >= 0 => encode_head(0, i.unsigned_abs(), buf),
+        Value::Integer(i) => encode_head(1, (-1 - *i).unsigned_abs(), buf),
+        Value::Float(f) => {
+            buf.push(0xfb);
+            buf.extend_from_slice(&f.to_be_bytes());
+        }
+        Value::Bytes(bytes) => {
+            encode_head(2, bytes.len() as u64, buf);
+            buf.extend_from_slice(bytes);
+        }
+        Value::String(s) => {
+            encode_head(3, s.len() as u64, buf);
+            buf.extend_from_slice(s.as_bytes());
+        }
+        Value::Array(values) => {
+            encode_head(4, values.len() as u64, buf);
+            for v in values {
+                encode_value(v, buf);
+            }
+        }
+        Value::Map(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then(a.cmp(b)));
+            encode_head(5, entries.len() as u64, buf);
+            for (k, v) in entries {
+                encode_head(3, k.len() as u64, buf);
+                buf.extend_from_slice(k.as_bytes());
+                encode_value(v, buf);
+            }
+        }
+        Value::Link(cid) => {
+            encode_head(6, 42, buf);
+            encode_head(2, cid.bytes.len() as u64 + 1, buf);
+            buf.push(0);
+            buf.extend_from_slice(&cid.bytes);
+        }
+    }
+}
+
+fn encode_head(major: u8, len: u64, buf: &mut Vec<u8>) {
+    let major = major << 5;
+    match len {
+        0..=23 => buf.push(major | len as u8),
+        24..=0xff => buf.extend_from_slice(&[major | 24, len as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            buf.push(major | 26);
+            buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+}
+
+fn write_varint(mut n: u64, buf: &mut Vec<u8>) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn invalid_cid(err: Error) -> Error {
+    match err {
+        Error::Decode(reason) => Error::InvalidCid(reason),
+        err => err,
+    }
+}
+
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// RFC 4648 base32, lowercase and unpadded, as in the `b` multibase.
+pub(crate) fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 8 / 5 + 1);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(buffer >> bits) as usize & 0x1f].into());
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(buffer << (5 - bits)) as usize & 0x1f].into());
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        let value = BASE32.iter().position(|b| *b == c.to_ascii_lowercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
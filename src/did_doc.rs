@@ -1,16 +1,83 @@
-use crate::common_web::did_doc::{DidDocument, Service};
-use worker::{Env, Response, Result};
+use crate::common_web::did_doc::{DidDocument, Service, VerificationMethod};
+use crate::crypto::{did, error};
 
-pub fn did_doc(env: &Env) -> Result<Response> {
-    Response::from_json(&DidDocument {
-        context: Some(vec![String::from("https://www.w3.org/ns/did/v1")]),
-        id: env.var("SERVICE_DID")?.to_string(),
-        also_known_as: None,
-        verification_method: None,
-        service: Some(vec![Service {
-            id: String::from("#bsky_fg"),
-            r#type: String::from("BskyFeedGenerator"),
-            service_endpoint: env.var("SERVICE_ENDPOINT")?.to_string(),
-        }]),
-    })
+/// The identity that the service publishes as its did:web document.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceIdentity {
+    pub did: String,
+    pub service_endpoint: String,
+    /// Pairs of fragment id (e.g. `atproto`) and `did:key`.
+    pub verification_methods: Vec<(String, String)>,
+    pub also_known_as: Vec<String>,
+}
+
+impl ServiceIdentity {
+    /// Parse `SERVICE_VERIFICATION_METHODS` style values: `id=did:key:...` separated by commas.
+    /// Fails with the first entry that is not of that form.
+    pub fn parse_verification_methods(value: &str) -> Result<Vec<(String, String)>, String> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                entry
+                    .split_once('=')
+                    .map(|(id, did_key)| (id.trim(), did_key.trim()))
+                    .filter(|(id, did_key)| !id.is_empty() && !did_key.is_empty())
+                    .map(|(id, did_key)| (id.to_string(), did_key.to_string()))
+                    .ok_or_else(|| entry.to_string())
+            })
+            .collect()
+    }
+    /// Parse `SERVICE_ALSO_KNOWN_AS` style values separated by commas.
+    pub fn parse_also_known_as(value: &str) -> Vec<String> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    }
+    pub fn document(&self) -> error::Result<DidDocument> {
+        let mut context = vec![String::from("https://www.w3.org/ns/did/v1")];
+        if !self.verification_methods.is_empty() {
+            context.push(String::from("https://w3id.org/security/multikey/v1"));
+        }
+        let verification_method = self
+            .verification_methods
+            .iter()
+            .map(|(id, did_key)| {
+                let parsed = did::parse_did_key(did_key)?;
+                Ok(VerificationMethod {
+                    id: format!("{}#{id}", self.did),
+                    r#type: String::from("Multikey"),
+                    controller: self.did.clone(),
+                    public_key_multibase: Some(did::format_multikey(parsed.jwt_alg, &parsed.key)?),
+                    public_key_jwk: None,
+                })
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        Ok(DidDocument {
+            context: Some(context),
+            id: self.did.clone(),
+            also_known_as: Some(self.also_known_as.clone()).filter(|v| !v.is_empty()),
+            verification_method: Some(verification_method).filter(|v| !v.is_empty()),
+            service: Some(vec![Service {
+                id: String::from("#bsky_fg"),
+                r#type: String::from("BskyFeedGenerator"),
+                service_endpoint: self.service_endpoint.clone(),
+            }]),
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn did_doc(config: &crate::config::Config) -> worker::Result<worker::Response> {
+    match config.service.document() {
+        Ok(did_doc) => worker::Response::from_json(&did_doc),
+        Err(err) => {
+            worker::console_error!("invalid service verification method: {err}");
+            worker::Response::error("Internal Server Error", 500)
+        }
+    }
 }
@@ -1,68 +1,216 @@
 use crate::auth::{self, verify_jwt, SigningKeyProvider};
-use crate::client::{FetchClient, FetchHttpClient};
+use crate::client::FetchClient;
+use crate::config::Config;
+use crate::d1_index::D1Index;
+use crate::filter;
+use crate::identity::did::did_cache::DidCache;
 use crate::identity::did::did_resolver::{DidResolver, Resolver};
+use crate::manifest::{AuthRequirement, FeedDefinition, Source};
+use crate::post_index::PostIndex;
+use crate::post_status::MemoryStatusCache;
+use crate::resolver::did_resolver;
+use crate::skeleton::{self, SkeletonQuery};
+use crate::skeleton_cache;
+use atrium_api::app::bsky::feed::{describe_feed_generator, get_feed_skeleton};
 use atrium_api::client::AtpServiceClient;
 use atrium_api::types::LimitedNonZeroU8;
-use chrono::{Months, SecondsFormat, Utc};
+use atrium_api::xrpc::HttpClient;
+use chrono::{DurationRound, TimeDelta, Utc};
 use serde::Deserialize;
+use std::sync::OnceLock;
 use worker::{console_error, console_log, Env, Request, Response, Result};
 
 #[derive(Debug, Deserialize)]
 struct Query {
-    #[allow(dead_code)]
     feed: String,
     limit: Option<LimitedNonZeroU8<100>>,
+    cursor: Option<String>,
 }
 
-pub async fn feed_skeleton(req: &Request, env: &Env) -> Result<Response> {
-    let mut feed = Vec::new();
+pub async fn feed_skeleton(req: &Request, env: &Env, config: &Config) -> Result<Response> {
+    let mut output = get_feed_skeleton::Output {
+        cursor: None,
+        feed: Vec::new(),
+    };
+    if let Err(err) = config.publisher() {
+        return invalid_configuration(err);
+    }
     let query = req.query::<Query>()?;
-    if let Some(did) = get_user_did(req, env).await? {
-        let params = atrium_api::app::bsky::feed::search_posts::Parameters {
-            author: did.parse().ok(),
-            cursor: None,
-            domain: None,
-            lang: None,
-            limit: query.limit,
-            mentions: None,
-            q: did,
-            since: None,
-            sort: None,
-            tag: None,
-            until: Utc::now()
-                .fixed_offset()
-                .checked_sub_months(Months::new(6))
-                .map(|dt| dt.to_rfc3339_opts(SecondsFormat::Micros, true)),
-            url: None,
-        };
-        console_log!("params: {params:?}");
-        let client = AtpServiceClient::new(FetchClient::new("https://api.bsky.app"));
-        match client.service.app.bsky.feed.search_posts(params).await {
-            Ok(output) => {
-                for post in output.posts {
-                    feed.push(atrium_api::app::bsky::feed::defs::SkeletonFeedPost {
-                        feed_context: None,
-                        post: post.uri,
-                        reason: None,
-                    });
-                }
-            }
-            Err(err) => {
-                console_error!("failed to search post: {err}");
+    let Some(feed) = config.feed_by_uri(&query.feed) else {
+        return xrpc_error(400, "UnknownFeed", &format!("Unknown feed: {}", query.feed));
+    };
+    let did = get_user_did(req, env, config).await?;
+    if did.is_none() && feed.auth == AuthRequirement::Required {
+        return xrpc_error(
+            401,
+            "AuthenticationRequired",
+            "This feed requires a service JWT",
+        );
+    }
+    if let Some(did) = did {
+        if query.cursor.is_none() {
+            if let Some(cached) =
+                skeleton_cache::first_page(env, config, feed, &did, query.limit).await
+            {
+                return Response::from_json(&cached);
             }
         }
+        match assemble(env, config, feed, did, query.limit, query.cursor).await {
+            Ok(skeleton) => output = skeleton,
+            Err(err) => console_error!("{err}"),
+        }
     }
-    Response::from_json(&atrium_api::app::bsky::feed::get_feed_skeleton::Output {
-        cursor: None,
-        feed,
+    Response::from_json(&output)
+}
+
+/// Assemble a page of `feed` for `did` from the upstream services.
+pub async fn assemble(
+    env: &Env,
+    config: &Config,
+    feed: &FeedDefinition,
+    did: String,
+    limit: Option<LimitedNonZeroU8<100>>,
+    cursor: Option<String>,
+) -> Result<get_feed_skeleton::Output> {
+    let history = match did_resolver(env, config)?.resolve_history(&did).await {
+        Ok(history) => history.unwrap_or_default(),
+        Err(err) => {
+            console_error!("failed to resolve plc history: {err}");
+            Vec::new()
+        }
+    };
+    let query = SkeletonQuery {
+        did,
+        limit,
+        cursor,
+        // whole minutes, so that refreshes share cached upstream responses
+        now: Utc::now()
+            .duration_trunc(TimeDelta::minutes(1))
+            .unwrap_or_else(|_| Utc::now()),
+        rule: feed.rule.clone(),
+        lang: filter::search_lang(&feed.filters),
+    };
+    console_log!("query: {query:?}");
+    let client = AtpServiceClient::new(FetchClient::new(
+        &config.upstream.appview_url,
+        &config.upstream,
+    ));
+    let index = match (feed.index, &config.index) {
+        (true, Some(index)) => Some(D1Index::new(env, &index.binding)?),
+        (true, None) => {
+            console_error!(
+                "{} reads the post index, but INDEX_D1 is not set",
+                feed.rkey
+            );
+            None
+        }
+        (false, _) => None,
+    };
+    if let Some(index) = &index {
+        // from now on the viewer's records are read from the Jetstream
+        let tracked = match feed.backfill {
+            true => index.request_backfill(&query.did).await,
+            false => index.track(&query.did).await,
+        };
+        if let Err(err) = tracked {
+            console_error!("{err}");
+        }
+    }
+    let pds = match index.is_none() && (feed.source == Source::Likes || feed.include_reposts) {
+        true => pds_endpoint(env, config, &query.did)
+            .await
+            .map(|pds| AtpServiceClient::new(FetchClient::new(&pds, &config.upstream))),
+        false => None,
+    };
+    let relationships = FetchClient::new(&config.upstream.appview_url, &config.upstream);
+    let upstream = skeleton::Upstream {
+        appview: &client,
+        relationships: &relationships,
+        pds: pds.as_ref(),
+        index: index.as_ref(),
+        statuses: post_statuses(),
+    };
+    let skeleton = skeleton::assemble(&upstream, feed, query, &history)
+        .await
+        .map_err(|err| worker::Error::RustError(err.to_string()))?;
+    Ok(get_feed_skeleton::Output {
+        cursor: skeleton.cursor,
+        feed: skeleton.items.into_iter().map(Into::into).collect(),
+    })
+}
+
+pub fn describe_feed_generator(config: &Config) -> Result<Response> {
+    let publisher = match config.publisher() {
+        Ok(publisher) => publisher,
+        Err(err) => return invalid_configuration(err),
+    };
+    let links = (config.manifest.privacy_policy.is_some()
+        || config.manifest.terms_of_service.is_some())
+    .then(|| describe_feed_generator::Links {
+        privacy_policy: config.manifest.privacy_policy.clone(),
+        terms_of_service: config.manifest.terms_of_service.clone(),
+    });
+    Response::from_json(&describe_feed_generator::Output {
+        did: config.service.did.parse()?,
+        feeds: config
+            .manifest
+            .feeds
+            .iter()
+            .map(|feed| describe_feed_generator::Feed {
+                uri: feed.uri(publisher),
+            })
+            .collect(),
+        links,
     })
 }
 
-struct KeyProvider<T> {
-    did_resolver: DidResolver<T>,
+/// Whether posts still exist, shared by the requests served by this isolate.
+fn post_statuses() -> &'static MemoryStatusCache {
+    static POST_STATUSES: OnceLock<MemoryStatusCache> = OnceLock::new();
+    POST_STATUSES.get_or_init(|| MemoryStatusCache::new(10_000))
+}
+
+/// The PDS of `did`, where its repost records are listed.
+async fn pds_endpoint(env: &Env, config: &Config, did: &str) -> Option<String> {
+    let resolver = match did_resolver(env, config) {
+        Ok(resolver) => resolver,
+        Err(err) => {
+            console_error!("{err}");
+            return None;
+        }
+    };
+    match resolver.ensure_resolve(did, false).await {
+        Ok(did_doc) => did_doc.get_pds_endpoint(),
+        Err(err) => {
+            console_error!("failed to resolve pds of {did}: {err}");
+            None
+        }
+    }
+}
+
+fn xrpc_error(status: u16, error: &str, message: &str) -> Result<Response> {
+    Ok(Response::from_json(&serde_json::json!({
+        "error": error,
+        "message": message,
+    }))?
+    .with_status(status))
+}
+
+/// The response of routes that the configuration does not allow serving.
+fn invalid_configuration(err: crate::config::Error) -> Result<Response> {
+    console_error!("{err}");
+    Response::error(format!("Invalid configuration: {err}"), 500)
+}
+
+struct KeyProvider<T, C> {
+    did_resolver: DidResolver<T, C>,
 }
 
-impl SigningKeyProvider for KeyProvider<FetchHttpClient> {
+impl<T, C> SigningKeyProvider for KeyProvider<T, C>
+where
+    T: HttpClient,
+    C: DidCache,
+{
     async fn get_signing_key(&self, iss: &str, force_refresh: bool) -> auth::Result<String> {
         self.did_resolver
             .resolve_atproto_key(iss, force_refresh)
@@ -71,7 +219,7 @@ impl SigningKeyProvider for KeyProvider<FetchHttpClient> {
     }
 }
 
-async fn get_user_did(req: &Request, env: &Env) -> Result<Option<String>> {
+async fn get_user_did(req: &Request, env: &Env, config: &Config) -> Result<Option<String>> {
     let token = req
         .headers()
         .get("Authorization")?
@@ -79,9 +227,10 @@ async fn get_user_did(req: &Request, env: &Env) -> Result<Option<String>> {
     if let Some(jwt) = token {
         match verify_jwt(
             &jwt,
-            Some(&env.var("SERVICE_DID")?.to_string()),
+            Some(&config.service.did),
+            config.auth.lxm.as_deref(),
             KeyProvider {
-                did_resolver: DidResolver::new(FetchHttpClient, "https://plc.directory"),
+                did_resolver: did_resolver(env, config)?,
             },
         )
         .await
@@ -0,0 +1,115 @@
+//! Per-feed content filters, applied to hydrated post metadata.
+use crate::manifest::Filter;
+use crate::skeleton::FeedItem;
+use atrium_api::app::bsky::embed::record_with_media::MainMediaRefs;
+use atrium_api::app::bsky::feed::defs::PostView;
+use atrium_api::app::bsky::feed::post::{self, RecordEmbedRefs};
+use atrium_api::app::bsky::richtext::facet::MainFeaturesItem;
+use atrium_api::records::{KnownRecord, Record};
+use atrium_api::types::Union;
+
+/// What the filters need to know about a post.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PostMeta {
+    pub is_reply: bool,
+    /// Whether the post embeds images or video.
+    pub has_media: bool,
+    /// Whether the post links to a URL, in its text or as an external embed.
+    pub has_links: bool,
+    pub langs: Vec<String>,
+}
+
+impl From<&PostView> for PostMeta {
+    fn from(post: &PostView) -> Self {
+        match &post.record {
+            Record::Known(KnownRecord::AppBskyFeedPost(record)) => Self::from(record.as_ref()),
+            _ => Self::default(),
+        }
+    }
+}
+
+impl From<&post::Record> for PostMeta {
+    fn from(record: &post::Record) -> Self {
+        let (has_media, external) = match &record.embed {
+            Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedImagesMain(_))) => (true, false),
+            Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedExternalMain(_))) => (false, true),
+            Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedRecordWithMediaMain(embed))) => {
+                match &embed.media {
+                    Union::Refs(MainMediaRefs::AppBskyEmbedImagesMain(_)) => (true, false),
+                    Union::Refs(MainMediaRefs::AppBskyEmbedExternalMain(_)) => (false, true),
+                    Union::Unknown(data) => (is_video(data), false),
+                }
+            }
+            Some(Union::Unknown(data)) => (is_video(data), false),
+            _ => (false, false),
+        };
+        let has_link_facet = record.facets.iter().flatten().any(|facet| {
+            facet
+                .features
+                .iter()
+                .any(|feature| matches!(feature, Union::Refs(MainFeaturesItem::Link(_))))
+        });
+        Self {
+            is_reply: record.reply.is_some(),
+            has_media,
+            has_links: external || has_link_facet,
+            langs: record
+                .langs
+                .iter()
+                .flatten()
+                .map(|lang| lang.as_ref().as_str().to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Embeds that this version of the lexicon does not know, such as `app.bsky.embed.video`.
+fn is_video<T: serde::Serialize>(data: &T) -> bool {
+    serde_json::to_value(data)
+        .ok()
+        .and_then(|value| value["$type"].as_str().map(|t| t == "app.bsky.embed.video"))
+        .unwrap_or(false)
+}
+
+impl Filter {
+    pub fn matches(&self, meta: &PostMeta) -> bool {
+        match self {
+            Filter::NoReplies => !meta.is_reply,
+            Filter::Media { exclude } => meta.has_media != *exclude,
+            Filter::Links { exclude } => meta.has_links != *exclude,
+            Filter::Lang { langs } => meta.langs.iter().any(|tag| {
+                // `en` matches `en-US`
+                let primary = tag.split('-').next().unwrap_or(tag);
+                langs.iter().any(|lang| {
+                    lang.eq_ignore_ascii_case(tag) || lang.eq_ignore_ascii_case(primary)
+                })
+            }),
+        }
+    }
+}
+
+/// Keep the items whose metadata matches every filter.
+///
+/// Items without metadata, i.e. posts that could not be hydrated, are dropped unless
+/// there are no filters.
+pub fn apply(filters: &[Filter], items: Vec<FeedItem>) -> Vec<FeedItem> {
+    if filters.is_empty() {
+        return items;
+    }
+    items
+        .into_iter()
+        .filter(|item| {
+            item.meta
+                .as_ref()
+                .is_some_and(|meta| filters.iter().all(|filter| filter.matches(meta)))
+        })
+        .collect()
+}
+
+/// The language to pass to `searchPosts`, when a single one is required.
+pub fn search_lang(filters: &[Filter]) -> Option<String> {
+    filters.iter().find_map(|filter| match filter {
+        Filter::Lang { langs } if langs.len() == 1 => Some(langs[0].clone()),
+        _ => None,
+    })
+}
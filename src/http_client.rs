@@ -0,0 +1,291 @@
+//! Runtime-independent helpers shared by `HttpClient` implementations.
+use async_trait::async_trait;
+use atrium_api::xrpc::{HttpClient, XrpcClient};
+use chrono::{DateTime, Utc};
+use http::{header, Method, Request, Response, StatusCode};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// A request extension asking the `HttpClient` to give up after the given duration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTimeout(pub Duration);
+
+/// A request extension bounding the total time `RetryingHttpClient` spends on a request,
+/// including every attempt and the waits between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestDeadline(pub Duration);
+
+/// The error of `RetryingHttpClient` when the `RequestDeadline` of a request passed before
+/// an attempt, with the URI of the request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadlineExceeded(pub String);
+
+impl std::fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "deadline exceeded: {}", self.0)
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// A request extension asking the `HttpClient` to stop reading a response whose body is
+/// longer than the given number of bytes, and to fail with `ResponseTooLarge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxResponseSize(pub usize);
+
+/// The error of an `HttpClient` that gave up on a response over its `MaxResponseSize`, with
+/// the size that was declared or read so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseTooLarge(pub usize);
+
+impl std::fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "response too large: {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for ResponseTooLarge {}
+
+impl ResponseTooLarge {
+    /// The `ResponseTooLarge` that `err` is or was caused by, if any.
+    pub fn find<'a>(err: &'a (dyn std::error::Error + 'static)) -> Option<&'a Self> {
+        let mut err = Some(err);
+        while let Some(current) = err {
+            if let Some(too_large) = current.downcast_ref::<Self>() {
+                return Some(too_large);
+            }
+            err = current.source();
+        }
+        None
+    }
+}
+
+/// A request extension marking a request as safe to repeat even though its method is not
+/// idempotent, e.g. an XRPC procedure that has no side effects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Idempotent;
+
+/// Waits for a duration on the current runtime.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait Sleep {
+    async fn sleep(&self, duration: Duration);
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryOptions {
+    /// Attempts per request, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled for every further one.
+    pub base_delay: Duration,
+    /// The longest wait before a retry. Responses asking to be retried later are returned.
+    pub max_delay: Duration,
+    /// The timeout of each attempt, unless the request has a `RequestTimeout`.
+    pub attempt_timeout: Option<Duration>,
+    /// The total time per request, unless the request has a `RequestDeadline`.
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            attempt_timeout: Some(Duration::from_secs(5)),
+            deadline: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+/// An `HttpClient` retrying transient failures of `T` with exponential backoff and jitter.
+///
+/// Idempotent requests are retried after transport errors, timeouts, 408, 429 and 5xx
+/// gateway errors. Other requests are only retried when the server asks to be retried
+/// later with 429 or a 503 with `Retry-After`, since they were not processed. A response
+/// whose `Retry-After` is over `max_delay` or the time left before the deadline is
+/// returned rather than retried early.
+#[derive(Debug, Clone)]
+pub struct RetryingHttpClient<T, S> {
+    inner: T,
+    sleep: S,
+    options: RetryOptions,
+}
+
+impl<T, S> RetryingHttpClient<T, S> {
+    pub fn new(inner: T, sleep: S) -> Self {
+        Self::with_options(inner, sleep, RetryOptions::default())
+    }
+    pub fn with_options(inner: T, sleep: S, options: RetryOptions) -> Self {
+        Self {
+            inner,
+            sleep,
+            options,
+        }
+    }
+    /// The delay before retry number `retry` (from 1), with equal jitter.
+    fn backoff(&self, retry: u32) -> Duration {
+        let delay = self
+            .options
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.options.max_delay);
+        let mut random = [0u8; 4];
+        let fraction = match getrandom::getrandom(&mut random) {
+            Ok(()) => f64::from(u32::from_le_bytes(random)) / f64::from(u32::MAX),
+            Err(_) => 1.0,
+        };
+        delay / 2 + (delay / 2).mul_f64(fraction)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<T, S> HttpClient for RetryingHttpClient<T, S>
+where
+    T: HttpClient + Send + Sync,
+    S: Sleep + Send + Sync,
+{
+    async fn send_http(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, BoxError> {
+        let started = Utc::now();
+        let deadline = request
+            .extensions()
+            .get::<RequestDeadline>()
+            .map(|RequestDeadline(deadline)| *deadline)
+            .or(self.options.deadline)
+            .and_then(|deadline| chrono::Duration::from_std(deadline).ok())
+            .map(|deadline| started + deadline);
+        let attempt_timeout = request
+            .extensions()
+            .get::<RequestTimeout>()
+            .map(|RequestTimeout(timeout)| *timeout)
+            .or(self.options.attempt_timeout);
+        let idempotent = is_idempotent(&request);
+        let mut attempt = 1;
+        loop {
+            let remaining = match deadline.map(|deadline| time_left(deadline, Utc::now())) {
+                Some(None) => return Err(DeadlineExceeded(request.uri().to_string()).into()),
+                Some(Some(remaining)) => Some(remaining),
+                None => None,
+            };
+            let mut retry = copy_request(&request)?;
+            if let Some(timeout) = match (attempt_timeout, remaining) {
+                (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+                (timeout, remaining) => timeout.or(remaining),
+            } {
+                retry.extensions_mut().insert(RequestTimeout(timeout));
+            }
+            let result = self.inner.send_http(retry).await;
+            if attempt >= self.options.max_attempts {
+                return result;
+            }
+            let delay = match &result {
+                Ok(response) => match retry_delay(response, idempotent) {
+                    // the server is not ready before then, so a sooner retry would only fail
+                    Some(Some(retry_after)) if retry_after > self.options.max_delay => {
+                        return result
+                    }
+                    Some(Some(retry_after)) => retry_after,
+                    Some(None) => self.backoff(attempt),
+                    None => return result,
+                },
+                Err(err) if idempotent && ResponseTooLarge::find(err.as_ref()).is_none() => {
+                    self.backoff(attempt)
+                }
+                Err(_) => return result,
+            };
+            if let Some(deadline) = deadline {
+                // give up rather than wait past the deadline
+                match time_left(deadline, Utc::now()) {
+                    Some(remaining) if delay < remaining => {}
+                    _ => return result,
+                }
+            }
+            self.sleep.sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<T, S> XrpcClient for RetryingHttpClient<T, S>
+where
+    T: XrpcClient + Send + Sync,
+    S: Sleep + Send + Sync,
+{
+    fn base_uri(&self) -> String {
+        self.inner.base_uri()
+    }
+    async fn auth(&self, is_refresh: bool) -> Option<String> {
+        self.inner.auth(is_refresh).await
+    }
+}
+
+fn is_idempotent(request: &Request<Vec<u8>>) -> bool {
+    matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+    ) || request.extensions().get::<Idempotent>().is_some()
+}
+
+/// Whether to retry after `response`: `Some(Some(delay))` if the server gave a delay,
+/// `Some(None)` to back off, and `None` not to retry.
+fn retry_delay(response: &Response<Vec<u8>>, idempotent: bool) -> Option<Option<Duration>> {
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_retry_after(value, Utc::now()));
+    match response.status() {
+        StatusCode::TOO_MANY_REQUESTS => Some(retry_after),
+        StatusCode::SERVICE_UNAVAILABLE if retry_after.is_some() => Some(retry_after),
+        StatusCode::REQUEST_TIMEOUT
+        | StatusCode::INTERNAL_SERVER_ERROR
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT
+            if idempotent =>
+        {
+            Some(None)
+        }
+        _ => None,
+    }
+}
+
+/// Parse `Retry-After`, either delay seconds or an HTTP date.
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => DateTime::parse_from_rfc2822(value.trim()).ok().map(|date| {
+            (date.with_timezone(&Utc) - now)
+                .to_std()
+                .unwrap_or_default()
+        }),
+    }
+}
+
+fn time_left(deadline: DateTime<Utc>, now: DateTime<Utc>) -> Option<Duration> {
+    (deadline - now).to_std().ok().filter(|d| !d.is_zero())
+}
+
+/// Copy `request` for another attempt. Extensions other than the ones of this module are
+/// not carried over.
+fn copy_request(request: &Request<Vec<u8>>) -> Result<Request<Vec<u8>>, BoxError> {
+    let mut builder = Request::builder()
+        .method(request.method().clone())
+        .uri(request.uri().clone())
+        .version(request.version());
+    if let Some(headers) = builder.headers_mut() {
+        headers.clone_from(request.headers());
+    }
+    let mut copy = builder.body(request.body().clone())?;
+    if request.extensions().get::<Idempotent>().is_some() {
+        copy.extensions_mut().insert(Idempotent);
+    }
+    if let Some(max_size) = request.extensions().get::<MaxResponseSize>() {
+        copy.extensions_mut().insert(*max_size);
+    }
+    Ok(copy)
+}
@@ -1,3 +1,6 @@
 pub mod atproto_data;
+pub mod did_cache;
 pub mod did_resolver;
+pub mod plc_operation;
 pub mod plc_resolver;
+pub mod web_resolver;
@@ -1,10 +1,20 @@
-use crate::common_web::did_doc::DidDocument;
+use crate::common_web::did_doc::{DidDocument, Jwk, VerificationMethod};
 use crate::crypto::consts::JwtAlg;
 use crate::crypto::{did, error, multibase};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use std::collections::BTreeMap;
 
 #[derive(Debug)]
 pub enum Error {
     SigningKeyNotFound(DidDocument),
+    /// No verification method with the key id (without `#`) in the document of the DID.
+    VerificationMethodNotFound(String, String),
+    UnsupportedKeyType(String),
+    /// A key on a curve whose keys cannot be converted (P-256), named as in JWKs.
+    UnsupportedCurve(String),
+    MissingPublicKey(String),
+    InvalidJwk(Jwk),
+    Base64Decode(base64::DecodeError),
     Crypto(error::Error),
 }
 
@@ -14,6 +24,23 @@ impl std::fmt::Display for Error {
             Error::SigningKeyNotFound(did_doc) => {
                 write!(f, "Could not parse signingKey from doc: {did_doc:?}")
             }
+            Error::VerificationMethodNotFound(key_id, did) => {
+                write!(f, "Could not find verification method #{key_id} for {did}")
+            }
+            Error::UnsupportedKeyType(key_type) => {
+                write!(f, "Unsupported verification method type: {key_type}")
+            }
+            Error::UnsupportedCurve(crv) => {
+                write!(
+                    f,
+                    "Unsupported key curve: {crv}, only secp256k1 keys are supported"
+                )
+            }
+            Error::MissingPublicKey(id) => {
+                write!(f, "No public key in verification method: {id}")
+            }
+            Error::InvalidJwk(jwk) => write!(f, "Invalid JWK: {jwk:?}"),
+            Error::Base64Decode(err) => write!(f, "Base64Decode: {err}"),
             Error::Crypto(err) => write!(f, "Crypto error: {err}"),
         }
     }
@@ -23,21 +50,28 @@ impl std::error::Error for Error {}
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-fn get_key(did_doc: &DidDocument) -> Result<Option<String>> {
-    if let Some(key) = did_doc.get_signing_key() {
-        Ok(Some(get_did_key_from_multibase(key)?))
+fn get_key(did_doc: &DidDocument, key_id: &str) -> Result<Option<String>> {
+    if let Some(method) = did_doc.get_verification_method(key_id) {
+        Ok(Some(get_did_key(method)?))
     } else {
         Ok(None)
     }
 }
 
+fn get_did_key(method: &VerificationMethod) -> Result<String> {
+    match (&method.public_key_multibase, &method.public_key_jwk) {
+        (Some(public_key_multibase), _) => {
+            get_did_key_from_multibase((method.r#type.clone(), public_key_multibase.clone()))
+        }
+        (None, Some(jwk)) if method.r#type == "JsonWebKey2020" => get_did_key_from_jwk(jwk),
+        (None, Some(_)) => Err(Error::UnsupportedKeyType(method.r#type.clone())),
+        (None, None) => Err(Error::MissingPublicKey(method.id.clone())),
+    }
+}
+
 fn get_did_key_from_multibase((r#type, public_key_multibase): (String, String)) -> Result<String> {
     match r#type.as_str() {
-        "EcdsaSecp256r1VerificationKey2019" => Ok(did::format_did_key(
-            JwtAlg::P256,
-            &multibase::multibase_to_bytes(&public_key_multibase).map_err(Error::Crypto)?,
-        )
-        .map_err(Error::Crypto)?),
+        "EcdsaSecp256r1VerificationKey2019" => Err(Error::UnsupportedCurve("P-256".into())),
         "EcdsaSecp256k1VerificationKey2019" => Ok(did::format_did_key(
             JwtAlg::Secp256k1,
             &multibase::multibase_to_bytes(&public_key_multibase).map_err(Error::Crypto)?,
@@ -47,10 +81,58 @@ fn get_did_key_from_multibase((r#type, public_key_multibase): (String, String))
             let parsed = did::parse_multikey(&public_key_multibase).map_err(Error::Crypto)?;
             Ok(did::format_did_key(parsed.jwt_alg, &parsed.key).map_err(Error::Crypto)?)
         }
-        _ => unimplemented!(),
+        _ => Err(Error::UnsupportedKeyType(r#type)),
+    }
+}
+
+fn get_did_key_from_jwk(jwk: &Jwk) -> Result<String> {
+    let jwt_alg = match (jwk.kty.as_str(), jwk.crv.as_str()) {
+        // rejected before decoding, as the P-256 plugin does not compress keys
+        ("EC", "P-256") => return Err(Error::UnsupportedCurve(jwk.crv.clone())),
+        ("EC", "secp256k1") => JwtAlg::Secp256k1,
+        _ => return Err(Error::InvalidJwk(jwk.clone())),
+    };
+    let y = jwk
+        .y
+        .as_ref()
+        .ok_or_else(|| Error::InvalidJwk(jwk.clone()))?;
+    let x = URL_SAFE_NO_PAD
+        .decode(&jwk.x)
+        .map_err(Error::Base64Decode)?;
+    let y = URL_SAFE_NO_PAD.decode(y).map_err(Error::Base64Decode)?;
+    if x.len() != 32 || y.len() != 32 {
+        return Err(Error::InvalidJwk(jwk.clone()));
     }
+    // uncompressed SEC1 point
+    let key = [&[0x04], x.as_slice(), y.as_slice()].concat();
+    did::format_did_key(jwt_alg, &key).map_err(Error::Crypto)
+}
+
+/// All verification methods of the document as `did:key`s, keyed by fragment id
+/// (e.g. `atproto`, `atproto_label`). Each method is converted on its own, so one that is
+/// unsupported or malformed does not hide the others.
+pub fn get_keys(did_doc: &DidDocument) -> BTreeMap<String, Result<String>> {
+    let did = did_doc.get_did();
+    did_doc
+        .verification_method
+        .iter()
+        .flatten()
+        .filter_map(|method| {
+            method
+                .id
+                .strip_prefix(&did)
+                .unwrap_or(&method.id)
+                .strip_prefix('#')
+                .map(|key_id| (key_id.to_string(), get_did_key(method)))
+        })
+        .collect()
+}
+
+pub fn ensure_key(did_doc: &DidDocument, key_id: &str) -> Result<String> {
+    get_key(did_doc, key_id)?
+        .ok_or_else(|| Error::VerificationMethodNotFound(key_id.into(), did_doc.get_did()))
 }
 
 pub fn ensure_atproto_key(did_doc: &DidDocument) -> Result<String> {
-    get_key(did_doc)?.ok_or(Error::SigningKeyNotFound(did_doc.clone()))
+    get_key(did_doc, "atproto")?.ok_or(Error::SigningKeyNotFound(did_doc.clone()))
 }
@@ -0,0 +1,40 @@
+use super::did_resolver::Result;
+use super::plc_operation::PlcHistoryEntry;
+use crate::common_web::did_doc::DidDocument;
+use std::future::Future;
+
+pub trait DidCache {
+    fn get(&self, did: &str) -> impl Future<Output = Result<Option<DidDocument>>>;
+    fn set(&self, did: &str, did_doc: &DidDocument) -> impl Future<Output = Result<()>>;
+    /// Forget the document and the history of `did`.
+    fn clear(&self, did: &str) -> impl Future<Output = Result<()>>;
+    /// The verified identity timeline of a `did:plc`.
+    fn get_history(&self, did: &str) -> impl Future<Output = Result<Option<Vec<PlcHistoryEntry>>>>;
+    fn set_history(
+        &self,
+        did: &str,
+        history: &[PlcHistoryEntry],
+    ) -> impl Future<Output = Result<()>>;
+}
+
+/// A cache that never stores anything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoCache;
+
+impl DidCache for NoCache {
+    async fn get(&self, _did: &str) -> Result<Option<DidDocument>> {
+        Ok(None)
+    }
+    async fn set(&self, _did: &str, _did_doc: &DidDocument) -> Result<()> {
+        Ok(())
+    }
+    async fn clear(&self, _did: &str) -> Result<()> {
+        Ok(())
+    }
+    async fn get_history(&self, _did: &str) -> Result<Option<Vec<PlcHistoryEntry>>> {
+        Ok(None)
+    }
+    async fn set_history(&self, _did: &str, _history: &[PlcHistoryEntry]) -> Result<()> {
+        Ok(())
+    }
+}
@@ -1,19 +1,32 @@
 use super::atproto_data;
+use super::did_cache::{DidCache, NoCache};
+use super::plc_operation::{self, PlcHistoryEntry};
 use super::plc_resolver::DidPlcResolver;
+use super::web_resolver::DidWebResolver;
 use crate::common_web::did_doc::DidDocument;
+use crate::http_client::{MaxResponseSize, RequestTimeout, ResponseTooLarge};
 use atrium_api::xrpc::HttpClient;
+use chrono::{DateTime, Utc};
+use http::Request;
 use serde_json::from_slice;
+use std::collections::BTreeMap;
 use std::future::Future;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum Error {
     DidNotFoundError(String),
     PoorlyFormattedDid(String),
     UnsupportedDidMethod(String),
+    UnsupportedDidWebPath(String),
+    DidWebHostNotAllowed(String),
+    DocumentTooLarge(String, usize),
+    Cache(String),
     Http(http::Error),
     HttpClient(Box<dyn std::error::Error + Send + Sync + 'static>),
     SerdeJson(serde_json::Error),
     AtprotoData(atproto_data::Error),
+    PlcOperation(plc_operation::Error),
 }
 
 impl std::fmt::Display for Error {
@@ -22,10 +35,19 @@ impl std::fmt::Display for Error {
             Error::DidNotFoundError(did) => write!(f, "Could not resolve DID: {did}"),
             Error::PoorlyFormattedDid(did) => write!(f, "Poorly formatted DID: {did}"),
             Error::UnsupportedDidMethod(did) => write!(f, "Unsupported DID method: {did}"),
+            Error::UnsupportedDidWebPath(did) => {
+                write!(f, "Unsupported did:web paths: {did}")
+            }
+            Error::DidWebHostNotAllowed(did) => write!(f, "did:web host not allowed: {did}"),
+            Error::DocumentTooLarge(did, size) => {
+                write!(f, "DID document too large ({size} bytes): {did}")
+            }
+            Error::Cache(msg) => write!(f, "Cache error: {msg}"),
             Error::Http(err) => write!(f, "HTTP error: {err}"),
             Error::HttpClient(err) => write!(f, "HttpClient error: {err}"),
             Error::SerdeJson(err) => write!(f, "SerdeJson error: {err}"),
             Error::AtprotoData(err) => write!(f, "AtprotoData error: {err}"),
+            Error::PlcOperation(err) => write!(f, "PlcOperation error: {err}"),
         }
     }
 }
@@ -40,7 +62,7 @@ pub trait Resolver {
     fn resolve(
         &self,
         did: &str,
-        force_refresh: bool,
+        _force_refresh: bool,
     ) -> impl Future<Output = Result<Option<DidDocument>>> {
         async move {
             // TODO: from cache?
@@ -73,6 +95,30 @@ pub trait Resolver {
                 .ok_or(Error::DidNotFoundError(did.into()))
         }
     }
+    /// Resolve the verification method `#{key_id}` of `did` as a `did:key`.
+    fn resolve_key(
+        &self,
+        did: &str,
+        key_id: &str,
+        force_refresh: bool,
+    ) -> impl Future<Output = Result<String>> {
+        async move {
+            let did_document = self.ensure_resolve(did, force_refresh).await?;
+            atproto_data::ensure_key(&did_document, key_id).map_err(Error::AtprotoData)
+        }
+    }
+    /// Resolve every verification method of `did` as `did:key`s keyed by fragment id, with
+    /// the error of each method that could not be converted.
+    fn resolve_keys(
+        &self,
+        did: &str,
+        force_refresh: bool,
+    ) -> impl Future<Output = Result<BTreeMap<String, atproto_data::Result<String>>>> {
+        async move {
+            let did_document = self.ensure_resolve(did, force_refresh).await?;
+            Ok(atproto_data::get_keys(&did_document))
+        }
+    }
     fn resolve_atproto_key(
         &self,
         did: &str,
@@ -89,24 +135,170 @@ pub trait Resolver {
     }
 }
 
-pub struct DidResolver<T> {
+/// GET `uri` for `did`, giving up on bodies over `max_size` as they are read.
+pub(super) async fn get<T: HttpClient>(
+    client: &T,
+    did: &str,
+    uri: &str,
+    timeout: Option<Duration>,
+    max_size: Option<usize>,
+) -> Result<Option<Vec<u8>>> {
+    let mut request = Request::get(uri).body(Vec::new()).map_err(Error::Http)?;
+    if let Some(timeout) = timeout {
+        request.extensions_mut().insert(RequestTimeout(timeout));
+    }
+    if let Some(max_size) = max_size {
+        request.extensions_mut().insert(MaxResponseSize(max_size));
+    }
+    let response = match client.send_http(request).await {
+        Ok(response) => response,
+        Err(err) => {
+            return Err(match ResponseTooLarge::find(err.as_ref()) {
+                Some(ResponseTooLarge(size)) => Error::DocumentTooLarge(did.into(), *size),
+                None => Error::HttpClient(err),
+            })
+        }
+    };
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    // for clients that do not honor `MaxResponseSize`
+    match max_size {
+        Some(max) if response.body().len() > max => {
+            Err(Error::DocumentTooLarge(did.into(), response.body().len()))
+        }
+        _ => Ok(Some(response.into_body())),
+    }
+}
+
+pub struct DidResolverOptions<C> {
+    pub plc_url: String,
+    /// Hosts that may be resolved as `did:web`; `None` allows any host.
+    pub web_allowed_hosts: Option<Vec<String>>,
+    pub timeout: Option<Duration>,
+    pub max_document_size: Option<usize>,
+    pub cache: C,
+}
+
+impl Default for DidResolverOptions<NoCache> {
+    fn default() -> Self {
+        Self {
+            plc_url: String::from("https://plc.directory"),
+            web_allowed_hosts: None,
+            timeout: Some(Duration::from_secs(3)),
+            max_document_size: Some(64 * 1024),
+            cache: NoCache,
+        }
+    }
+}
+
+pub struct DidResolver<T, C = NoCache> {
     plc: DidPlcResolver<T>,
+    web: DidWebResolver<T>,
+    cache: C,
 }
 
-impl<T> DidResolver<T> {
+impl<T> DidResolver<T>
+where
+    T: Clone,
+{
     pub fn new(client: T, plc_url: impl AsRef<str>) -> Self {
+        Self::with_options(
+            client,
+            DidResolverOptions {
+                plc_url: plc_url.as_ref().into(),
+                ..Default::default()
+            },
+        )
+    }
+}
+
+impl<T, C> DidResolver<T, C>
+where
+    T: Clone,
+{
+    pub fn with_options(client: T, options: DidResolverOptions<C>) -> Self {
         Self {
-            plc: DidPlcResolver::new(client, plc_url),
+            plc: DidPlcResolver::new(
+                client.clone(),
+                options.plc_url,
+                options.timeout,
+                options.max_document_size,
+            ),
+            web: DidWebResolver::new(
+                client,
+                options.timeout,
+                options.max_document_size,
+                options.web_allowed_hosts,
+            ),
+            cache: options.cache,
+        }
+    }
+}
+
+impl<T, C> DidResolver<T, C>
+where
+    T: HttpClient,
+    C: DidCache,
+{
+    /// The identity timeline of `did`, available only for `did:plc`. It is cached along
+    /// with the DID document, so the audit log is fetched and verified once per cache TTL.
+    pub async fn resolve_history(&self, did: &str) -> Result<Option<Vec<PlcHistoryEntry>>> {
+        if !did.starts_with("did:plc:") {
+            return Ok(None);
+        }
+        match self.cache.get_history(did).await {
+            Ok(Some(cached)) => return Ok(Some(cached)),
+            Ok(None) => {}
+            Err(err) => log_error!("failed to read the history of {did} from the cache: {err}"),
+        }
+        let history = self.plc.resolve_history(did).await?;
+        if let Some(history) = &history {
+            if let Err(err) = self.cache.set_history(did, history).await {
+                log_error!("failed to cache the history of {did}: {err}");
+            }
         }
+        Ok(history)
+    }
+    /// The handle, PDS and signing key that `did` had at `at`.
+    pub async fn resolve_at(
+        &self,
+        did: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<PlcHistoryEntry>> {
+        Ok(self
+            .resolve_history(did)
+            .await?
+            .and_then(|history| plc_operation::entry_at(&history, at).cloned()))
     }
 }
 
-impl<T> Resolver for DidResolver<T>
+impl<T, C> Resolver for DidResolver<T, C>
 where
     T: HttpClient,
+    C: DidCache,
 {
+    /// Resolve `did` through the cache. The cache is only an optimization: when it fails,
+    /// the document is resolved as if it were not cached.
+    async fn resolve(&self, did: &str, force_refresh: bool) -> Result<Option<DidDocument>> {
+        if !force_refresh {
+            match self.cache.get(did).await {
+                Ok(Some(cached)) => return Ok(Some(cached)),
+                Ok(None) => {}
+                Err(err) => log_error!("failed to read {did} from the cache: {err}"),
+            }
+        }
+        let got = self.resolve_no_cache(did).await?;
+        let cached = match &got {
+            Some(got) => self.cache.set(did, got).await,
+            None => self.cache.clear(did).await,
+        };
+        if let Err(err) = cached {
+            log_error!("failed to update {did} in the cache: {err}");
+        }
+        Ok(got)
+    }
     async fn resolve_no_check(&self, did: &str) -> Result<Option<Vec<u8>>> {
-        println!("Resolving DID: {did}");
         let parts = did.split(':').collect::<Vec<_>>();
         if parts.len() < 2 || parts[0] != "did" {
             return Err(Error::PoorlyFormattedDid(did.into()));
@@ -114,7 +306,7 @@ where
         let method = parts[1];
         match method {
             "plc" => self.plc.resolve_no_check(did).await,
-            "web" => unimplemented!(),
+            "web" => self.web.resolve_no_check(did).await,
             _ => Err(Error::UnsupportedDidMethod(did.into())),
         }
     }
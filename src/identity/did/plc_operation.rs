@@ -0,0 +1,302 @@
+//! PLC operation log parsing and verification.
+use crate::crypto;
+use crate::dag_cbor::{self, Cid, DAG_CBOR};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidGenesis(String),
+    /// The operation does not hash to the CID of its entry.
+    InvalidCid(String),
+    MisorderedOperation(String),
+    InvalidSignature(String),
+    AfterTombstone(String),
+    InvalidCreatedAt(String),
+    UnsupportedValue(Value),
+    Base64Decode(base64::DecodeError),
+    SerdeJson(serde_json::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::InvalidGenesis(cid) => write!(f, "Invalid genesis operation: {cid}"),
+            Error::InvalidCid(cid) => write!(f, "Operation does not match its CID: {cid}"),
+            Error::MisorderedOperation(cid) => write!(f, "Operations not correctly ordered: {cid}"),
+            Error::InvalidSignature(cid) => write!(f, "Invalid signature on operation: {cid}"),
+            Error::AfterTombstone(cid) => write!(f, "Operation after tombstone: {cid}"),
+            Error::InvalidCreatedAt(created_at) => write!(f, "Invalid createdAt: {created_at}"),
+            Error::UnsupportedValue(value) => write!(f, "Unsupported value in operation: {value}"),
+            Error::Base64Decode(err) => write!(f, "Base64Decode: {err}"),
+            Error::SerdeJson(err) => write!(f, "SerdeJson error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An entry of the `/log/audit` response.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLogEntry {
+    pub did: String,
+    /// Kept as a raw value so that the signed bytes can be reproduced exactly.
+    pub operation: Value,
+    pub cid: String,
+    pub nullified: bool,
+    pub created_at: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum Operation {
+    #[serde(rename = "plc_operation")]
+    Operation(PlcOperation),
+    #[serde(rename = "plc_tombstone")]
+    Tombstone(Tombstone),
+    #[serde(rename = "create")]
+    Legacy(LegacyCreate),
+}
+
+impl Operation {
+    pub fn prev(&self) -> Option<&str> {
+        match self {
+            Operation::Operation(op) => op.prev.as_deref(),
+            Operation::Tombstone(op) => Some(&op.prev),
+            Operation::Legacy(op) => op.prev.as_deref(),
+        }
+    }
+    pub fn sig(&self) -> &str {
+        match self {
+            Operation::Operation(op) => &op.sig,
+            Operation::Tombstone(op) => &op.sig,
+            Operation::Legacy(op) => &op.sig,
+        }
+    }
+    pub fn document_data(&self) -> Option<DocumentData> {
+        match self {
+            Operation::Operation(op) => Some(DocumentData {
+                rotation_keys: op.rotation_keys.clone(),
+                verification_methods: op.verification_methods.clone(),
+                also_known_as: op.also_known_as.clone(),
+                services: op.services.clone(),
+            }),
+            Operation::Tombstone(_) => None,
+            Operation::Legacy(op) => Some(DocumentData {
+                rotation_keys: vec![op.recovery_key.clone(), op.signing_key.clone()],
+                verification_methods: BTreeMap::from([(
+                    String::from("atproto"),
+                    op.signing_key.clone(),
+                )]),
+                also_known_as: vec![ensure_at_prefix(&op.handle)],
+                services: BTreeMap::from([(
+                    String::from("atproto_pds"),
+                    PlcService {
+                        r#type: String::from("AtprotoPersonalDataServer"),
+                        endpoint: ensure_http_prefix(&op.service),
+                    },
+                )]),
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PlcOperation {
+    pub rotation_keys: Vec<String>,
+    pub verification_methods: BTreeMap<String, String>,
+    pub also_known_as: Vec<String>,
+    pub services: BTreeMap<String, PlcService>,
+    pub prev: Option<String>,
+    pub sig: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Tombstone {
+    pub prev: String,
+    pub sig: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyCreate {
+    pub signing_key: String,
+    pub recovery_key: String,
+    pub handle: String,
+    pub service: String,
+    pub prev: Option<String>,
+    pub sig: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PlcService {
+    pub r#type: String,
+    pub endpoint: String,
+}
+
+/// The state of a DID document as defined by a PLC operation.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentData {
+    pub rotation_keys: Vec<String>,
+    pub verification_methods: BTreeMap<String, String>,
+    pub also_known_as: Vec<String>,
+    pub services: BTreeMap<String, PlcService>,
+}
+
+impl DocumentData {
+    pub fn get_handle(&self) -> Option<String> {
+        self.also_known_as
+            .iter()
+            .find_map(|s| s.strip_prefix("at://").map(String::from))
+    }
+    pub fn get_signing_key(&self) -> Option<String> {
+        self.verification_methods.get("atproto").cloned()
+    }
+    pub fn get_pds_endpoint(&self) -> Option<String> {
+        self.services
+            .get("atproto_pds")
+            .filter(|service| service.r#type == "AtprotoPersonalDataServer")
+            .map(|service| service.endpoint.clone())
+    }
+}
+
+/// A point in the identity timeline of a DID.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PlcHistoryEntry {
+    pub cid: String,
+    pub created_at: DateTime<Utc>,
+    /// `None` if the DID was tombstoned by this operation.
+    pub data: Option<DocumentData>,
+}
+
+impl PlcHistoryEntry {
+    pub fn get_handle(&self) -> Option<String> {
+        self.data.as_ref().and_then(DocumentData::get_handle)
+    }
+    pub fn get_signing_key(&self) -> Option<String> {
+        self.data.as_ref().and_then(DocumentData::get_signing_key)
+    }
+    pub fn get_pds_endpoint(&self) -> Option<String> {
+        self.data.as_ref().and_then(DocumentData::get_pds_endpoint)
+    }
+}
+
+/// Verify the chain of operations in the audit log of `did` and return the resulting
+/// timeline.
+///
+/// Nullified entries are skipped; every other operation must hash to the CID of its entry,
+/// reference the previous one and be signed by one of its rotation keys (or its own, for
+/// the genesis operation, which must also hash to `did`).
+pub fn verify_audit_log(did: &str, log: &[AuditLogEntry]) -> Result<Vec<PlcHistoryEntry>> {
+    let mut history = Vec::<PlcHistoryEntry>::new();
+    for entry in log.iter().filter(|entry| !entry.nullified) {
+        let operation = serde_json::from_value::<Operation>(entry.operation.clone())
+            .map_err(Error::SerdeJson)?;
+        let block = dag_cbor::to_vec(&entry.operation)
+            .map_err(|_| Error::UnsupportedValue(entry.operation.clone()))?;
+        if entry.cid.parse::<Cid>().ok() != Some(Cid::compute(DAG_CBOR, &block)) {
+            return Err(Error::InvalidCid(entry.cid.clone()));
+        }
+        let data = operation.document_data();
+        let rotation_keys = match history.last() {
+            None => {
+                if operation.prev().is_some() || genesis_did(&block) != did {
+                    return Err(Error::InvalidGenesis(entry.cid.clone()));
+                }
+                match &data {
+                    Some(data) => data.rotation_keys.clone(),
+                    None => return Err(Error::InvalidGenesis(entry.cid.clone())),
+                }
+            }
+            Some(last) => {
+                if operation.prev() != Some(&last.cid) {
+                    return Err(Error::MisorderedOperation(entry.cid.clone()));
+                }
+                if matches!(operation, Operation::Legacy(_)) {
+                    return Err(Error::MisorderedOperation(entry.cid.clone()));
+                }
+                match &last.data {
+                    Some(data) => data.rotation_keys.clone(),
+                    None => return Err(Error::AfterTombstone(entry.cid.clone())),
+                }
+            }
+        };
+        verify_operation_signature(&entry.operation, operation.sig(), &rotation_keys)
+            .map_err(|_| Error::InvalidSignature(entry.cid.clone()))?;
+        history.push(PlcHistoryEntry {
+            cid: entry.cid.clone(),
+            created_at: DateTime::parse_from_rfc3339(&entry.created_at)
+                .map_err(|_| Error::InvalidCreatedAt(entry.created_at.clone()))?
+                .with_timezone(&Utc),
+            data,
+        });
+    }
+    Ok(history)
+}
+
+/// The `did:plc` created by the signed genesis operation encoded as `block`: the first 24
+/// characters of the base32 sha-256 of the block.
+pub fn genesis_did(block: &[u8]) -> String {
+    let mut id = dag_cbor::base32_encode(&Sha256::digest(block));
+    id.truncate(24);
+    format!("did:plc:{id}")
+}
+
+/// Find the entry of `history` that was in effect at `at`.
+pub fn entry_at(history: &[PlcHistoryEntry], at: DateTime<Utc>) -> Option<&PlcHistoryEntry> {
+    history
+        .iter()
+        .take_while(|entry| entry.created_at <= at)
+        .last()
+}
+
+fn verify_operation_signature(
+    operation: &Value,
+    sig: &str,
+    rotation_keys: &[String],
+) -> Result<()> {
+    let mut unsigned = operation.clone();
+    if let Some(map) = unsigned.as_object_mut() {
+        map.remove("sig");
+    }
+    let msg = dag_cbor::to_vec(&unsigned).map_err(|_| Error::UnsupportedValue(unsigned))?;
+    let sig_bytes = URL_SAFE_NO_PAD
+        .decode(sig.as_bytes())
+        .map_err(Error::Base64Decode)?;
+    if rotation_keys
+        .iter()
+        .any(|key| crypto::verify::verify_signature(key, &msg, &sig_bytes).is_ok())
+    {
+        Ok(())
+    } else {
+        Err(Error::InvalidSignature(sig.into()))
+    }
+}
+
+fn ensure_at_prefix(handle: &str) -> String {
+    if handle.starts_with("at://") {
+        handle.into()
+    } else {
+        format!("at://{handle}")
+    }
+}
+
+fn ensure_http_prefix(service: &str) -> String {
+    if service.starts_with("http://") || service.starts_with("https://") {
+        service.into()
+    } else {
+        format!("https://{service}")
+    }
+}
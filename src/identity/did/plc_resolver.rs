@@ -1,39 +1,75 @@
-use super::did_resolver::{Error, Resolver, Result};
+use super::did_resolver::{self, Error, Resolver, Result};
+use super::plc_operation::{self, AuditLogEntry, PlcHistoryEntry};
 use atrium_api::xrpc::HttpClient;
-use http::Request;
+use std::time::Duration;
 
 pub struct DidPlcResolver<T> {
     client: T,
     plc_url: String,
+    timeout: Option<Duration>,
+    /// The size limit of documents and audit logs.
+    max_size: Option<usize>,
 }
 
 impl<T> DidPlcResolver<T> {
-    pub fn new(client: T, plc_url: impl AsRef<str>) -> Self {
+    pub fn new(
+        client: T,
+        plc_url: impl AsRef<str>,
+        timeout: Option<Duration>,
+        max_size: Option<usize>,
+    ) -> Self {
         Self {
             client,
             plc_url: plc_url.as_ref().into(),
+            timeout,
+            max_size,
         }
     }
 }
 
-impl<T> Resolver for DidPlcResolver<T>
+impl<T> DidPlcResolver<T>
 where
     T: HttpClient,
 {
-    async fn resolve_no_check(&self, did: &str) -> Result<Option<Vec<u8>>> {
-        let response = self
-            .client
-            .send_http(
-                Request::get(format!("{}/{did}", self.plc_url))
-                    .body(Vec::new())
-                    .map_err(Error::Http)?,
+    pub async fn get_audit_log(&self, did: &str) -> Result<Option<Vec<AuditLogEntry>>> {
+        Ok(
+            if let Some(got) = did_resolver::get(
+                &self.client,
+                did,
+                &format!("{}/{did}/log/audit", self.plc_url),
+                self.timeout,
+                self.max_size,
             )
-            .await
-            .map_err(Error::HttpClient)?;
-        Ok(if response.status().is_success() {
-            Some(response.body().to_vec())
+            .await?
+            {
+                Some(serde_json::from_slice(&got).map_err(Error::SerdeJson)?)
+            } else {
+                None
+            },
+        )
+    }
+    /// Fetch the audit log of `did` and verify it, returning the timeline of its identity.
+    pub async fn resolve_history(&self, did: &str) -> Result<Option<Vec<PlcHistoryEntry>>> {
+        Ok(if let Some(log) = self.get_audit_log(did).await? {
+            Some(plc_operation::verify_audit_log(did, &log).map_err(Error::PlcOperation)?)
         } else {
             None
         })
     }
 }
+
+impl<T> Resolver for DidPlcResolver<T>
+where
+    T: HttpClient,
+{
+    async fn resolve_no_check(&self, did: &str) -> Result<Option<Vec<u8>>> {
+        did_resolver::get(
+            &self.client,
+            did,
+            &format!("{}/{did}", self.plc_url),
+            self.timeout,
+            self.max_size,
+        )
+        .await
+    }
+}
@@ -0,0 +1,69 @@
+use super::did_resolver::{self, Error, Resolver, Result};
+use atrium_api::xrpc::HttpClient;
+use std::time::Duration;
+
+const DOC_PATH: &str = "/.well-known/did.json";
+
+pub struct DidWebResolver<T> {
+    client: T,
+    timeout: Option<Duration>,
+    max_size: Option<usize>,
+    allowed_hosts: Option<Vec<String>>,
+}
+
+impl<T> DidWebResolver<T> {
+    /// `allowed_hosts` of `None` allows any host.
+    pub fn new(
+        client: T,
+        timeout: Option<Duration>,
+        max_size: Option<usize>,
+        allowed_hosts: Option<Vec<String>>,
+    ) -> Self {
+        Self {
+            client,
+            timeout,
+            max_size,
+            allowed_hosts,
+        }
+    }
+}
+
+impl<T> Resolver for DidWebResolver<T>
+where
+    T: HttpClient,
+{
+    async fn resolve_no_check(&self, did: &str) -> Result<Option<Vec<u8>>> {
+        let parsed = did
+            .strip_prefix("did:web:")
+            .ok_or_else(|| Error::PoorlyFormattedDid(did.into()))?;
+        let mut parts = parsed.split(':');
+        let host = parts
+            .next()
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| Error::PoorlyFormattedDid(did.into()))?
+            .replace("%3A", ":")
+            .replace("%3a", ":");
+        if parts.next().is_some() {
+            return Err(Error::UnsupportedDidWebPath(did.into()));
+        }
+        if let Some(allowed_hosts) = &self.allowed_hosts {
+            let hostname = host.split(':').next().unwrap_or_default();
+            if !allowed_hosts.iter().any(|allowed| allowed == hostname) {
+                return Err(Error::DidWebHostNotAllowed(did.into()));
+            }
+        }
+        let scheme = if host == "localhost" || host.starts_with("localhost:") {
+            "http"
+        } else {
+            "https"
+        };
+        did_resolver::get(
+            &self.client,
+            did,
+            &format!("{scheme}://{host}{DOC_PATH}"),
+            self.timeout,
+            self.max_size,
+        )
+        .await
+    }
+}
@@ -0,0 +1,116 @@
+//! The queries of the post index, shared by the D1 and SQLite backends so that the
+//! native tests run the statements of the worker against the schema in `migrations/`.
+//!
+//! Parameters are numbered; the comment of each query lists them.
+use crate::post_index::{Flags, IndexedRecord, RecordKind};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+
+/// The migrations in order, each applied once.
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub const MIGRATIONS: [&str; 3] = [
+    include_str!("../migrations/0001_post_index.sql"),
+    include_str!("../migrations/0002_backfill.sql"),
+    include_str!("../migrations/0003_backfill_attempts.sql"),
+];
+
+/// uri, did, kind, created_at, subject, flags, langs
+pub const PUT_RECORD: &str = "INSERT OR REPLACE INTO records \
+     (uri, did, kind, created_at, subject, flags, langs) \
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+
+/// uri
+pub const DELETE_RECORD: &str = "DELETE FROM records WHERE uri = ?1";
+
+/// did
+pub const DELETE_ACCOUNT: &str = "DELETE FROM records WHERE did = ?1";
+
+/// time_us
+pub const PUT_CURSOR: &str = "INSERT INTO jetstream_cursor (id, time_us) VALUES (0, ?1) \
+     ON CONFLICT (id) DO UPDATE SET time_us = excluded.time_us";
+
+pub const CURSOR: &str = "SELECT time_us FROM jetstream_cursor WHERE id = 0";
+
+/// did, created_at, uri, limit, then `kinds` kinds.
+pub fn records(kinds: usize) -> String {
+    let placeholders = (0..kinds)
+        .map(|i| format!("?{}", i + 5))
+        .collect::<Vec<_>>();
+    format!(
+        "SELECT uri, did, kind, created_at, subject, flags, langs FROM records \
+         WHERE did = ?1 AND (created_at < ?2 OR (created_at = ?2 AND uri < ?3)) \
+         AND kind IN ({}) ORDER BY created_at DESC, uri DESC LIMIT ?4",
+        placeholders.join(", ")
+    )
+}
+
+/// did
+pub const TRACK: &str = "INSERT OR IGNORE INTO tracked_dids (did) VALUES (?1)";
+
+pub const TRACKED: &str = "SELECT did FROM tracked_dids";
+
+/// did
+pub const REQUEST_BACKFILL: &str = "INSERT INTO tracked_dids (did, backfill) VALUES (?1, 1) \
+     ON CONFLICT (did) DO UPDATE SET backfill = 1";
+
+/// limit, now, max attempts; never attempted first, then least recently attempted
+pub const PENDING_BACKFILLS: &str = "SELECT did, backfill_attempts AS attempts FROM tracked_dids \
+     WHERE backfill = 1 AND backfill_rev IS NULL AND backfill_attempts < ?3 \
+     AND (backfill_retry_at IS NULL OR backfill_retry_at <= ?2) \
+     ORDER BY backfill_attempted_at IS NOT NULL, backfill_attempted_at LIMIT ?1";
+
+/// did, attempted_at, retry_at
+pub const RECORD_BACKFILL_ATTEMPT: &str = "UPDATE tracked_dids \
+     SET backfill_attempts = backfill_attempts + 1, backfill_attempted_at = ?2, \
+     backfill_retry_at = ?3 WHERE did = ?1";
+
+/// did, rev
+pub const COMPLETE_BACKFILL: &str = "UPDATE tracked_dids SET backfill_rev = ?2 WHERE did = ?1";
+
+/// The `created_at` column of `time`.
+pub fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// A row of the `records` table.
+#[derive(Debug, Deserialize)]
+pub struct Row {
+    pub uri: String,
+    pub did: String,
+    pub kind: String,
+    pub created_at: String,
+    pub subject: Option<String>,
+    pub flags: Option<u32>,
+    pub langs: Option<String>,
+}
+
+impl Row {
+    pub fn record(self) -> Option<IndexedRecord> {
+        let kind = RecordKind::parse(&self.kind)?;
+        let langs = self
+            .langs
+            .iter()
+            .flat_map(|langs| langs.split(','))
+            .filter(|lang| !lang.is_empty())
+            .map(String::from)
+            .collect();
+        Some(IndexedRecord {
+            uri: self.uri,
+            did: self.did,
+            kind,
+            created_at: DateTime::parse_from_rfc3339(&self.created_at)
+                .ok()?
+                .with_timezone(&Utc),
+            subject: self.subject,
+            meta: self.flags.map(|flags| Flags(flags).meta(langs)),
+        })
+    }
+}
+
+/// The `flags` and `langs` columns of `record`.
+pub fn meta_columns(record: &IndexedRecord) -> (Option<u32>, Option<String>) {
+    match &record.meta {
+        Some(meta) => (Some(Flags::from(meta).0), Some(meta.langs.join(","))),
+        None => (None, None),
+    }
+}
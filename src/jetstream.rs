@@ -0,0 +1,149 @@
+//! Reading the Jetstream, the JSON rendition of the firehose, into a `PostIndex`.
+use crate::post_index::{self, Change, IndexedRecord, PostIndex, RecordKind};
+use serde::Deserialize;
+
+/// Events applied to the index at once.
+const BATCH_SIZE: usize = 100;
+
+/// How far before the stored cursor to resume, so that no event is missed. Replayed
+/// events are applied again, which changes nothing.
+const REWIND_US: i64 = 5_000_000;
+
+#[derive(Debug, Deserialize)]
+pub struct Event {
+    pub did: String,
+    pub time_us: i64,
+    pub kind: String,
+    pub commit: Option<Commit>,
+    pub account: Option<Account>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Commit {
+    pub operation: Operation,
+    pub collection: String,
+    pub rkey: String,
+    pub record: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Account {
+    pub active: bool,
+    pub status: Option<String>,
+}
+
+impl Event {
+    /// The change to the index, if the event is about an indexed record or a deleted
+    /// account.
+    pub fn change(&self) -> Option<Change> {
+        match self.kind.as_str() {
+            "commit" => {
+                let commit = self.commit.as_ref()?;
+                RecordKind::from_collection(&commit.collection)?;
+                match commit.operation {
+                    Operation::Delete => Some(Change::Delete {
+                        uri: format!("at://{}/{}/{}", self.did, commit.collection, commit.rkey),
+                    }),
+                    Operation::Create | Operation::Update => IndexedRecord::parse(
+                        &self.did,
+                        &commit.collection,
+                        &commit.rkey,
+                        commit.record.clone()?,
+                    )
+                    .map(Change::Put),
+                }
+            }
+            "account" => {
+                let account = self.account.as_ref()?;
+                (!account.active && account.status.as_deref() == Some("deleted")).then(|| {
+                    Change::DeleteAccount {
+                        did: self.did.clone(),
+                    }
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestStats {
+    pub events: usize,
+    pub changes: usize,
+    /// Lines that are not Jetstream events.
+    pub invalid: usize,
+}
+
+/// Apply the Jetstream events in `lines`, one JSON object each, storing the time of the
+/// last one as the cursor.
+pub async fn ingest<I, S>(
+    index: &I,
+    lines: impl IntoIterator<Item = S>,
+) -> post_index::Result<IngestStats>
+where
+    I: PostIndex,
+    S: AsRef<str>,
+{
+    let mut stats = IngestStats::default();
+    let mut changes = Vec::new();
+    let mut cursor = None;
+    for line in lines {
+        let line = line.as_ref().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Ok(event) = serde_json::from_str::<Event>(line) else {
+            stats.invalid += 1;
+            continue;
+        };
+        stats.events += 1;
+        cursor = cursor.max(Some(event.time_us));
+        changes.extend(event.change());
+        if changes.len() >= BATCH_SIZE {
+            stats.changes += changes.len();
+            index.apply(&changes, cursor).await?;
+            changes.clear();
+        }
+    }
+    if stats.events > 0 {
+        stats.changes += changes.len();
+        index.apply(&changes, cursor).await?;
+    }
+    Ok(stats)
+}
+
+/// The URL subscribing to indexed records at the Jetstream instance at `base`, resuming
+/// shortly before `cursor`. The server waits for `options_update` to send events.
+pub fn subscribe_url(base: &str, cursor: Option<i64>) -> String {
+    let params = RecordKind::ALL
+        .iter()
+        .map(|kind| format!("wantedCollections={}", kind.collection()))
+        .chain(cursor.map(|cursor| format!("cursor={}", (cursor - REWIND_US).max(0))))
+        .chain(Some(String::from("requireHello=true")))
+        .collect::<Vec<_>>();
+    format!(
+        "{}/subscribe?{}",
+        base.trim_end_matches('/'),
+        params.join("&")
+    )
+}
+
+/// The message restricting a subscription to the records of `dids`, which may be too
+/// many for the URL.
+pub fn options_update(dids: &[String]) -> serde_json::Value {
+    serde_json::json!({
+        "type": "options_update",
+        "payload": {
+            "wantedCollections": RecordKind::ALL.map(RecordKind::collection),
+            "wantedDids": dids,
+        },
+    })
+}
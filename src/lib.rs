@@ -1,21 +1,124 @@
-#![cfg(target_arch = "wasm32")]
+/// Log an error that is handled by degrading: to the console in the worker and to stderr
+/// natively, keeping stdout for the output of the CLI.
+macro_rules! log_error {
+    ($($arg:tt)*) => {{
+        #[cfg(target_arch = "wasm32")]
+        worker::console_error!($($arg)*);
+        #[cfg(not(target_arch = "wasm32"))]
+        eprintln!($($arg)*);
+    }};
+}
 
 pub mod auth;
+pub mod backfill;
+pub mod cached_page;
 pub mod common_web;
+pub mod config;
 pub mod crypto;
+pub mod dag_cbor;
+pub mod did_doc;
+pub mod filter;
+pub mod http_client;
 pub mod identity;
+mod index_sql;
+pub mod jetstream;
+pub mod manifest;
+pub mod moderation;
+pub mod post_index;
+pub mod post_status;
+pub mod repo;
+pub mod response_cache;
+pub mod skeleton;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sqlite_index;
 
-mod client;
-mod did_doc;
+#[cfg(target_arch = "wasm32")]
+pub mod client;
+#[cfg(target_arch = "wasm32")]
+mod d1_index;
+#[cfg(target_arch = "wasm32")]
 mod feed;
+#[cfg(target_arch = "wasm32")]
+mod resolver;
+#[cfg(target_arch = "wasm32")]
+mod skeleton_cache;
 
+#[cfg(target_arch = "wasm32")]
 use worker::*;
 
+#[cfg(target_arch = "wasm32")]
+static CONFIG: std::sync::OnceLock<config::Config> = std::sync::OnceLock::new();
+
+#[cfg(target_arch = "wasm32")]
 #[event(fetch)]
 pub async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    let config = match config(&env).await {
+        Ok(config) => config,
+        Err(err) => {
+            console_error!("{err}");
+            return Response::error(format!("Invalid configuration: {err}"), 500);
+        }
+    };
     match req.url()?.path() {
-        "/xrpc/app.bsky.feed.getFeedSkeleton" => crate::feed::feed_skeleton(&req, &env).await,
-        "/.well-known/did.json" => crate::did_doc::did_doc(&env),
+        "/xrpc/app.bsky.feed.getFeedSkeleton" => {
+            crate::feed::feed_skeleton(&req, &env, config).await
+        }
+        "/xrpc/app.bsky.feed.describeFeedGenerator" => crate::feed::describe_feed_generator(config),
+        "/.well-known/did.json" => crate::did_doc::did_doc(config),
         _ => Response::error("Not Found", 404),
     }
 }
+
+#[cfg(target_arch = "wasm32")]
+#[event(scheduled)]
+pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    let config = match config(&env).await {
+        Ok(config) => config,
+        Err(err) => {
+            console_error!("Invalid configuration: {err}");
+            return;
+        }
+    };
+    if let Err(err) = crate::d1_index::ingest(&env, config).await {
+        console_error!("failed to ingest the jetstream: {err}");
+    }
+    if let Err(err) = crate::d1_index::backfill(&env, config).await {
+        console_error!("failed to backfill: {err}");
+    }
+    if let Err(err) = crate::skeleton_cache::precompute(&env, config).await {
+        console_error!("failed to precompute skeletons: {err}");
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn config(env: &Env) -> std::result::Result<&'static config::Config, String> {
+    match CONFIG.get() {
+        Some(config) => Ok(config),
+        None => {
+            let config = load_config(env).await?;
+            Ok(CONFIG.get_or_init(|| config))
+        }
+    }
+}
+
+/// Load the configuration, replacing the manifest with the one in KV if configured.
+#[cfg(target_arch = "wasm32")]
+async fn load_config(env: &Env) -> std::result::Result<config::Config, String> {
+    let mut config = config::Config::load(env).map_err(|err| err.to_string())?;
+    if let Some(binding) = &config.manifest_kv {
+        let text = env
+            .kv(binding)
+            .map_err(|err| err.to_string())?
+            .get("manifest")
+            .text()
+            .await
+            .map_err(|err| err.to_string())?
+            .ok_or_else(|| format!("no manifest in KV namespace {binding}"))?;
+        let manifest = manifest::Manifest::parse(&text)
+            .map_err(|err| format!("invalid config FEEDS_MANIFEST_KV: {err}"))?;
+        config = config
+            .with_manifest(manifest, "FEEDS_MANIFEST_KV")
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(config)
+}
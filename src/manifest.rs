@@ -0,0 +1,241 @@
+//! Declarative definitions of the feeds served by the generator.
+//!
+//! The manifest is written in TOML or JSON and is the single source for routing
+//! `getFeedSkeleton`, answering `describeFeedGenerator` and publishing the
+//! `app.bsky.feed.generator` records.
+use crate::moderation;
+use chrono::{DateTime, Months, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// The manifest compiled into the worker, used when no other manifest is configured.
+pub const EMBEDDED: &str = include_str!("../feeds.toml");
+
+#[derive(Debug)]
+pub enum Error {
+    Toml(toml::de::Error),
+    SerdeJson(serde_json::Error),
+    Invalid(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Toml(err) => write!(f, "toml error: {err}"),
+            Error::SerdeJson(err) => write!(f, "serde_json error: {err}"),
+            Error::Invalid(reason) => write!(f, "invalid feed manifest: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::Toml(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::SerdeJson(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    /// The account that publishes the feed generator records, which must not be the service
+    /// DID since that has no repository. Without it no feeds are served.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privacy_policy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terms_of_service: Option<String>,
+    pub feeds: Vec<FeedDefinition>,
+}
+
+impl Manifest {
+    /// Parse a manifest, as JSON if it is an object and as TOML otherwise.
+    pub fn parse(text: &str) -> Result<Self> {
+        let manifest: Self = if text.trim_start().starts_with('{') {
+            serde_json::from_str(text)?
+        } else {
+            toml::from_str(text)?
+        };
+        manifest.validate()?;
+        Ok(manifest)
+    }
+    pub fn embedded() -> Result<Self> {
+        Self::parse(EMBEDDED)
+    }
+    fn validate(&self) -> Result<()> {
+        if self.feeds.is_empty() {
+            return Err(Error::Invalid(String::from("no feeds defined")));
+        }
+        if let Some(publisher) = self.publisher.as_deref().filter(|p| !p.starts_with("did:")) {
+            return Err(Error::Invalid(format!(
+                "publisher is not a DID: {publisher}"
+            )));
+        }
+        let mut rkeys = HashSet::new();
+        for feed in &self.feeds {
+            if !is_valid_rkey(&feed.rkey) {
+                return Err(Error::Invalid(format!(
+                    "invalid record key: {:?}",
+                    feed.rkey
+                )));
+            }
+            if !rkeys.insert(feed.rkey.as_str()) {
+                return Err(Error::Invalid(format!(
+                    "duplicate record key: {}",
+                    feed.rkey
+                )));
+            }
+            if feed.display_name.is_empty() {
+                return Err(Error::Invalid(format!("{}: empty displayName", feed.rkey)));
+            }
+            if feed.backfill && !feed.index {
+                return Err(Error::Invalid(format!(
+                    "{}: backfill requires index",
+                    feed.rkey
+                )));
+            }
+        }
+        Ok(())
+    }
+    pub fn feed(&self, rkey: &str) -> Option<&FeedDefinition> {
+        self.feeds.iter().find(|feed| feed.rkey == rkey)
+    }
+}
+
+/// A feed, published as `at://<publisher>/app.bsky.feed.generator/<rkey>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedDefinition {
+    pub rkey: String,
+    pub display_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Path of the avatar image uploaded by the publishing CLI.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    pub rule: TimeRule,
+    #[serde(default)]
+    pub source: Source,
+    /// Interleave the viewer's reposts from the same time.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_reposts: bool,
+    /// Read the viewer's records from the post index rather than the AppView and PDS.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub index: bool,
+    /// Backfill the index with the viewer's records from before they first loaded the
+    /// feed, read from their repository.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub backfill: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<Filter>,
+    /// Labels on a post or its author that keep the post out of the feed.
+    #[serde(default = "moderation::default_labels")]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub auth: AuthRequirement,
+    /// Seconds that a precomputed first page may be served for. Unset disables
+    /// precomputation of the feed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+}
+
+impl FeedDefinition {
+    pub fn uri(&self, publisher: &str) -> String {
+        format!("at://{publisher}/app.bsky.feed.generator/{}", self.rkey)
+    }
+}
+
+/// The point in time that a feed shows posts from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TimeRule {
+    /// A fixed distance before now.
+    Offset {
+        #[serde(default)]
+        years: u32,
+        #[serde(default)]
+        months: u32,
+        #[serde(default)]
+        days: u32,
+    },
+    /// A fixed date.
+    Date { date: DateTime<Utc> },
+}
+
+impl TimeRule {
+    /// The latest creation time of posts in the feed.
+    pub fn until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            TimeRule::Offset {
+                years,
+                months,
+                days,
+            } => now
+                .checked_sub_months(Months::new(years.checked_mul(12)?.checked_add(*months)?))?
+                .checked_sub_signed(chrono::Duration::try_days((*days).into())?),
+            TimeRule::Date { date } => Some(*date),
+        }
+    }
+}
+
+/// Where the posts of a feed come from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Source {
+    /// `app.bsky.feed.searchPosts` on the AppView, by the viewer.
+    #[default]
+    SearchPosts,
+    /// `app.bsky.feed.like` records in the viewer's repo, on its PDS.
+    Likes,
+}
+
+/// A condition that posts must meet to appear in a feed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Filter {
+    /// Drop replies.
+    NoReplies,
+    /// Keep only posts with (or, if `exclude`, without) images or video.
+    Media {
+        #[serde(default)]
+        exclude: bool,
+    },
+    /// Keep only posts with (or, if `exclude`, without) links.
+    Links {
+        #[serde(default)]
+        exclude: bool,
+    },
+    /// Keep only posts in one of `langs`.
+    Lang { langs: Vec<String> },
+}
+
+/// Whether a feed is served to viewers without a service JWT.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuthRequirement {
+    /// Anonymous viewers get an empty feed.
+    #[default]
+    Optional,
+    /// Anonymous viewers get an `AuthenticationRequired` error.
+    Required,
+}
+
+/// Record keys of the `any` format: 1-512 characters of `A-Za-z0-9.-_:~`, not `.` or `..`.
+fn is_valid_rkey(rkey: &str) -> bool {
+    (1..=512).contains(&rkey.len())
+        && rkey != "."
+        && rkey != ".."
+        && rkey
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-_:~".contains(c))
+}
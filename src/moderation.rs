@@ -0,0 +1,147 @@
+//! Labels, takedowns and blocks that keep posts out of a feed, read from hydrated posts.
+//!
+//! Posts are looked up without the viewer's credentials, so the AppView does not say
+//! whether their authors block or are blocked by the viewer. `Moderation` holds what is
+//! the same for every viewer, and blocks are checked against the viewer's
+//! `app.bsky.graph.getRelationships`.
+use atrium_api::app::bsky::embed::record::{View as RecordView, ViewRecordRefs};
+use atrium_api::app::bsky::feed::defs::{PostView, PostViewEmbedRefs};
+use atrium_api::com::atproto::label::defs::Label;
+use atrium_api::records::{KnownRecord, Record};
+use atrium_api::types::Union;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+/// The labels that hide posts when a feed does not configure its own.
+pub const DEFAULT_LABELS: [&str; 7] = [
+    "!hide",
+    "!takedown",
+    "!suspend",
+    "porn",
+    "sexual",
+    "nudity",
+    "graphic-media",
+];
+
+pub fn default_labels() -> Vec<String> {
+    DEFAULT_LABELS.map(String::from).to_vec()
+}
+
+/// What moderation needs to know about a post, whoever the viewer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Moderation {
+    /// The labels on the post and its author, without negated ones.
+    pub labels: Vec<String>,
+    /// The accounts whose blocks with the viewer hide the post: its author, the author of
+    /// a quoted post and those of the parent and root of a reply, sorted.
+    pub authors: Vec<String>,
+}
+
+impl From<&PostView> for Moderation {
+    fn from(post: &PostView) -> Self {
+        let labels = post
+            .labels
+            .iter()
+            .chain(&post.author.labels)
+            .flatten()
+            .filter(|label| !label.neg.unwrap_or(false))
+            .map(|label: &Label| label.val.clone())
+            .collect();
+        let quoted = match &post.embed {
+            Some(Union::Refs(PostViewEmbedRefs::AppBskyEmbedRecordView(view))) => Some(&**view),
+            Some(Union::Refs(PostViewEmbedRefs::AppBskyEmbedRecordWithMediaView(view))) => {
+                Some(&view.record)
+            }
+            _ => None,
+        };
+        let mut authors = thread(post)
+            .iter()
+            .filter_map(|uri| uri_did(uri))
+            .chain(quoted.and_then(quoted_author))
+            .map(String::from)
+            .chain([post.author.did.to_string()])
+            .collect::<Vec<_>>();
+        authors.sort();
+        authors.dedup();
+        Self { labels, authors }
+    }
+}
+
+impl Moderation {
+    /// Whether the post is to be left out of a feed hiding `labels` for a viewer with
+    /// `blocks`.
+    pub fn hides(&self, labels: &[String], blocks: &Blocks) -> bool {
+        self.authors.iter().any(|did| blocks.contains(did))
+            || self.labels.iter().any(|label| labels.contains(label))
+    }
+}
+
+/// The accounts that block or are blocked by a viewer, directly or through a list.
+pub type Blocks = HashSet<String>;
+
+/// An item of the output of `app.bsky.graph.getRelationships`, with the block fields that
+/// the generated `app.bsky.graph.defs#relationship` lacks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "$type")]
+pub enum Relationship {
+    #[serde(rename = "app.bsky.graph.defs#relationship", rename_all = "camelCase")]
+    Relationship {
+        did: String,
+        blocking: Option<String>,
+        blocked_by: Option<String>,
+        blocking_by_list: Option<String>,
+        blocked_by_list: Option<String>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl Relationship {
+    /// The DID of the other account, if the viewer blocks it or is blocked by it.
+    pub fn blocked(&self) -> Option<&str> {
+        match self {
+            Relationship::Relationship {
+                did,
+                blocking,
+                blocked_by,
+                blocking_by_list,
+                blocked_by_list,
+            } => [blocking, blocked_by, blocking_by_list, blocked_by_list]
+                .iter()
+                .any(|block| block.is_some())
+                .then_some(did.as_str()),
+            Relationship::Other => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Relationships {
+    pub relationships: Vec<Relationship>,
+}
+
+/// The parent and root of a reply, whose authors may block or be blocked by the viewer.
+pub fn thread(post: &PostView) -> Vec<String> {
+    match &post.record {
+        Record::Known(KnownRecord::AppBskyFeedPost(record)) => record
+            .reply
+            .iter()
+            .flat_map(|reply| [reply.parent.uri.clone(), reply.root.uri.clone()])
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The DID of the repository of an `at://` URI.
+fn uri_did(uri: &str) -> Option<&str> {
+    uri.strip_prefix("at://")?.split('/').next()
+}
+
+fn quoted_author(view: &RecordView) -> Option<&str> {
+    match &view.record {
+        Union::Refs(ViewRecordRefs::ViewRecord(record)) => Some(record.author.did.as_str()),
+        Union::Refs(ViewRecordRefs::ViewBlocked(blocked)) => Some(blocked.author.did.as_str()),
+        Union::Refs(ViewRecordRefs::ViewNotFound(not_found)) => uri_did(&not_found.uri),
+        _ => None,
+    }
+}
@@ -0,0 +1,355 @@
+//! An index of the post, repost and like records of tracked viewers, fed by the
+//! Jetstream (see `jetstream`) so that feeds do not depend on `searchPosts`.
+//!
+//! The worker keeps it in D1 with the schema in `migrations/`; `SqliteIndex` runs the same
+//! schema and queries natively, and `MemoryIndex` keeps it in memory.
+use crate::filter::PostMeta;
+use crate::skeleton::TimeCursor;
+use async_trait::async_trait;
+use atrium_api::app::bsky::feed::{like, post, repost};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum Error {
+    Backend(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Backend(err) => write!(f, "post index error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RecordKind {
+    Post,
+    Repost,
+    Like,
+}
+
+impl RecordKind {
+    pub const ALL: [RecordKind; 3] = [RecordKind::Post, RecordKind::Repost, RecordKind::Like];
+
+    pub fn collection(self) -> &'static str {
+        match self {
+            RecordKind::Post => "app.bsky.feed.post",
+            RecordKind::Repost => "app.bsky.feed.repost",
+            RecordKind::Like => "app.bsky.feed.like",
+        }
+    }
+    pub fn from_collection(collection: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.collection() == collection)
+    }
+    /// The name stored in the `kind` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RecordKind::Post => "post",
+            RecordKind::Repost => "repost",
+            RecordKind::Like => "like",
+        }
+    }
+    pub fn parse(kind: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == kind)
+    }
+}
+
+/// The `flags` column, a bit set of what the filters need to know about a post.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags(pub u32);
+
+impl Flags {
+    pub const REPLY: u32 = 1;
+    pub const MEDIA: u32 = 1 << 1;
+    pub const LINKS: u32 = 1 << 2;
+
+    pub fn meta(self, langs: Vec<String>) -> PostMeta {
+        PostMeta {
+            is_reply: self.0 & Self::REPLY != 0,
+            has_media: self.0 & Self::MEDIA != 0,
+            has_links: self.0 & Self::LINKS != 0,
+            langs,
+        }
+    }
+}
+
+impl From<&PostMeta> for Flags {
+    fn from(meta: &PostMeta) -> Self {
+        let mut flags = 0;
+        if meta.is_reply {
+            flags |= Self::REPLY;
+        }
+        if meta.has_media {
+            flags |= Self::MEDIA;
+        }
+        if meta.has_links {
+            flags |= Self::LINKS;
+        }
+        Self(flags)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedRecord {
+    pub uri: String,
+    pub did: String,
+    pub kind: RecordKind,
+    pub created_at: DateTime<Utc>,
+    /// The post that a repost or like refers to.
+    pub subject: Option<String>,
+    /// The metadata of a post.
+    pub meta: Option<PostMeta>,
+}
+
+impl IndexedRecord {
+    /// The record at `at://<did>/<collection>/<rkey>` from its JSON, if it is of an
+    /// indexed kind.
+    pub fn parse(
+        did: &str,
+        collection: &str,
+        rkey: &str,
+        value: serde_json::Value,
+    ) -> Option<Self> {
+        let kind = RecordKind::from_collection(collection)?;
+        let (created_at, subject, meta) = match kind {
+            RecordKind::Post => {
+                let post = serde_json::from_value::<post::Record>(value).ok()?;
+                (post.created_at.clone(), None, Some(PostMeta::from(&post)))
+            }
+            RecordKind::Repost => {
+                let repost = serde_json::from_value::<repost::Record>(value).ok()?;
+                (
+                    repost.created_at.clone(),
+                    Some(repost.subject.uri.clone()),
+                    None,
+                )
+            }
+            RecordKind::Like => {
+                let like = serde_json::from_value::<like::Record>(value).ok()?;
+                (
+                    like.created_at.clone(),
+                    Some(like.subject.uri.clone()),
+                    None,
+                )
+            }
+        };
+        Some(Self {
+            uri: format!("at://{did}/{collection}/{rkey}"),
+            did: did.into(),
+            kind,
+            created_at: created_at.as_ref().with_timezone(&Utc),
+            subject,
+            meta,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Put(IndexedRecord),
+    Delete {
+        uri: String,
+    },
+    /// The account was deleted, along with its records.
+    DeleteAccount {
+        did: String,
+    },
+}
+
+/// Backfills are given up after this many attempts.
+pub const MAX_BACKFILL_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingBackfill {
+    pub did: String,
+    /// The attempts made so far.
+    pub attempts: u32,
+}
+
+/// A store of `IndexedRecord`s.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait PostIndex {
+    /// Apply `changes` in order, then store `cursor`, the Jetstream time they were read up to.
+    async fn apply(&self, changes: &[Change], cursor: Option<i64>) -> Result<()>;
+    /// The cursor stored by the last `apply`.
+    async fn cursor(&self) -> Result<Option<i64>>;
+    /// Up to `limit` records of `did` of the given kinds that come after `position`,
+    /// newest first and by URI between records created at the same time.
+    async fn records(
+        &self,
+        did: &str,
+        kinds: &[RecordKind],
+        position: &TimeCursor,
+        limit: usize,
+    ) -> Result<Vec<IndexedRecord>>;
+    /// Start indexing the records of `did`.
+    async fn track(&self, did: &str) -> Result<()>;
+    /// The DIDs whose records are indexed.
+    async fn tracked(&self) -> Result<Vec<String>>;
+    /// Start indexing the records of `did`, and backfill those it made before.
+    async fn request_backfill(&self, did: &str) -> Result<()>;
+    /// Up to `limit` backfills that were requested but not completed, are due at `now` and
+    /// were attempted fewer than `MAX_BACKFILL_ATTEMPTS` times, the ones never attempted
+    /// first and then the least recently attempted.
+    async fn pending_backfills(
+        &self,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PendingBackfill>>;
+    /// Record that a backfill of `did` started at `at`, to be retried from `retry_at` if it
+    /// does not complete.
+    async fn record_backfill_attempt(
+        &self,
+        did: &str,
+        at: DateTime<Utc>,
+        retry_at: DateTime<Utc>,
+    ) -> Result<()>;
+    /// Record that the repository of `did` was backfilled up to revision `rev`.
+    async fn complete_backfill(&self, did: &str, rev: &str) -> Result<()>;
+}
+
+/// An in-memory `PostIndex`.
+#[derive(Debug, Default)]
+pub struct MemoryIndex {
+    inner: Mutex<Memory>,
+}
+
+#[derive(Debug, Default)]
+struct Memory {
+    records: HashMap<String, IndexedRecord>,
+    tracked: BTreeSet<String>,
+    backfills: BTreeMap<String, Backfill>,
+    cursor: Option<i64>,
+}
+
+/// A requested backfill.
+#[derive(Debug, Default)]
+struct Backfill {
+    /// The revision it completed at.
+    rev: Option<String>,
+    attempts: u32,
+    attempted_at: Option<DateTime<Utc>>,
+    retry_at: Option<DateTime<Utc>>,
+}
+
+impl MemoryIndex {
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Memory>> {
+        self.inner.lock().map_err(|e| Error::Backend(e.to_string()))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl PostIndex for MemoryIndex {
+    async fn apply(&self, changes: &[Change], cursor: Option<i64>) -> Result<()> {
+        let mut memory = self.lock()?;
+        for change in changes {
+            match change {
+                Change::Put(record) => {
+                    memory.records.insert(record.uri.clone(), record.clone());
+                }
+                Change::Delete { uri } => {
+                    memory.records.remove(uri);
+                }
+                Change::DeleteAccount { did } => {
+                    memory.records.retain(|_, record| record.did != *did);
+                }
+            }
+        }
+        if cursor.is_some() {
+            memory.cursor = cursor;
+        }
+        Ok(())
+    }
+    async fn cursor(&self) -> Result<Option<i64>> {
+        Ok(self.lock()?.cursor)
+    }
+    async fn records(
+        &self,
+        did: &str,
+        kinds: &[RecordKind],
+        position: &TimeCursor,
+        limit: usize,
+    ) -> Result<Vec<IndexedRecord>> {
+        let memory = self.lock()?;
+        let mut records = memory
+            .records
+            .values()
+            .filter(|record| {
+                record.did == did
+                    && kinds.contains(&record.kind)
+                    && position.is_after(record.created_at, &record.uri)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        records.sort_by(|a, b| (b.created_at, &b.uri).cmp(&(a.created_at, &a.uri)));
+        records.truncate(limit);
+        Ok(records)
+    }
+    async fn track(&self, did: &str) -> Result<()> {
+        self.lock()?.tracked.insert(did.into());
+        Ok(())
+    }
+    async fn tracked(&self) -> Result<Vec<String>> {
+        Ok(self.lock()?.tracked.iter().cloned().collect())
+    }
+    async fn request_backfill(&self, did: &str) -> Result<()> {
+        let mut memory = self.lock()?;
+        memory.tracked.insert(did.into());
+        memory.backfills.entry(did.into()).or_default();
+        Ok(())
+    }
+    async fn pending_backfills(
+        &self,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PendingBackfill>> {
+        let memory = self.lock()?;
+        let mut pending = memory
+            .backfills
+            .iter()
+            .filter(|(_, backfill)| {
+                backfill.rev.is_none()
+                    && backfill.attempts < MAX_BACKFILL_ATTEMPTS
+                    && backfill.retry_at.is_none_or(|retry_at| retry_at <= now)
+            })
+            .collect::<Vec<_>>();
+        // `None` sorts first
+        pending.sort_by_key(|(_, backfill)| backfill.attempted_at);
+        Ok(pending
+            .into_iter()
+            .take(limit)
+            .map(|(did, backfill)| PendingBackfill {
+                did: did.clone(),
+                attempts: backfill.attempts,
+            })
+            .collect())
+    }
+    async fn record_backfill_attempt(
+        &self,
+        did: &str,
+        at: DateTime<Utc>,
+        retry_at: DateTime<Utc>,
+    ) -> Result<()> {
+        if let Some(backfill) = self.lock()?.backfills.get_mut(did) {
+            backfill.attempts += 1;
+            backfill.attempted_at = Some(at);
+            backfill.retry_at = Some(retry_at);
+        }
+        Ok(())
+    }
+    async fn complete_backfill(&self, did: &str, rev: &str) -> Result<()> {
+        self.lock()?.backfills.entry(did.into()).or_default().rev = Some(rev.into());
+        Ok(())
+    }
+}
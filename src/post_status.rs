@@ -0,0 +1,64 @@
+//! Whether posts still exist, as last seen by `getPosts`, so that the posts of a page
+//! are not looked up again on every request.
+use crate::moderation::Moderation;
+use crate::response_cache::{self, MemoryCache};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Error {
+    Backend(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Backend(err) => write!(f, "post status cache error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PostStatus {
+    Live(Moderation),
+    /// Deleted, taken down or otherwise not returned by the AppView.
+    Gone,
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait PostStatusCache {
+    /// The statuses of those of `uris` that are cached.
+    async fn get(&self, uris: &[String]) -> Result<HashMap<String, PostStatus>>;
+    async fn put(&self, uri: &str, status: PostStatus, ttl: Duration) -> Result<()>;
+}
+
+/// An in-memory `PostStatusCache` holding up to `capacity` posts, evicting expired and
+/// then least recently used ones.
+pub type MemoryStatusCache = MemoryCache<PostStatus>;
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl PostStatusCache for MemoryStatusCache {
+    async fn get(&self, uris: &[String]) -> Result<HashMap<String, PostStatus>> {
+        let mut statuses = HashMap::new();
+        for uri in uris {
+            if let Some(status) = self.get_with(uri, PostStatus::clone).map_err(backend)? {
+                statuses.insert(uri.clone(), status);
+            }
+        }
+        Ok(statuses)
+    }
+    async fn put(&self, uri: &str, status: PostStatus, ttl: Duration) -> Result<()> {
+        self.insert(uri, status, ttl).map_err(backend)
+    }
+}
+
+fn backend(err: response_cache::Error) -> Error {
+    Error::Backend(err.to_string())
+}
@@ -0,0 +1,222 @@
+//! Reading a repository from its CAR export (`com.atproto.sync.getRepo`): the signed
+//! commit, the Merkle Search Tree under it and the records it points to.
+use crate::crypto;
+use crate::dag_cbor::{self, Cid, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// How deep the MST may be.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub enum Error {
+    Car(String),
+    DagCbor(dag_cbor::Error),
+    MissingBlock(Cid),
+    InvalidCommit(String),
+    InvalidMst(String),
+    InvalidSignature(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Car(reason) => write!(f, "invalid CAR file: {reason}"),
+            Error::DagCbor(err) => write!(f, "{err}"),
+            Error::MissingBlock(cid) => write!(f, "block not found: {cid}"),
+            Error::InvalidCommit(reason) => write!(f, "invalid commit: {reason}"),
+            Error::InvalidMst(reason) => write!(f, "invalid MST: {reason}"),
+            Error::InvalidSignature(did) => write!(f, "invalid commit signature for {did}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A record of the repository, keyed by `<collection>/<rkey>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub cid: Cid,
+}
+
+impl Entry {
+    pub fn collection(&self) -> &str {
+        self.key
+            .split_once('/')
+            .map_or(&self.key, |(collection, _)| collection)
+    }
+    pub fn rkey(&self) -> &str {
+        self.key.split_once('/').map_or("", |(_, rkey)| rkey)
+    }
+}
+
+/// A repository read from a CAR file. Blocks are checked against their CIDs as they are
+/// read; the commit is to be trusted only after `verify_signature`.
+#[derive(Debug)]
+pub struct Repo {
+    pub did: String,
+    pub rev: String,
+    data: Cid,
+    commit: BTreeMap<String, Value>,
+    blocks: HashMap<Cid, Vec<u8>>,
+}
+
+impl Repo {
+    pub fn from_car(car: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(car);
+        let header_len = reader.varint()?;
+        let header = dag_cbor::decode(reader.take(header_len)?).map_err(Error::DagCbor)?;
+        let root = match header.get("roots") {
+            Some(Value::Array(roots)) => match roots.as_slice() {
+                [Value::Link(root)] => root.clone(),
+                _ => return Err(Error::Car(String::from("expected a single root"))),
+            },
+            _ => return Err(Error::Car(String::from("no roots in header"))),
+        };
+        if !matches!(header.get("version"), Some(Value::Integer(1))) {
+            return Err(Error::Car(String::from("unsupported version")));
+        }
+        let mut blocks = HashMap::new();
+        while reader.pos < car.len() {
+            let len = reader.varint()?;
+            let section = reader.take(len)?;
+            let (cid, cid_len) = Cid::read(section).map_err(Error::DagCbor)?;
+            let block = &section[cid_len..];
+            cid.verify(block).map_err(Error::DagCbor)?;
+            blocks.insert(cid, block.to_vec());
+        }
+        let block = blocks.get(&root).ok_or(Error::MissingBlock(root))?;
+        let Value::Map(commit) = dag_cbor::decode(block).map_err(Error::DagCbor)? else {
+            return Err(Error::InvalidCommit(String::from("not a map")));
+        };
+        let (Some(Value::String(did)), Some(Value::String(rev)), Some(Value::Link(data))) =
+            (commit.get("did"), commit.get("rev"), commit.get("data"))
+        else {
+            return Err(Error::InvalidCommit(String::from(
+                "missing did, rev or data",
+            )));
+        };
+        if !matches!(commit.get("version"), Some(Value::Integer(2 | 3))) {
+            return Err(Error::InvalidCommit(String::from("unsupported version")));
+        }
+        Ok(Self {
+            did: did.clone(),
+            rev: rev.clone(),
+            data: data.clone(),
+            commit,
+            blocks,
+        })
+    }
+    /// Verify that the commit is signed by `did_key`, the atproto signing key of the DID.
+    pub fn verify_signature(&self, did_key: &str) -> Result<()> {
+        let Some(Value::Bytes(sig)) = self.commit.get("sig") else {
+            return Err(Error::InvalidCommit(String::from("unsigned")));
+        };
+        let mut unsigned = self.commit.clone();
+        unsigned.remove("sig");
+        let msg = dag_cbor::encode(&Value::Map(unsigned));
+        crypto::verify::verify_signature(did_key, &msg, sig)
+            .map_err(|_| Error::InvalidSignature(self.did.clone()))
+    }
+    /// The records of the repository, in key order.
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        self.walk(&self.data, 0, &mut entries)?;
+        Ok(entries)
+    }
+    /// The record with `cid`, as JSON in the atproto data model (`$link`, `$bytes`).
+    pub fn record(&self, cid: &Cid) -> Result<serde_json::Value> {
+        Ok(dag_cbor::decode(self.block(cid)?)
+            .map_err(Error::DagCbor)?
+            .into())
+    }
+    fn block(&self, cid: &Cid) -> Result<&[u8]> {
+        self.blocks
+            .get(cid)
+            .map(Vec::as_slice)
+            .ok_or_else(|| Error::MissingBlock(cid.clone()))
+    }
+    /// Append the entries of the MST node `cid` to `entries`, in order.
+    fn walk(&self, cid: &Cid, depth: usize, entries: &mut Vec<Entry>) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(Error::InvalidMst(String::from("too deep")));
+        }
+        let invalid = |reason: &str| Error::InvalidMst(format!("{reason} in node {cid}"));
+        let node = dag_cbor::decode(self.block(cid)?).map_err(Error::DagCbor)?;
+        match node.get("l") {
+            Some(Value::Link(left)) => self.walk(left, depth + 1, entries)?,
+            Some(Value::Null) => {}
+            _ => return Err(invalid("no left subtree")),
+        }
+        let Some(Value::Array(node_entries)) = node.get("e") else {
+            return Err(invalid("no entries"));
+        };
+        let mut key = Vec::<u8>::new();
+        for entry in node_entries {
+            let (
+                Some(Value::Integer(prefix)),
+                Some(Value::Bytes(suffix)),
+                Some(Value::Link(value)),
+            ) = (entry.get("p"), entry.get("k"), entry.get("v"))
+            else {
+                return Err(invalid("malformed entry"));
+            };
+            let prefix = usize::try_from(*prefix)
+                .ok()
+                .filter(|prefix| *prefix <= key.len())
+                .ok_or_else(|| invalid("invalid key prefix"))?;
+            key.truncate(prefix);
+            key.extend_from_slice(suffix);
+            let key = String::from_utf8(key.clone()).map_err(|_| invalid("non-UTF-8 key"))?;
+            if entries.last().is_some_and(|last| last.key >= key) {
+                return Err(invalid("keys out of order"));
+            }
+            entries.push(Entry {
+                key,
+                cid: value.clone(),
+            });
+            match entry.get("t") {
+                Some(Value::Link(right)) => self.walk(right, depth + 1, entries)?,
+                Some(Value::Null) => {}
+                _ => return Err(invalid("no right subtree")),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the framing of a CAR file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| Error::Car(String::from("unexpected end of data")))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+    /// An unsigned LEB128 varint.
+    fn varint(&mut self) -> Result<usize> {
+        let mut value = 0usize;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= usize::from(byte & 0x7f).checked_shl(shift).unwrap_or(0);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::Car(String::from("varint too long")))
+    }
+}
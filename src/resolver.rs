@@ -0,0 +1,106 @@
+use crate::client::{retrying_fetch_http_client, RetryingFetchHttpClient};
+use crate::common_web::did_doc::DidDocument;
+use crate::config::Config;
+use crate::identity::did::did_cache::DidCache;
+use crate::identity::did::did_resolver::{self, DidResolver, DidResolverOptions};
+use crate::identity::did::plc_operation::PlcHistoryEntry;
+use worker::kv::KvStore;
+use worker::{Env, Result};
+
+/// The cache backend selected by `ResolverConfig::cache`.
+pub enum DidCacheBackend {
+    None,
+    Kv { store: KvStore, ttl: u64 },
+}
+
+impl DidCache for DidCacheBackend {
+    async fn get(&self, did: &str) -> did_resolver::Result<Option<DidDocument>> {
+        match self {
+            DidCacheBackend::None => Ok(None),
+            DidCacheBackend::Kv { store, .. } => store
+                .get(did)
+                .json()
+                .await
+                .map_err(|e| did_resolver::Error::Cache(e.to_string())),
+        }
+    }
+    async fn set(&self, did: &str, did_doc: &DidDocument) -> did_resolver::Result<()> {
+        match self {
+            DidCacheBackend::None => Ok(()),
+            DidCacheBackend::Kv { store, ttl } => store
+                .put(did, did_doc)
+                .map(|builder| builder.expiration_ttl(*ttl))
+                .map_err(|e| did_resolver::Error::Cache(e.to_string()))?
+                .execute()
+                .await
+                .map_err(|e| did_resolver::Error::Cache(e.to_string())),
+        }
+    }
+    async fn clear(&self, did: &str) -> did_resolver::Result<()> {
+        match self {
+            DidCacheBackend::None => Ok(()),
+            DidCacheBackend::Kv { store, .. } => {
+                for key in [did.to_string(), history_key(did)] {
+                    store
+                        .delete(&key)
+                        .await
+                        .map_err(|e| did_resolver::Error::Cache(e.to_string()))?;
+                }
+                Ok(())
+            }
+        }
+    }
+    async fn get_history(&self, did: &str) -> did_resolver::Result<Option<Vec<PlcHistoryEntry>>> {
+        match self {
+            DidCacheBackend::None => Ok(None),
+            DidCacheBackend::Kv { store, .. } => store
+                .get(&history_key(did))
+                .json()
+                .await
+                .map_err(|e| did_resolver::Error::Cache(e.to_string())),
+        }
+    }
+    async fn set_history(
+        &self,
+        did: &str,
+        history: &[PlcHistoryEntry],
+    ) -> did_resolver::Result<()> {
+        match self {
+            DidCacheBackend::None => Ok(()),
+            DidCacheBackend::Kv { store, ttl } => store
+                .put(&history_key(did), history)
+                .map(|builder| builder.expiration_ttl(*ttl))
+                .map_err(|e| did_resolver::Error::Cache(e.to_string()))?
+                .execute()
+                .await
+                .map_err(|e| did_resolver::Error::Cache(e.to_string())),
+        }
+    }
+}
+
+/// The KV key of the history of `did`, next to its document under `did`.
+fn history_key(did: &str) -> String {
+    format!("history:{did}")
+}
+
+pub fn did_resolver(
+    env: &Env,
+    config: &Config,
+) -> Result<DidResolver<RetryingFetchHttpClient, DidCacheBackend>> {
+    Ok(DidResolver::with_options(
+        retrying_fetch_http_client(config.upstream.retry.clone()),
+        DidResolverOptions {
+            plc_url: config.upstream.plc_url.clone(),
+            web_allowed_hosts: config.resolver.web_allowed_hosts.clone(),
+            timeout: config.resolver.timeout,
+            max_document_size: config.resolver.max_document_size,
+            cache: match &config.resolver.cache {
+                Some(cache) => DidCacheBackend::Kv {
+                    store: env.kv(&cache.binding)?,
+                    ttl: cache.ttl,
+                },
+                None => DidCacheBackend::None,
+            },
+        },
+    ))
+}
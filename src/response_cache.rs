@@ -0,0 +1,368 @@
+//! An HTTP-level cache of XRPC query responses.
+use async_trait::async_trait;
+use atrium_api::xrpc::{HttpClient, XrpcClient};
+use chrono::{DateTime, Utc};
+use http::{header, HeaderValue, Method, Request, Response, StatusCode};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[derive(Debug)]
+pub enum Error {
+    Backend(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Backend(err) => write!(f, "response cache error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Stores responses by cache key, see `cache_key`.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait ResponseCache {
+    async fn get(&self, key: &str) -> Result<Option<Response<Vec<u8>>>>;
+    async fn put(&self, key: &str, response: &Response<Vec<u8>>, ttl: Duration) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CacheOptions {
+    /// The minimum time to cache successful responses, by XRPC method. Responses are
+    /// cached for longer if their `Cache-Control` allows it.
+    pub min_ttl: HashMap<String, Duration>,
+}
+
+/// An `HttpClient` serving unauthenticated `GET` requests from a `ResponseCache`.
+///
+/// Successful responses are cached for their `Cache-Control` max-age or the minimum TTL of
+/// their XRPC method, whichever is longer, unless they are `no-store` or `private`.
+/// `no-cache` responses with an `ETag` or `Last-Modified` are stored too, but only served
+/// after the upstream confirms them with a 304 to a conditional request.
+/// Cache failures are not fatal; the request is sent upstream instead.
+#[derive(Debug, Clone)]
+pub struct CachingHttpClient<T, C> {
+    inner: T,
+    cache: C,
+    options: CacheOptions,
+}
+
+impl<T, C> CachingHttpClient<T, C> {
+    pub fn new(inner: T, cache: C) -> Self {
+        Self::with_options(inner, cache, CacheOptions::default())
+    }
+    pub fn with_options(inner: T, cache: C, options: CacheOptions) -> Self {
+        Self {
+            inner,
+            cache,
+            options,
+        }
+    }
+    /// How long to cache `response` to a request for `path`, if at all, and whether it
+    /// must be revalidated before it is served.
+    fn ttl(&self, path: &str, response: &Response<Vec<u8>>) -> Option<(Duration, bool)> {
+        if response.status() != StatusCode::OK {
+            return None;
+        }
+        let min_ttl = path
+            .strip_prefix("/xrpc/")
+            .and_then(|nsid| self.options.min_ttl.get(nsid))
+            .copied()
+            .unwrap_or_default();
+        let (ttl, revalidate) = match CacheControl::parse(response) {
+            CacheControl::NoStore => return None,
+            CacheControl::NoCache(_) if !has_validator(response) => return None,
+            CacheControl::NoCache(max_age) => (max_age.max(min_ttl).max(REVALIDATE_TTL), true),
+            CacheControl::MaxAge(max_age) => (max_age.max(min_ttl), false),
+            CacheControl::None => (min_ttl, false),
+        };
+        (!ttl.is_zero()).then_some((ttl, revalidate))
+    }
+}
+
+/// How long `no-cache` responses are kept at least, for their validators.
+const REVALIDATE_TTL: Duration = Duration::from_secs(600);
+
+/// Marks stored responses that must be revalidated, since their `Cache-Control` is replaced
+/// when they are stored.
+const REVALIDATE: &str = "x-cache-revalidate";
+
+fn has_validator(response: &Response<Vec<u8>>) -> bool {
+    response.headers().contains_key(header::ETAG)
+        || response.headers().contains_key(header::LAST_MODIFIED)
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<T, C> HttpClient for CachingHttpClient<T, C>
+where
+    T: HttpClient + Send + Sync,
+    C: ResponseCache + Send + Sync,
+{
+    async fn send_http(
+        &self,
+        mut request: Request<Vec<u8>>,
+    ) -> std::result::Result<Response<Vec<u8>>, BoxError> {
+        let Some(key) = cache_key(&request) else {
+            return self.inner.send_http(request).await;
+        };
+        let stale = match self.cache.get(&key).await {
+            Ok(Some(cached)) if !cached.headers().contains_key(REVALIDATE) => return Ok(cached),
+            Ok(cached) => cached,
+            Err(_) => None,
+        };
+        if let Some(stale) = &stale {
+            for (validator, condition) in [
+                (header::ETAG, header::IF_NONE_MATCH),
+                (header::LAST_MODIFIED, header::IF_MODIFIED_SINCE),
+            ] {
+                if let Some(value) = stale.headers().get(validator) {
+                    request.headers_mut().insert(condition, value.clone());
+                }
+            }
+        }
+        let path = request.uri().path().to_string();
+        let response = self.inner.send_http(request).await?;
+        if let Some(mut stale) = stale.filter(|_| response.status() == StatusCode::NOT_MODIFIED) {
+            stale.headers_mut().remove(REVALIDATE);
+            return Ok(stale);
+        }
+        if let Some((ttl, revalidate)) = self.ttl(&path, &response) {
+            if revalidate {
+                let mut stored = copy_response(&response);
+                stored
+                    .headers_mut()
+                    .insert(REVALIDATE, HeaderValue::from_static("1"));
+                self.cache.put(&key, &stored, ttl).await.ok();
+            } else {
+                self.cache.put(&key, &response, ttl).await.ok();
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<T, C> XrpcClient for CachingHttpClient<T, C>
+where
+    T: XrpcClient + Send + Sync,
+    C: ResponseCache + Send + Sync,
+{
+    fn base_uri(&self) -> String {
+        self.inner.base_uri()
+    }
+    async fn auth(&self, is_refresh: bool) -> Option<String> {
+        self.inner.auth(is_refresh).await
+    }
+}
+
+/// The cache key of `request`, its URL with the query parameters sorted, or `None` if the
+/// response must not be shared.
+pub fn cache_key(request: &Request<Vec<u8>>) -> Option<String> {
+    if request.method() != Method::GET || request.headers().contains_key(header::AUTHORIZATION) {
+        return None;
+    }
+    let uri = request.uri();
+    let base = format!("{}://{}{}", uri.scheme_str()?, uri.authority()?, uri.path());
+    match uri.query() {
+        Some(query) => {
+            let mut params = query.split('&').collect::<Vec<_>>();
+            params.sort_unstable();
+            Some(format!("{base}?{}", params.join("&")))
+        }
+        None => Some(base),
+    }
+}
+
+enum CacheControl {
+    NoStore,
+    /// Stored, but revalidated before every use, with the max-age if any.
+    NoCache(Duration),
+    MaxAge(Duration),
+    None,
+}
+
+impl CacheControl {
+    fn parse(response: &Response<Vec<u8>>) -> Self {
+        let mut max_age = None;
+        let mut no_cache = false;
+        for directive in response
+            .headers()
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+        {
+            let (name, value) = directive
+                .trim()
+                .split_once('=')
+                .unwrap_or((directive.trim(), ""));
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" | "private" => return CacheControl::NoStore,
+                "no-cache" => no_cache = true,
+                "max-age" | "s-maxage" => {
+                    let seconds = value.trim_matches('"').parse().ok();
+                    max_age = max_age.max(seconds.map(Duration::from_secs));
+                }
+                _ => {}
+            }
+        }
+        match (no_cache, max_age) {
+            (true, max_age) => CacheControl::NoCache(max_age.unwrap_or_default()),
+            (false, Some(max_age)) => CacheControl::MaxAge(max_age),
+            (false, None) => CacheControl::None,
+        }
+    }
+}
+
+/// Copy `response` without its extensions.
+pub fn copy_response(response: &Response<Vec<u8>>) -> Response<Vec<u8>> {
+    let mut copy = Response::new(response.body().clone());
+    *copy.status_mut() = response.status();
+    *copy.version_mut() = response.version();
+    copy.headers_mut().clone_from(response.headers());
+    copy
+}
+
+/// Copy `response` with its `Cache-Control` replaced by a max-age of `ttl`.
+pub fn with_max_age(response: &Response<Vec<u8>>, ttl: Duration) -> Response<Vec<u8>> {
+    let mut copy = copy_response(response);
+    if let Ok(value) = HeaderValue::try_from(format!("max-age={}", ttl.as_secs())) {
+        copy.headers_mut().insert(header::CACHE_CONTROL, value);
+    }
+    copy
+}
+
+/// An in-memory cache holding up to `capacity` values, by default responses, evicting
+/// expired and then least recently used ones.
+#[derive(Debug)]
+pub struct MemoryCache<V = Response<Vec<u8>>> {
+    capacity: usize,
+    entries: Mutex<Lru<V>>,
+}
+
+/// The entries and their order of use and expiry, so that evicting one takes logarithmic
+/// time rather than a scan of them all.
+#[derive(Debug)]
+struct Lru<V> {
+    tick: u64,
+    entries: HashMap<String, Entry<V>>,
+    /// Keys by the tick they were last used at.
+    by_use: BTreeMap<u64, String>,
+    /// Keys by the time they expire at.
+    by_expiry: BTreeSet<(DateTime<Utc>, String)>,
+}
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    expires_at: DateTime<Utc>,
+    used: u64,
+}
+
+impl<V> Lru<V> {
+    fn remove(&mut self, key: &str) -> Option<Entry<V>> {
+        let entry = self.entries.remove(key)?;
+        self.by_use.remove(&entry.used);
+        self.by_expiry.remove(&(entry.expires_at, key.to_string()));
+        Some(entry)
+    }
+    /// The expired entry that expired first, or else the least recently used one.
+    fn evictable(&self, now: DateTime<Utc>) -> Option<String> {
+        match self.by_expiry.first() {
+            Some((expires_at, key)) if *expires_at <= now => Some(key.clone()),
+            _ => self.by_use.values().next().cloned(),
+        }
+    }
+}
+
+impl<V> MemoryCache<V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(Lru {
+                tick: 0,
+                entries: HashMap::new(),
+                by_use: BTreeMap::new(),
+                by_expiry: BTreeSet::new(),
+            }),
+        }
+    }
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Lru<V>>> {
+        self.entries
+            .lock()
+            .map_err(|e| Error::Backend(e.to_string()))
+    }
+    /// `read` of the value of `key`, if it is cached and not expired.
+    pub fn get_with<T>(&self, key: &str, read: impl FnOnce(&V) -> T) -> Result<Option<T>> {
+        let mut lru = self.lock()?;
+        let Some((expires_at, used)) = lru
+            .entries
+            .get(key)
+            .map(|entry| (entry.expires_at, entry.used))
+        else {
+            return Ok(None);
+        };
+        if expires_at <= Utc::now() {
+            lru.remove(key);
+            return Ok(None);
+        }
+        lru.tick += 1;
+        let tick = lru.tick;
+        lru.by_use.remove(&used);
+        lru.by_use.insert(tick, key.into());
+        Ok(lru.entries.get_mut(key).map(|entry| {
+            entry.used = tick;
+            read(&entry.value)
+        }))
+    }
+    /// Cache `value` under `key` for `ttl`, evicting another entry if full.
+    pub fn insert(&self, key: &str, value: V, ttl: Duration) -> Result<()> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        let expires_at = chrono::Duration::from_std(ttl)
+            .ok()
+            .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let mut lru = self.lock()?;
+        if lru.remove(key).is_none() && lru.entries.len() >= self.capacity {
+            if let Some(evicted) = lru.evictable(Utc::now()) {
+                lru.remove(&evicted);
+            }
+        }
+        lru.tick += 1;
+        let used = lru.tick;
+        lru.by_use.insert(used, key.into());
+        lru.by_expiry.insert((expires_at, key.into()));
+        lru.entries.insert(
+            key.into(),
+            Entry {
+                value,
+                expires_at,
+                used,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl ResponseCache for MemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<Response<Vec<u8>>>> {
+        self.get_with(key, copy_response)
+    }
+    async fn put(&self, key: &str, response: &Response<Vec<u8>>, ttl: Duration) -> Result<()> {
+        self.insert(key, with_max_age(response, ttl), ttl)
+    }
+}
@@ -0,0 +1,789 @@
+//! Runtime-independent assembly of the time machine feed skeleton.
+use crate::filter::{self, PostMeta};
+use crate::identity::did::plc_operation::{self, PlcHistoryEntry};
+use crate::manifest::{FeedDefinition, Filter, Source, TimeRule};
+use crate::moderation::{Blocks, Moderation, Relationship, Relationships};
+use crate::post_index::{self, PostIndex, RecordKind};
+use crate::post_status::{self, PostStatus, PostStatusCache};
+use atrium_api::app::bsky::feed::defs::{
+    PostView, SkeletonFeedPost, SkeletonFeedPostReasonRefs, SkeletonReasonRepost,
+};
+use atrium_api::app::bsky::feed::{get_posts, search_posts, Like, Repost};
+use atrium_api::app::bsky::graph::get_relationships;
+use atrium_api::client::AtpServiceClient;
+use atrium_api::com::atproto::repo::list_records;
+use atrium_api::records::{KnownRecord, Record};
+use atrium_api::types::string::{AtIdentifier, Nsid};
+use atrium_api::types::{Collection, LimitedNonZeroU8, Union};
+use atrium_api::xrpc::{OutputDataOrBytes, XrpcClient, XrpcRequest};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Error {
+    SearchPosts(atrium_api::xrpc::error::Error<search_posts::Error>),
+    GetPosts(atrium_api::xrpc::error::Error<get_posts::Error>),
+    GetRelationships(atrium_api::xrpc::error::Error<get_relationships::Error>),
+    InvalidDid(String),
+    ListRecords(atrium_api::xrpc::error::Error<list_records::Error>),
+    Index(post_index::Error),
+    PostStatus(post_status::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::SearchPosts(err) => write!(f, "failed to search posts: {err}"),
+            Error::GetPosts(err) => write!(f, "failed to get posts: {err}"),
+            Error::GetRelationships(err) => write!(f, "failed to get relationships: {err}"),
+            Error::InvalidDid(did) => write!(f, "invalid DID: {did}"),
+            Error::ListRecords(err) => write!(f, "failed to list records: {err}"),
+            Error::Index(err) => write!(f, "failed to read post index: {err}"),
+            Error::PostStatus(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A post of the skeleton, with the time it was created (or reposted).
+#[derive(Debug, Clone)]
+pub struct FeedItem {
+    pub uri: String,
+    pub created_at: DateTime<Utc>,
+    pub feed_context: Option<String>,
+    /// The URI of the repost record, if the item is a repost of `uri`.
+    pub repost: Option<String>,
+    /// Metadata for filtering, if the post has been hydrated.
+    pub meta: Option<PostMeta>,
+}
+
+impl FeedItem {
+    /// The URI of the record that put the item in the feed: the repost or the post.
+    pub fn record_uri(&self) -> &str {
+        self.repost.as_deref().unwrap_or(&self.uri)
+    }
+}
+
+impl From<FeedItem> for SkeletonFeedPost {
+    fn from(item: FeedItem) -> Self {
+        SkeletonFeedPost {
+            feed_context: item.feed_context,
+            post: item.uri,
+            reason: item.repost.map(|repost| {
+                Union::Refs(SkeletonFeedPostReasonRefs::SkeletonReasonRepost(Box::new(
+                    SkeletonReasonRepost { repost },
+                )))
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Skeleton {
+    pub items: Vec<FeedItem>,
+    pub cursor: Option<String>,
+}
+
+/// Parameters of a single page of the feed.
+#[derive(Debug, Clone)]
+pub struct SkeletonQuery {
+    pub did: String,
+    pub limit: Option<LimitedNonZeroU8<100>>,
+    pub cursor: Option<String>,
+    pub now: DateTime<Utc>,
+    /// How far back from `now` the feed goes.
+    pub rule: TimeRule,
+    /// Restrict the search to posts in this language.
+    pub lang: Option<String>,
+}
+
+/// Build a page of posts that `did` made before the time `rule` gives for `now`.
+///
+/// `history` is the PLC history of `did`, used to annotate each post with the handle
+/// the author had when it was created.
+pub async fn time_machine<X>(
+    appview: &AtpServiceClient<X>,
+    query: SkeletonQuery,
+    history: &[PlcHistoryEntry],
+) -> Result<Skeleton>
+where
+    X: XrpcClient + Send + Sync,
+{
+    let until = query.rule.until(query.now);
+    search(appview, &query, until, query.cursor.clone(), history).await
+}
+
+/// Like [`time_machine`], interleaved with the reposts that `did` made before the same
+/// time, read from the `app.bsky.feed.repost` records on its PDS.
+///
+/// Both sources are paged by creation time, so the cursor is a [`TimeCursor`] at the last
+/// item rather than a `searchPosts` cursor.
+pub async fn time_machine_with_reposts<X, Y>(
+    appview: &AtpServiceClient<X>,
+    pds: &AtpServiceClient<Y>,
+    query: SkeletonQuery,
+    history: &[PlcHistoryEntry],
+) -> Result<Skeleton>
+where
+    X: XrpcClient + Send + Sync,
+    Y: XrpcClient + Send + Sync,
+{
+    let Some(until) = query.rule.until(query.now) else {
+        return Ok(Skeleton::default());
+    };
+    let position = TimeCursor::start(until, query.cursor.as_deref());
+    // both sources list records before a time, so ask for those up to and including the
+    // time of the cursor and leave out the ones at that time already served
+    let inclusive = position.created_at + chrono::Duration::microseconds(1);
+    let limit = query.limit.map_or(DEFAULT_LIMIT, u8::from);
+    let posts = search(appview, &query, Some(inclusive), None, history).await?;
+    let reposts = reposts(pds, &query.did, inclusive, limit).await?;
+    let mut items = posts
+        .items
+        .into_iter()
+        .chain(reposts)
+        .filter(|item| position.is_after(item.created_at, item.record_uri()))
+        .collect::<Vec<_>>();
+    items.sort_by(|a, b| (b.created_at, b.record_uri()).cmp(&(a.created_at, a.record_uri())));
+    items.truncate(limit.into());
+    let cursor = items
+        .last()
+        .filter(|_| items.len() == usize::from(limit))
+        .map(|item| TimeCursor::at(item.created_at, item.record_uri()).to_string());
+    Ok(Skeleton { items, cursor })
+}
+
+/// A position in a feed of records ordered by creation time, newest first, and by URI
+/// between records created at the same time, formatted as `<created_at>::<uri>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeCursor {
+    pub created_at: DateTime<Utc>,
+    pub uri: String,
+}
+
+impl TimeCursor {
+    pub fn at(created_at: DateTime<Utc>, uri: &str) -> Self {
+        Self {
+            created_at,
+            uri: uri.into(),
+        }
+    }
+    /// The position of `cursor`, or of the records created before `until` if that comes
+    /// first or there is no valid cursor.
+    pub fn start(until: DateTime<Utc>, cursor: Option<&str>) -> Self {
+        let before_until = Self::at(until, "");
+        match cursor.and_then(|cursor| cursor.parse::<Self>().ok()) {
+            Some(cursor) if before_until.is_after(cursor.created_at, &cursor.uri) => cursor,
+            _ => before_until,
+        }
+    }
+    /// Whether the record created at `created_at` with `uri` comes after this position.
+    pub fn is_after(&self, created_at: DateTime<Utc>, uri: &str) -> bool {
+        (created_at, uri) < (self.created_at, self.uri.as_str())
+    }
+}
+
+impl std::fmt::Display for TimeCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}::{}",
+            self.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.uri
+        )
+    }
+}
+
+impl std::str::FromStr for TimeCursor {
+    type Err = chrono::ParseError;
+
+    /// Parse a cursor, also accepting a bare time as before all records created then.
+    fn from_str(cursor: &str) -> std::result::Result<Self, Self::Err> {
+        let (created_at, uri) = cursor.split_once("::").unwrap_or((cursor, ""));
+        Ok(Self::at(
+            DateTime::parse_from_rfc3339(created_at)?.with_timezone(&Utc),
+            uri,
+        ))
+    }
+}
+
+/// The default page size of `getFeedSkeleton`.
+const DEFAULT_LIMIT: u8 = 50;
+
+async fn search<X>(
+    appview: &AtpServiceClient<X>,
+    query: &SkeletonQuery,
+    until: Option<DateTime<Utc>>,
+    cursor: Option<String>,
+    history: &[PlcHistoryEntry],
+) -> Result<Skeleton>
+where
+    X: XrpcClient + Send + Sync,
+{
+    let Some(until) = until else {
+        return Ok(Skeleton::default());
+    };
+    let params = search_posts::Parameters {
+        author: query.did.parse().ok(),
+        cursor,
+        domain: None,
+        lang: query.lang.as_deref().and_then(|lang| lang.parse().ok()),
+        limit: query.limit,
+        mentions: None,
+        q: query.did.clone(),
+        since: None,
+        sort: None,
+        tag: None,
+        until: Some(until.to_rfc3339_opts(SecondsFormat::Micros, true)),
+        url: None,
+    };
+    let output = appview
+        .service
+        .app
+        .bsky
+        .feed
+        .search_posts(params)
+        .await
+        .map_err(Error::SearchPosts)?;
+    Ok(Skeleton {
+        items: output
+            .posts
+            .into_iter()
+            .map(|post| {
+                let created_at = created_at(&post);
+                FeedItem {
+                    // the handle the author had when the post was created
+                    feed_context: handle_at(history, created_at),
+                    repost: None,
+                    meta: Some(PostMeta::from(&post)),
+                    uri: post.uri,
+                    created_at,
+                }
+            })
+            .collect(),
+        cursor: output.cursor,
+    })
+}
+
+/// Build a page of the posts that `did` liked before the time `rule` gives for `now`,
+/// read from the `app.bsky.feed.like` records on its PDS.
+///
+/// The cursor is the record key of the last like. Liked posts that have since been
+/// deleted are left for [`moderate`] to drop.
+pub async fn liked_back_then<Y>(pds: &AtpServiceClient<Y>, query: SkeletonQuery) -> Result<Skeleton>
+where
+    Y: XrpcClient + Send + Sync,
+{
+    let Some(until) = query.rule.until(query.now) else {
+        return Ok(Skeleton::default());
+    };
+    // TIDs sort by time, so a cursor newer than `until` is clamped to it
+    let start = tid(until);
+    let cursor = query
+        .cursor
+        .filter(|cursor| *cursor < start)
+        .unwrap_or(start);
+    let limit = query.limit.map_or(DEFAULT_LIMIT, u8::from);
+    let (likes, cursor) = list_subjects(pds, &query.did, Like::nsid(), cursor, limit).await?;
+    let items = likes
+        .into_iter()
+        .filter(|subject| subject.created_at < until)
+        .map(|subject| FeedItem {
+            uri: subject.uri,
+            created_at: subject.created_at,
+            feed_context: None,
+            repost: None,
+            meta: None,
+        })
+        .collect();
+    Ok(Skeleton { items, cursor })
+}
+
+/// Build a page of `source` from the post index rather than the AppView and PDS: the
+/// posts of `did`, interleaved with its reposts if `include_reposts`, or its likes.
+///
+/// The cursor is a [`TimeCursor`] at the last record, so that records created at the same
+/// time are not skipped between pages. Liked posts that have since been deleted are left
+/// for [`moderate`] to drop.
+pub async fn indexed<I>(
+    index: &I,
+    source: Source,
+    include_reposts: bool,
+    query: SkeletonQuery,
+    history: &[PlcHistoryEntry],
+) -> Result<Skeleton>
+where
+    I: PostIndex,
+{
+    let Some(until) = query.rule.until(query.now) else {
+        return Ok(Skeleton::default());
+    };
+    let position = TimeCursor::start(until, query.cursor.as_deref());
+    let kinds: &[RecordKind] = match (source, include_reposts) {
+        (Source::SearchPosts, false) => &[RecordKind::Post],
+        (Source::SearchPosts, true) => &[RecordKind::Post, RecordKind::Repost],
+        (Source::Likes, _) => &[RecordKind::Like],
+    };
+    let limit = query.limit.map_or(DEFAULT_LIMIT, u8::from);
+    let records = index
+        .records(&query.did, kinds, &position, limit.into())
+        .await
+        .map_err(Error::Index)?;
+    let cursor = records
+        .last()
+        .filter(|_| records.len() == usize::from(limit))
+        .map(|record| TimeCursor::at(record.created_at, &record.uri).to_string());
+    let items = records
+        .into_iter()
+        .filter_map(|record| {
+            // the handle of `did` only describes its own posts
+            let feed_context = match record.kind {
+                RecordKind::Post => handle_at(history, record.created_at),
+                RecordKind::Repost | RecordKind::Like => None,
+            };
+            let (uri, repost) = match record.kind {
+                RecordKind::Post => (record.uri, None),
+                RecordKind::Repost => (record.subject?, Some(record.uri)),
+                RecordKind::Like => (record.subject?, None),
+            };
+            Some(FeedItem {
+                uri,
+                created_at: record.created_at,
+                feed_context,
+                repost,
+                meta: record.meta,
+            })
+        })
+        .collect();
+    Ok(Skeleton { items, cursor })
+}
+
+/// Up to `limit` reposts that `did` made before `until`, newest first.
+///
+/// Unlike posts, they have no feed context: the handle of `did` at the time would read as
+/// the handle of the reposted author.
+async fn reposts<Y>(
+    pds: &AtpServiceClient<Y>,
+    did: &str,
+    until: DateTime<Utc>,
+    limit: u8,
+) -> Result<Vec<FeedItem>>
+where
+    Y: XrpcClient + Send + Sync,
+{
+    let (reposts, _) = list_subjects(pds, did, Repost::nsid(), tid(until), limit).await?;
+    Ok(reposts
+        .into_iter()
+        .map(|subject| FeedItem {
+            feed_context: None,
+            uri: subject.uri,
+            created_at: subject.created_at,
+            repost: Some(subject.record),
+            meta: None,
+        })
+        .collect())
+}
+
+/// A like or repost record and the post it refers to.
+struct Subject {
+    record: String,
+    uri: String,
+    created_at: DateTime<Utc>,
+}
+
+/// List the like or repost records of `did` in `collection` after `cursor`, newest first.
+///
+/// Record keys are TIDs, so the records created before a time are listed after its TID.
+async fn list_subjects<Y>(
+    pds: &AtpServiceClient<Y>,
+    did: &str,
+    collection: Nsid,
+    cursor: String,
+    limit: u8,
+) -> Result<(Vec<Subject>, Option<String>)>
+where
+    Y: XrpcClient + Send + Sync,
+{
+    let Ok(repo) = did.parse() else {
+        return Ok((Vec::new(), None));
+    };
+    let output = pds
+        .service
+        .com
+        .atproto
+        .repo
+        .list_records(list_records::Parameters {
+            collection,
+            cursor: Some(cursor),
+            limit: limit.try_into().ok(),
+            repo: AtIdentifier::Did(repo),
+            reverse: None,
+            rkey_end: None,
+            rkey_start: None,
+        })
+        .await
+        .map_err(Error::ListRecords)?;
+    let subjects = output
+        .records
+        .into_iter()
+        .filter_map(|record| {
+            let (subject, created_at) = match record.value {
+                Record::Known(KnownRecord::AppBskyFeedRepost(repost)) => {
+                    (repost.subject, repost.created_at)
+                }
+                Record::Known(KnownRecord::AppBskyFeedLike(like)) => {
+                    (like.subject, like.created_at)
+                }
+                _ => return None,
+            };
+            Some(Subject {
+                record: record.uri,
+                uri: subject.uri,
+                created_at: created_at.as_ref().with_timezone(&Utc),
+            })
+        })
+        .collect();
+    Ok((subjects, output.cursor))
+}
+
+fn handle_at(history: &[PlcHistoryEntry], at: DateTime<Utc>) -> Option<String> {
+    plc_operation::entry_at(history, at).and_then(|entry| entry.get_handle())
+}
+
+/// The smallest TID of `time`: microseconds since the epoch and a zero clock id, in
+/// base32-sortable.
+fn tid(time: DateTime<Utc>) -> String {
+    const ALPHABET: &[u8; 32] = b"234567abcdefghijklmnopqrstuvwxyz";
+    let mut value = (time.timestamp_micros().max(0) as u64 & ((1 << 53) - 1)) << 10;
+    let mut tid = [0u8; 13];
+    for c in tid.iter_mut().rev() {
+        *c = ALPHABET[(value & 31) as usize];
+        value >>= 5;
+    }
+    tid.iter().map(|&c| char::from(c)).collect()
+}
+
+/// Apply `filters` to the items of `skeleton`, hydrating those without metadata first.
+pub async fn filter<X>(
+    appview: &AtpServiceClient<X>,
+    filters: &[Filter],
+    mut skeleton: Skeleton,
+) -> Result<Skeleton>
+where
+    X: XrpcClient + Send + Sync,
+{
+    if !filters.is_empty() {
+        hydrate(appview, &mut skeleton.items).await?;
+        skeleton.items = filter::apply(filters, skeleton.items);
+    }
+    Ok(skeleton)
+}
+
+/// Fill in the metadata of `items` with `getPosts`, 25 posts at a time.
+pub async fn hydrate<X>(appview: &AtpServiceClient<X>, items: &mut [FeedItem]) -> Result<()>
+where
+    X: XrpcClient + Send + Sync,
+{
+    let uris = items
+        .iter()
+        .filter(|item| item.meta.is_none())
+        .map(|item| item.uri.clone())
+        .collect::<Vec<_>>();
+    let posts = get_posts(appview, &uris).await?;
+    for item in items.iter_mut().filter(|item| item.meta.is_none()) {
+        item.meta = posts.get(&item.uri).map(PostMeta::from);
+    }
+    Ok(())
+}
+
+/// How long a post that was found is trusted to still exist.
+const LIVE_TTL: Duration = Duration::from_secs(10 * 60);
+/// How long a post that was not found is remembered as gone. Deletions are final, but the
+/// AppView also leaves out posts it cannot serve for a while, so this is no longer than
+/// `LIVE_TTL`.
+const GONE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Drop the items whose posts have since been deleted or taken down, carry one of
+/// `labels` or are blocked, looking up those not in `cache` with `getPosts` and filling
+/// in their metadata along the way.
+///
+/// Blocks are those between `viewer` and the authors of the posts, their quoted posts and
+/// the parents and roots of replies, looked up on every call since they depend on the
+/// viewer. If the posts cannot be looked up, the items not in `cache` are kept unverified
+/// rather than failing the page; if the blocks cannot, only the viewer's own posts are kept.
+///
+/// `relationships` is the AppView client that `getRelationships` is sent with, since its
+/// generated output leaves out the block fields.
+pub async fn moderate<X, R, C>(
+    appview: &AtpServiceClient<X>,
+    relationships: &R,
+    cache: &C,
+    labels: &[String],
+    viewer: &str,
+    mut items: Vec<FeedItem>,
+) -> Result<Vec<FeedItem>>
+where
+    X: XrpcClient + Send + Sync,
+    R: XrpcClient + Send + Sync,
+    C: PostStatusCache,
+{
+    let uris = items
+        .iter()
+        .map(|item| item.uri.clone())
+        .collect::<Vec<_>>();
+    let mut statuses = cache.get(&uris).await.map_err(Error::PostStatus)?;
+    let mut unknown = uris
+        .into_iter()
+        .filter(|uri| !statuses.contains_key(uri))
+        .collect::<Vec<_>>();
+    unknown.sort();
+    unknown.dedup();
+    let mut posts = match get_posts(appview, &unknown).await {
+        Ok(posts) => posts,
+        Err(err) => {
+            log_error!("serving {} posts unverified: {err}", unknown.len());
+            unknown.clear();
+            HashMap::new()
+        }
+    };
+    for uri in unknown {
+        let (status, ttl) = match posts.get(&uri) {
+            Some(post) => (PostStatus::Live(Moderation::from(post)), LIVE_TTL),
+            None => (PostStatus::Gone, GONE_TTL),
+        };
+        cache
+            .put(&uri, status.clone(), ttl)
+            .await
+            .map_err(Error::PostStatus)?;
+        statuses.insert(uri, status);
+    }
+    let mut others = statuses
+        .values()
+        .filter_map(|status| match status {
+            PostStatus::Live(moderation) => Some(&moderation.authors),
+            PostStatus::Gone => None,
+        })
+        .flatten()
+        .filter(|did| *did != viewer)
+        .cloned()
+        .collect::<Vec<_>>();
+    others.sort();
+    others.dedup();
+    let blocks = match get_blocks(relationships, viewer, &others).await {
+        Ok(blocks) => blocks,
+        Err(err) => {
+            log_error!("serving only the posts of {viewer}: {err}");
+            others.into_iter().collect()
+        }
+    };
+    items.retain(|item| match statuses.get(&item.uri) {
+        Some(PostStatus::Live(moderation)) => !moderation.hides(labels, &blocks),
+        Some(PostStatus::Gone) => false,
+        // not looked up
+        None => true,
+    });
+    for item in &mut items {
+        if let Some(post) = posts.remove(&item.uri) {
+            item.meta = Some(PostMeta::from(&post));
+        }
+    }
+    Ok(items)
+}
+
+/// Pages built by [`fill`] for a single request at most.
+const MAX_PAGES: usize = 4;
+
+/// Build a page of up to the query's limit with `build`, dropping posts that [`moderate`]
+/// or `filters` reject. While items were dropped, further
+/// pages are built from the cursor of the last one for the missing number of items.
+pub async fn fill<X, R, C, F, Fut>(
+    appview: &AtpServiceClient<X>,
+    relationships: &R,
+    cache: &C,
+    labels: &[String],
+    filters: &[Filter],
+    mut query: SkeletonQuery,
+    mut build: F,
+) -> Result<Skeleton>
+where
+    X: XrpcClient + Send + Sync,
+    R: XrpcClient + Send + Sync,
+    C: PostStatusCache,
+    F: FnMut(SkeletonQuery) -> Fut,
+    Fut: Future<Output = Result<Skeleton>>,
+{
+    let limit = usize::from(query.limit.map_or(DEFAULT_LIMIT, u8::from));
+    let mut skeleton = Skeleton::default();
+    for _ in 0..MAX_PAGES {
+        let page = build(query.clone()).await?;
+        let items = moderate(
+            appview,
+            relationships,
+            cache,
+            labels,
+            &query.did,
+            page.items,
+        )
+        .await?;
+        let page = filter(
+            appview,
+            filters,
+            Skeleton {
+                items,
+                cursor: page.cursor,
+            },
+        )
+        .await?;
+        skeleton.items.extend(page.items);
+        skeleton.cursor = page.cursor;
+        let missing = limit.saturating_sub(skeleton.items.len());
+        let Some(missing) = u8::try_from(missing)
+            .ok()
+            .and_then(|missing| LimitedNonZeroU8::try_from(missing).ok())
+        else {
+            break;
+        };
+        if skeleton.cursor.is_none() {
+            break;
+        }
+        query.cursor = skeleton.cursor.clone();
+        query.limit = Some(missing);
+    }
+    Ok(skeleton)
+}
+
+/// The services and caches that pages of a feed are assembled from.
+pub struct Upstream<'a, X, Y, R, I, C>
+where
+    X: XrpcClient + Send + Sync,
+    Y: XrpcClient + Send + Sync,
+{
+    pub appview: &'a AtpServiceClient<X>,
+    /// The AppView client that blocks are looked up with; see [`moderate`].
+    pub relationships: &'a R,
+    /// The viewer's PDS, which likes and reposts are listed from without an index.
+    pub pds: Option<&'a AtpServiceClient<Y>>,
+    /// The post index, read instead of the AppView and PDS if given.
+    pub index: Option<&'a I>,
+    pub statuses: &'a C,
+}
+
+/// Assemble a page of `feed` from its source, filled up to the query's limit with
+/// [`fill`].
+///
+/// Without the PDS that the likes source is listed from, the page is empty.
+pub async fn assemble<X, Y, R, I, C>(
+    upstream: &Upstream<'_, X, Y, R, I, C>,
+    feed: &FeedDefinition,
+    query: SkeletonQuery,
+    history: &[PlcHistoryEntry],
+) -> Result<Skeleton>
+where
+    X: XrpcClient + Send + Sync,
+    Y: XrpcClient + Send + Sync,
+    R: XrpcClient + Send + Sync,
+    I: PostIndex,
+    C: PostStatusCache,
+{
+    let (appview, pds, index) = (upstream.appview, upstream.pds, upstream.index);
+    fill(
+        appview,
+        upstream.relationships,
+        upstream.statuses,
+        &feed.labels,
+        &feed.filters,
+        query,
+        |query| async move {
+            match (index, feed.source, pds) {
+                (Some(index), source, _) => {
+                    indexed(index, source, feed.include_reposts, query, history).await
+                }
+                (None, Source::SearchPosts, Some(pds)) if feed.include_reposts => {
+                    time_machine_with_reposts(appview, pds, query, history).await
+                }
+                (None, Source::SearchPosts, _) => time_machine(appview, query, history).await,
+                (None, Source::Likes, Some(pds)) => liked_back_then(pds, query).await,
+                (None, Source::Likes, None) => Ok(Skeleton::default()),
+            }
+        },
+    )
+    .await
+}
+
+/// The posts among `uris` that the AppView returns, 25 at a time.
+async fn get_posts<X>(
+    appview: &AtpServiceClient<X>,
+    uris: &[String],
+) -> Result<HashMap<String, PostView>>
+where
+    X: XrpcClient + Send + Sync,
+{
+    let mut posts = HashMap::new();
+    for chunk in uris.chunks(25) {
+        let output = appview
+            .service
+            .app
+            .bsky
+            .feed
+            .get_posts(get_posts::Parameters {
+                uris: chunk.to_vec(),
+            })
+            .await
+            .map_err(Error::GetPosts)?;
+        for post in output.posts {
+            posts.insert(post.uri.clone(), post);
+        }
+    }
+    Ok(posts)
+}
+
+/// Those of `others` that block or are blocked by `viewer`.
+async fn get_blocks<R>(appview: &R, viewer: &str, others: &[String]) -> Result<Blocks>
+where
+    R: XrpcClient + Send + Sync,
+{
+    let mut blocks = Blocks::new();
+    for chunk in others.chunks(30) {
+        let output = appview
+            .send_xrpc::<_, (), Relationships, get_relationships::Error>(&XrpcRequest {
+                method: http::Method::GET,
+                path: String::from("app.bsky.graph.getRelationships"),
+                parameters: Some(get_relationships::Parameters {
+                    actor: viewer
+                        .parse()
+                        .map_err(|_| Error::InvalidDid(viewer.into()))?,
+                    others: Some(chunk.iter().filter_map(|did| did.parse().ok()).collect()),
+                }),
+                input: None,
+                encoding: None,
+            })
+            .await
+            .map_err(Error::GetRelationships)?;
+        let OutputDataOrBytes::Data(output) = output else {
+            return Err(Error::GetRelationships(
+                atrium_api::xrpc::error::Error::UnexpectedResponseType,
+            ));
+        };
+        blocks.extend(
+            output
+                .relationships
+                .iter()
+                .filter_map(Relationship::blocked)
+                .map(String::from),
+        );
+    }
+    Ok(blocks)
+}
+
+pub fn created_at(post: &PostView) -> DateTime<Utc> {
+    match &post.record {
+        Record::Known(KnownRecord::AppBskyFeedPost(record)) => record.created_at.as_ref(),
+        _ => post.indexed_at.as_ref(),
+    }
+    .with_timezone(&Utc)
+}
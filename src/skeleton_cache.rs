@@ -0,0 +1,154 @@
+//! First pages precomputed on the cron trigger for recently active viewers, kept in KV.
+//!
+//! First-page requests record the viewer under `active/<rkey>/<did>`, expiring after
+//! `SkeletonCacheConfig::active_ttl` and written again once half of it passed, and
+//! scheduled runs store the page for them under `page/<rkey>/<limit>/<did>`. Record keys
+//! and DIDs cannot contain `/`.
+use crate::cached_page::{page_key, Activity, CachedPage};
+use crate::config::{Config, SkeletonCacheConfig};
+use crate::feed;
+use crate::manifest::FeedDefinition;
+use atrium_api::app::bsky::feed::get_feed_skeleton;
+use atrium_api::types::LimitedNonZeroU8;
+use chrono::Utc;
+use worker::kv::KvStore;
+use worker::{console_error, console_log, Env, Result};
+
+const ACTIVE_PREFIX: &str = "active/";
+
+/// The precomputed first page of `feed` for `did`, if it is fresh.
+///
+/// The request is recorded, so that scheduled runs keep precomputing the page while the
+/// viewer stays active.
+pub async fn first_page(
+    env: &Env,
+    config: &Config,
+    feed: &FeedDefinition,
+    did: &str,
+    limit: Option<LimitedNonZeroU8<100>>,
+) -> Option<get_feed_skeleton::Output> {
+    let (Some(cache), Some(max_age)) = (&config.skeleton_cache, feed.max_age) else {
+        return None;
+    };
+    let store = match env.kv(&cache.binding) {
+        Ok(store) => store,
+        Err(err) => {
+            console_error!("{err}");
+            return None;
+        }
+    };
+    let limit = limit.map(u8::from);
+    if let Err(err) = record_activity(&store, cache, feed, did, limit).await {
+        console_error!("failed to record activity of {did}: {err}");
+    }
+    match store
+        .get(&page_key(&feed.rkey, did, limit))
+        .json::<CachedPage>()
+        .await
+    {
+        Ok(Some(page)) if page.remaining(max_age, Utc::now()).is_some() => Some(page.output),
+        Ok(_) => None,
+        Err(err) => {
+            console_error!("failed to get precomputed page: {err}");
+            None
+        }
+    }
+}
+
+/// Precompute the first pages of active viewers that are more than half their max age
+/// old, up to `SkeletonCacheConfig::max_per_run` of them.
+pub async fn precompute(env: &Env, config: &Config) -> Result<()> {
+    let Some(cache) = &config.skeleton_cache else {
+        return Ok(());
+    };
+    let store = env.kv(&cache.binding)?;
+    let mut computed = 0;
+    let mut cursor = None;
+    loop {
+        let mut list = store.list().prefix(String::from(ACTIVE_PREFIX));
+        if let Some(cursor) = cursor {
+            list = list.cursor(cursor);
+        }
+        let response = list.execute().await?;
+        for key in response.keys {
+            if computed >= cache.max_per_run {
+                return Ok(());
+            }
+            let Some((feed, did)) = key
+                .name
+                .strip_prefix(ACTIVE_PREFIX)
+                .and_then(|key| key.split_once('/'))
+                .and_then(|(rkey, did)| Some((config.feed(rkey)?, did)))
+            else {
+                continue;
+            };
+            let Some(max_age) = feed.max_age else {
+                continue;
+            };
+            let limit = key
+                .metadata
+                .and_then(|metadata| serde_json::from_value::<Activity>(metadata).ok())
+                .and_then(|activity| activity.limit);
+            let page_key = page_key(&feed.rkey, did, limit);
+            let stale = match store.get(&page_key).json::<CachedPage>().await? {
+                Some(page) => page.is_stale(max_age, Utc::now()),
+                None => true,
+            };
+            if !stale {
+                continue;
+            }
+            computed += 1;
+            let generated_at = Utc::now();
+            let limit_param = limit.and_then(|limit| limit.try_into().ok());
+            match feed::assemble(env, config, feed, did.into(), limit_param, None).await {
+                Ok(output) => {
+                    store
+                        .put(
+                            &page_key,
+                            CachedPage {
+                                generated_at,
+                                output,
+                            },
+                        )?
+                        // the minimum expiration TTL of KV
+                        .expiration_ttl(max_age.max(60))
+                        .execute()
+                        .await?;
+                    console_log!("precomputed {page_key}");
+                }
+                Err(err) => console_error!("failed to precompute {page_key}: {err}"),
+            }
+        }
+        match response.cursor {
+            Some(next) if !response.list_complete => cursor = Some(next),
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Record that `did` requested the first page of `feed` with `limit`, unless a recent
+/// record for the same limit is stored.
+async fn record_activity(
+    store: &KvStore,
+    cache: &SkeletonCacheConfig,
+    feed: &FeedDefinition,
+    did: &str,
+    limit: Option<u8>,
+) -> Result<()> {
+    let key = format!("{ACTIVE_PREFIX}{}/{did}", feed.rkey);
+    let now = Utc::now();
+    let (_, stored) = store.get(&key).text_with_metadata::<Activity>().await?;
+    if stored.is_some_and(|activity| !activity.is_due(limit, cache.active_ttl, now)) {
+        return Ok(());
+    }
+    store
+        .put(&key, "")?
+        .metadata(Activity {
+            limit,
+            recorded_at: Some(now),
+        })?
+        .expiration_ttl(cache.active_ttl)
+        .execute()
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,193 @@
+//! The post index in SQLite, with the schema and queries of `D1Index`.
+use crate::index_sql::{self, Row};
+use crate::post_index::{
+    Change, Error, IndexedRecord, PendingBackfill, PostIndex, RecordKind, Result,
+    MAX_BACKFILL_ATTEMPTS,
+};
+use crate::skeleton::TimeCursor;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+use std::path::Path;
+use std::sync::Mutex;
+
+pub struct SqliteIndex(Mutex<Connection>);
+
+impl SqliteIndex {
+    /// Open the index at `path`, applying the migrations it is missing.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::migrate(Connection::open(path).map_err(backend)?)
+    }
+    /// An index in memory.
+    pub fn in_memory() -> Result<Self> {
+        Self::migrate(Connection::open_in_memory().map_err(backend)?)
+    }
+    /// Apply the migrations after the `user_version` of `connection`, and record them.
+    fn migrate(connection: Connection) -> Result<Self> {
+        let applied: usize = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .map_err(backend)?;
+        for (version, migration) in index_sql::MIGRATIONS.iter().enumerate().skip(applied) {
+            connection
+                .execute_batch(&format!(
+                    "BEGIN; {migration}; PRAGMA user_version = {}; COMMIT;",
+                    version + 1
+                ))
+                .map_err(backend)?;
+        }
+        Ok(Self(Mutex::new(connection)))
+    }
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.0.lock().map_err(|e| Error::Backend(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl PostIndex for SqliteIndex {
+    async fn apply(&self, changes: &[Change], cursor: Option<i64>) -> Result<()> {
+        let mut connection = self.lock()?;
+        let transaction = connection.transaction().map_err(backend)?;
+        for change in changes {
+            match change {
+                Change::Put(record) => {
+                    let (flags, langs) = index_sql::meta_columns(record);
+                    transaction.execute(
+                        index_sql::PUT_RECORD,
+                        params![
+                            record.uri,
+                            record.did,
+                            record.kind.as_str(),
+                            index_sql::timestamp(record.created_at),
+                            record.subject,
+                            flags,
+                            langs,
+                        ],
+                    )
+                }
+                Change::Delete { uri } => transaction.execute(index_sql::DELETE_RECORD, [uri]),
+                Change::DeleteAccount { did } => {
+                    transaction.execute(index_sql::DELETE_ACCOUNT, [did])
+                }
+            }
+            .map_err(backend)?;
+        }
+        if let Some(cursor) = cursor {
+            transaction
+                .execute(index_sql::PUT_CURSOR, [cursor])
+                .map_err(backend)?;
+        }
+        transaction.commit().map_err(backend)
+    }
+    async fn cursor(&self) -> Result<Option<i64>> {
+        self.lock()?
+            .query_row(index_sql::CURSOR, [], |row| row.get(0))
+            .optional()
+            .map_err(backend)
+    }
+    async fn records(
+        &self,
+        did: &str,
+        kinds: &[RecordKind],
+        position: &TimeCursor,
+        limit: usize,
+    ) -> Result<Vec<IndexedRecord>> {
+        let connection = self.lock()?;
+        let mut statement = connection
+            .prepare(&index_sql::records(kinds.len()))
+            .map_err(backend)?;
+        let timestamp = index_sql::timestamp(position.created_at);
+        let mut values: Vec<&dyn ToSql> = vec![&did, &timestamp, &position.uri, &limit];
+        let kinds = kinds.iter().map(|kind| kind.as_str()).collect::<Vec<_>>();
+        values.extend(kinds.iter().map(|kind| kind as &dyn ToSql));
+        let rows = statement
+            .query_map(values.as_slice(), |row| {
+                Ok(Row {
+                    uri: row.get("uri")?,
+                    did: row.get("did")?,
+                    kind: row.get("kind")?,
+                    created_at: row.get("created_at")?,
+                    subject: row.get("subject")?,
+                    flags: row.get("flags")?,
+                    langs: row.get("langs")?,
+                })
+            })
+            .map_err(backend)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(backend)?;
+        Ok(rows.into_iter().filter_map(Row::record).collect())
+    }
+    async fn track(&self, did: &str) -> Result<()> {
+        self.lock()?
+            .execute(index_sql::TRACK, [did])
+            .map_err(backend)?;
+        Ok(())
+    }
+    async fn tracked(&self) -> Result<Vec<String>> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare(index_sql::TRACKED).map_err(backend)?;
+        let dids = statement
+            .query_map([], |row| row.get("did"))
+            .map_err(backend)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(backend);
+        dids
+    }
+    async fn request_backfill(&self, did: &str) -> Result<()> {
+        self.lock()?
+            .execute(index_sql::REQUEST_BACKFILL, [did])
+            .map_err(backend)?;
+        Ok(())
+    }
+    async fn pending_backfills(
+        &self,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PendingBackfill>> {
+        let connection = self.lock()?;
+        let mut statement = connection
+            .prepare(index_sql::PENDING_BACKFILLS)
+            .map_err(backend)?;
+        let pending = statement
+            .query_map(
+                params![limit, index_sql::timestamp(now), MAX_BACKFILL_ATTEMPTS],
+                |row| {
+                    Ok(PendingBackfill {
+                        did: row.get("did")?,
+                        attempts: row.get("attempts")?,
+                    })
+                },
+            )
+            .map_err(backend)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(backend);
+        pending
+    }
+    async fn record_backfill_attempt(
+        &self,
+        did: &str,
+        at: DateTime<Utc>,
+        retry_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.lock()?
+            .execute(
+                index_sql::RECORD_BACKFILL_ATTEMPT,
+                [
+                    did,
+                    &index_sql::timestamp(at),
+                    &index_sql::timestamp(retry_at),
+                ],
+            )
+            .map_err(backend)?;
+        Ok(())
+    }
+    async fn complete_backfill(&self, did: &str, rev: &str) -> Result<()> {
+        self.lock()?
+            .execute(index_sql::COMPLETE_BACKFILL, [did, rev])
+            .map_err(backend)?;
+        Ok(())
+    }
+}
+
+fn backend(err: rusqlite::Error) -> Error {
+    Error::Backend(err.to_string())
+}
@@ -0,0 +1,221 @@
+mod common;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bsky_timemachine::common_web::did_doc::DidDocument;
+use bsky_timemachine::crypto;
+use bsky_timemachine::identity::did::atproto_data::{self, Error};
+use common::TestKey;
+use serde_json::{json, Value};
+
+const DID: &str = "did:plc:viewer";
+
+/// A document whose `#atproto` verification method is `method`.
+fn document(mut method: Value) -> DidDocument {
+    method["id"] = json!(format!("{DID}#atproto"));
+    method["controller"] = json!(DID);
+    serde_json::from_value(json!({ "id": DID, "verificationMethod": [method] })).unwrap()
+}
+
+/// The JWK coordinates of `key`.
+fn jwk(key: &TestKey) -> (String, String) {
+    let point = key.signing_key.verifying_key().to_encoded_point(false);
+    (
+        URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+        URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+    )
+}
+
+#[test]
+fn reads_multibase_and_jwk_keys() {
+    let key = TestKey::new(1);
+    let multikey = document(json!({ "type": "Multikey", "publicKeyMultibase": key.multikey() }));
+    assert_eq!(
+        atproto_data::ensure_atproto_key(&multikey).unwrap(),
+        key.did_key
+    );
+    let (x, y) = jwk(&key);
+    let jwk = document(json!({
+        "type": "JsonWebKey2020",
+        "publicKeyJwk": { "kty": "EC", "crv": "secp256k1", "x": x, "y": y },
+    }));
+    assert_eq!(atproto_data::ensure_atproto_key(&jwk).unwrap(), key.did_key);
+}
+
+#[test]
+fn rejects_unknown_verification_method_types() {
+    let key = TestKey::new(1);
+    let multibase = document(json!({
+        "type": "Ed25519VerificationKey2020",
+        "publicKeyMultibase": key.multikey(),
+    }));
+    assert!(matches!(
+        atproto_data::ensure_atproto_key(&multibase),
+        Err(Error::UnsupportedKeyType(key_type)) if key_type == "Ed25519VerificationKey2020"
+    ));
+    let (x, y) = jwk(&key);
+    let jwk = document(json!({
+        "type": "Multikey",
+        "publicKeyJwk": { "kty": "EC", "crv": "secp256k1", "x": x, "y": y },
+    }));
+    assert!(matches!(
+        atproto_data::ensure_atproto_key(&jwk),
+        Err(Error::UnsupportedKeyType(key_type)) if key_type == "Multikey"
+    ));
+    let neither = document(json!({ "type": "Multikey" }));
+    assert!(matches!(
+        atproto_data::ensure_atproto_key(&neither),
+        Err(Error::MissingPublicKey(_))
+    ));
+}
+
+#[test]
+fn rejects_bad_multibase() {
+    let key = TestKey::new(1);
+    // base64url rather than base58btc
+    let unsupported = document(json!({
+        "type": "EcdsaSecp256k1VerificationKey2019",
+        "publicKeyMultibase": format!("u{}", &key.multikey()[1..]),
+    }));
+    assert!(matches!(
+        atproto_data::ensure_atproto_key(&unsupported),
+        Err(Error::Crypto(crypto::error::Error::UnsupportedMultibase(_)))
+    ));
+    // `0` is not in the base58 alphabet
+    let not_base58 = document(json!({ "type": "Multikey", "publicKeyMultibase": "z0OIl" }));
+    assert!(matches!(
+        atproto_data::ensure_atproto_key(&not_base58),
+        Err(Error::Crypto(crypto::error::Error::Base58(_)))
+    ));
+    let no_prefix = document(json!({
+        "type": "Multikey",
+        "publicKeyMultibase": &key.multikey()[1..],
+    }));
+    assert!(matches!(
+        atproto_data::ensure_atproto_key(&no_prefix),
+        Err(Error::Crypto(
+            crypto::error::Error::IncorrectMultikeyPrefix(_)
+        ))
+    ));
+    // a valid multibase that is not a point on the curve
+    let not_a_key = document(json!({
+        "type": "EcdsaSecp256k1VerificationKey2019",
+        "publicKeyMultibase": format!("z{}", bs58::encode([0x02; 33]).into_string()),
+    }));
+    assert!(matches!(
+        atproto_data::ensure_atproto_key(&not_a_key),
+        Err(Error::Crypto(crypto::error::Error::ECDSA(_)))
+    ));
+}
+
+#[test]
+fn rejects_invalid_jwks() {
+    let (x, y) = jwk(&TestKey::new(1));
+    let with_jwk = |jwk: Value| {
+        atproto_data::ensure_atproto_key(&document(
+            json!({ "type": "JsonWebKey2020", "publicKeyJwk": jwk }),
+        ))
+    };
+    assert!(matches!(
+        with_jwk(json!({ "kty": "EC", "crv": "secp256k1", "x": x })),
+        Err(Error::InvalidJwk(_))
+    ));
+    assert!(matches!(
+        with_jwk(json!({ "kty": "EC", "crv": "secp256k1", "x": "not base64!", "y": y })),
+        Err(Error::Base64Decode(_))
+    ));
+    assert!(matches!(
+        with_jwk(json!({ "kty": "EC", "crv": "secp256k1", "x": x, "y": "AAAA" })),
+        Err(Error::InvalidJwk(_))
+    ));
+    assert!(matches!(
+        with_jwk(json!({ "kty": "OKP", "crv": "Ed25519", "x": x })),
+        Err(Error::InvalidJwk(_))
+    ));
+    // the right length, but not on the curve
+    assert!(matches!(
+        with_jwk(json!({ "kty": "EC", "crv": "secp256k1", "x": x, "y": x })),
+        Err(Error::Crypto(crypto::error::Error::ECDSA(_)))
+    ));
+}
+
+#[test]
+fn rejects_p256_keys_before_decoding() {
+    let (x, y) = jwk(&TestKey::new(1));
+    let p256 = |x: &str| {
+        document(json!({
+            "type": "JsonWebKey2020",
+            "publicKeyJwk": { "kty": "EC", "crv": "P-256", "x": x, "y": y },
+        }))
+    };
+    assert!(matches!(
+        atproto_data::ensure_atproto_key(&p256(&x)),
+        Err(Error::UnsupportedCurve(crv)) if crv == "P-256"
+    ));
+    // not a point at all
+    assert!(matches!(
+        atproto_data::ensure_atproto_key(&p256("not base64!")),
+        Err(Error::UnsupportedCurve(_))
+    ));
+    let err = atproto_data::ensure_atproto_key(&p256(&x)).unwrap_err();
+    assert!(err.to_string().contains("P-256"));
+    let multibase = document(json!({
+        "type": "EcdsaSecp256r1VerificationKey2019",
+        "publicKeyMultibase": TestKey::new(1).multikey(),
+    }));
+    assert!(matches!(
+        atproto_data::ensure_atproto_key(&multibase),
+        Err(Error::UnsupportedCurve(_))
+    ));
+}
+
+#[test]
+fn gets_every_key_on_its_own() {
+    let (key, label_key) = (TestKey::new(1), TestKey::new(2));
+    let did_doc: DidDocument = serde_json::from_value(json!({
+        "id": DID,
+        "verificationMethod": [
+            {
+                "id": format!("{DID}#atproto"),
+                "type": "Multikey",
+                "controller": DID,
+                "publicKeyMultibase": key.multikey(),
+            },
+            {
+                "id": "#atproto_label",
+                "type": "Multikey",
+                "controller": DID,
+                "publicKeyMultibase": label_key.multikey(),
+            },
+            {
+                "id": format!("{DID}#legacy"),
+                "type": "Ed25519VerificationKey2020",
+                "controller": DID,
+                "publicKeyMultibase": key.multikey(),
+            },
+            {
+                // not a fragment, so not keyed
+                "id": "did:plc:other",
+                "type": "Multikey",
+                "controller": DID,
+                "publicKeyMultibase": key.multikey(),
+            },
+        ],
+    }))
+    .unwrap();
+    let keys = atproto_data::get_keys(&did_doc);
+    assert_eq!(
+        keys.keys().map(String::as_str).collect::<Vec<_>>(),
+        ["atproto", "atproto_label", "legacy"]
+    );
+    assert_eq!(keys["atproto"].as_ref().unwrap(), &key.did_key);
+    assert_eq!(keys["atproto_label"].as_ref().unwrap(), &label_key.did_key);
+    assert!(matches!(
+        &keys["legacy"],
+        Err(Error::UnsupportedKeyType(key_type)) if key_type == "Ed25519VerificationKey2020"
+    ));
+    assert!(matches!(
+        atproto_data::ensure_key(&did_doc, "missing"),
+        Err(Error::VerificationMethodNotFound(key_id, did)) if key_id == "missing" && did == DID
+    ));
+}
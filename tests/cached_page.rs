@@ -0,0 +1,71 @@
+use atrium_api::app::bsky::feed::get_feed_skeleton;
+use bsky_timemachine::cached_page::{page_key, Activity, CachedPage};
+use chrono::{DateTime, Duration, Utc};
+
+fn page(generated_at: DateTime<Utc>) -> CachedPage {
+    CachedPage {
+        generated_at,
+        output: get_feed_skeleton::Output {
+            cursor: None,
+            feed: Vec::new(),
+        },
+    }
+}
+
+#[test]
+fn expires_pages_after_their_max_age() {
+    let now = Utc::now();
+    assert_eq!(page(now).remaining(600, now), Some(600));
+    assert_eq!(
+        page(now - Duration::seconds(200)).remaining(600, now),
+        Some(400)
+    );
+    assert_eq!(page(now - Duration::seconds(600)).remaining(600, now), None);
+    assert_eq!(page(now - Duration::days(1)).remaining(600, now), None);
+    // a clock running behind is not a negative age
+    assert_eq!(
+        page(now + Duration::seconds(30)).remaining(600, now),
+        Some(600)
+    );
+}
+
+#[test]
+fn refreshes_pages_at_half_their_max_age() {
+    let now = Utc::now();
+    assert!(!page(now).is_stale(600, now));
+    assert!(!page(now - Duration::seconds(299)).is_stale(600, now));
+    assert!(page(now - Duration::seconds(300)).is_stale(600, now));
+    assert!(page(now - Duration::seconds(900)).is_stale(600, now));
+    let stored = serde_json::to_value(page(now)).unwrap();
+    assert!(stored.get("generatedAt").is_some());
+    let stored: CachedPage = serde_json::from_value(stored).unwrap();
+    assert!(!stored.is_stale(600, now));
+}
+
+#[test]
+fn keys_pages_by_feed_limit_and_viewer() {
+    assert_eq!(
+        page_key("timemachine", "did:plc:viewer", Some(30)),
+        "page/timemachine/30/did:plc:viewer"
+    );
+    assert_eq!(
+        page_key("timemachine", "did:plc:viewer", None),
+        "page/timemachine/default/did:plc:viewer"
+    );
+}
+
+#[test]
+fn rewrites_activity_at_half_its_ttl_or_for_another_limit() {
+    let now = Utc::now();
+    let activity = |limit, recorded_at| Activity { limit, recorded_at };
+    assert!(!activity(Some(30), Some(now)).is_due(Some(30), 3600, now));
+    assert!(!activity(Some(30), Some(now - Duration::seconds(1799))).is_due(Some(30), 3600, now));
+    assert!(activity(Some(30), Some(now - Duration::seconds(1800))).is_due(Some(30), 3600, now));
+    assert!(activity(Some(30), Some(now)).is_due(None, 3600, now));
+    // written before the time was recorded
+    let stored: Activity = serde_json::from_value(serde_json::json!({ "limit": 30 })).unwrap();
+    assert_eq!(stored, activity(Some(30), None));
+    assert!(stored.is_due(Some(30), 3600, now));
+    let stored = serde_json::to_value(activity(None, Some(now))).unwrap();
+    assert!(stored.get("recordedAt").is_some());
+}
@@ -0,0 +1,323 @@
+//! An offline stand-in for HTTP, shared by the integration tests.
+#![allow(dead_code)]
+use async_trait::async_trait;
+use atrium_api::xrpc::{HttpClient, XrpcClient};
+use http::{Request, Response, StatusCode};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+type Handler = dyn Fn(&Request<Vec<u8>>) -> Response<Vec<u8>> + Send + Sync;
+type Routes = Vec<(String, Arc<Handler>)>;
+
+/// An `HttpClient` answering from in-memory handlers, matched on host and path.
+///
+/// Requests without a route get a 404 with an XRPC error body.
+#[derive(Clone, Default)]
+pub struct MockHttpClient {
+    base_uri: String,
+    routes: Arc<Mutex<Routes>>,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+/// A recorded response, stored as `tests/fixtures/<name>.json`.
+#[derive(Debug, Deserialize)]
+struct Fixture {
+    /// The URL that was requested; the query string is ignored when matching.
+    url: String,
+    #[serde(default = "default_status")]
+    status: u16,
+    body: serde_json::Value,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+impl MockHttpClient {
+    /// A client whose XRPC requests go to `base_uri`.
+    pub fn new(base_uri: &str) -> Self {
+        Self {
+            base_uri: base_uri.into(),
+            ..Default::default()
+        }
+    }
+    /// Answer requests for `url` (host and path, e.g. `https://plc.directory/did:plc:x`).
+    pub fn route(
+        self,
+        url: &str,
+        handler: impl Fn(&Request<Vec<u8>>) -> Response<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        self.routes
+            .lock()
+            .unwrap()
+            .push((route_key(url), Arc::new(handler)));
+        self
+    }
+    /// Answer requests for `url` with `body` as JSON.
+    pub fn json(self, url: &str, status: u16, body: serde_json::Value) -> Self {
+        let body = serde_json::to_vec(&body).unwrap();
+        self.route(url, move |_| {
+            Response::builder()
+                .status(status)
+                .header("content-type", "application/json")
+                .body(body.clone())
+                .unwrap()
+        })
+    }
+    /// Answer with the recorded response in `tests/fixtures/<name>.json`.
+    pub fn fixture(self, name: &str) -> Self {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(format!("{name}.json"));
+        let fixture: Fixture = serde_json::from_slice(
+            &std::fs::read(&path).unwrap_or_else(|err| panic!("{}: {err}", path.display())),
+        )
+        .unwrap_or_else(|err| panic!("{}: {err}", path.display()));
+        self.json(&fixture.url, fixture.status, fixture.body)
+    }
+    /// Answer `app.bsky.feed.getPosts` with the views among `posts` that are asked for.
+    pub fn posts(self, posts: Vec<serde_json::Value>) -> Self {
+        let url = format!("{}/xrpc/app.bsky.feed.getPosts", self.base_uri);
+        self.route(&url, move |request| {
+            let uris = request
+                .uri()
+                .query()
+                .unwrap_or_default()
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .filter(|(key, _)| key.starts_with("uris"))
+                .map(|(_, uri)| percent_decode(uri))
+                .collect::<Vec<_>>();
+            let posts = posts
+                .iter()
+                .filter(|post| uris.iter().any(|uri| post["uri"] == uri.as_str()))
+                .collect::<Vec<_>>();
+            Response::builder()
+                .header("content-type", "application/json")
+                .body(serde_json::to_vec(&serde_json::json!({ "posts": posts })).unwrap())
+                .unwrap()
+        })
+    }
+    /// Answer `app.bsky.graph.getRelationships` with those of `relationships` (by `did`,
+    /// `app.bsky.graph.defs#relationship` unless typed otherwise) that are asked for, and
+    /// no relationship with the other accounts.
+    pub fn relationships(self, relationships: Vec<serde_json::Value>) -> Self {
+        let url = format!("{}/xrpc/app.bsky.graph.getRelationships", self.base_uri);
+        self.route(&url, move |request| {
+            let query = request.uri().query().unwrap_or_default();
+            let params = query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(key, value)| (key, percent_decode(value)));
+            let mut actor = None;
+            let mut items = Vec::new();
+            for (key, did) in params {
+                if key == "actor" {
+                    actor = Some(did);
+                    continue;
+                }
+                let mut item = relationships
+                    .iter()
+                    .find(|item| item["did"] == did.as_str())
+                    .cloned()
+                    .unwrap_or_else(|| serde_json::json!({ "did": did }));
+                if item.get("$type").is_none() {
+                    item["$type"] = "app.bsky.graph.defs#relationship".into();
+                }
+                items.push(item);
+            }
+            let body = serde_json::json!({ "actor": actor, "relationships": items });
+            Response::builder()
+                .header("content-type", "application/json")
+                .body(serde_json::to_vec(&body).unwrap())
+                .unwrap()
+        })
+    }
+    /// The URLs requested so far, with their query strings.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = match (byte, tail) {
+            (b'%', [hi, lo, tail @ ..]) => {
+                let hex = std::str::from_utf8(&[*hi, *lo]).unwrap().to_owned();
+                bytes.push(u8::from_str_radix(&hex, 16).unwrap());
+                tail
+            }
+            _ => {
+                bytes.push(byte);
+                tail
+            }
+        };
+    }
+    String::from_utf8(bytes).unwrap()
+}
+
+fn route_key(url: &str) -> String {
+    let url = url.split_once('?').map_or(url, |(path, _)| path);
+    url.split_once("://").map_or(url, |(_, rest)| rest).into()
+}
+
+#[async_trait]
+impl HttpClient for MockHttpClient {
+    async fn send_http(
+        &self,
+        request: Request<Vec<u8>>,
+    ) -> Result<Response<Vec<u8>>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let url = request.uri().to_string();
+        self.requests.lock().unwrap().push(url.clone());
+        let key = route_key(&url);
+        let handler = self
+            .routes
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|(route, _)| *route == key)
+            .map(|(_, handler)| handler.clone());
+        Ok(match handler {
+            Some(handler) => handler(&request),
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .header("content-type", "application/json")
+                .body(
+                    serde_json::to_vec(&serde_json::json!({
+                        "error": "NotFound",
+                        "message": format!("no route for {url}"),
+                    }))
+                    .unwrap(),
+                )?,
+        })
+    }
+}
+
+impl XrpcClient for MockHttpClient {
+    fn base_uri(&self) -> String {
+        self.base_uri.clone()
+    }
+}
+
+/// A secp256k1 key for signing test JWTs, with its `did:key`.
+pub struct TestKey {
+    pub signing_key: k256::ecdsa::SigningKey,
+    pub did_key: String,
+}
+
+impl TestKey {
+    pub fn new(seed: u8) -> Self {
+        let signing_key = k256::ecdsa::SigningKey::from_bytes(&[seed; 32].into()).unwrap();
+        let uncompressed = signing_key.verifying_key().to_encoded_point(false);
+        let did_key = bsky_timemachine::crypto::did::format_did_key(
+            bsky_timemachine::crypto::consts::JwtAlg::Secp256k1,
+            uncompressed.as_bytes(),
+        )
+        .unwrap();
+        Self {
+            signing_key,
+            did_key,
+        }
+    }
+    /// The `publicKeyMultibase` of the key, as published in DID documents.
+    pub fn multikey(&self) -> &str {
+        self.did_key.strip_prefix("did:key:").unwrap()
+    }
+    /// Sign an ES256K JWT with `payload`.
+    pub fn sign_jwt(&self, payload: serde_json::Value) -> String {
+        use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        use base64::Engine;
+        use k256::ecdsa::signature::Signer;
+        let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256K"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(payload.to_string());
+        let msg = format!("{header}.{payload}");
+        let signature: k256::ecdsa::Signature = self.signing_key.sign(msg.as_bytes());
+        format!("{msg}.{}", URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    }
+}
+
+/// A DID document for `did` with `key` as its atproto signing key.
+pub fn did_document(did: &str, handle: &str, key: &TestKey) -> serde_json::Value {
+    serde_json::json!({
+        "@context": [
+            "https://www.w3.org/ns/did/v1",
+            "https://w3id.org/security/multikey/v1",
+        ],
+        "id": did,
+        "alsoKnownAs": [format!("at://{handle}")],
+        "verificationMethod": [{
+            "id": format!("{did}#atproto"),
+            "type": "Multikey",
+            "controller": did,
+            "publicKeyMultibase": key.multikey(),
+        }],
+        "service": [{
+            "id": "#atproto_pds",
+            "type": "AtprotoPersonalDataServer",
+            "serviceEndpoint": "https://pds.example.com",
+        }],
+    })
+}
+
+/// An audit log entry of `did` for `operation` signed by `key`, addressed by its CID.
+pub fn signed_plc_entry(
+    did: &str,
+    mut operation: serde_json::Value,
+    key: &TestKey,
+) -> bsky_timemachine::identity::did::plc_operation::AuditLogEntry {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use bsky_timemachine::dag_cbor::{self, Cid, DAG_CBOR};
+    use k256::ecdsa::signature::Signer;
+    let signature: k256::ecdsa::Signature =
+        key.signing_key.sign(&dag_cbor::to_vec(&operation).unwrap());
+    operation["sig"] = serde_json::json!(URL_SAFE_NO_PAD.encode(signature.to_bytes()));
+    let cid = Cid::compute(DAG_CBOR, &dag_cbor::to_vec(&operation).unwrap());
+    serde_json::from_value(serde_json::json!({
+        "did": did,
+        "operation": operation,
+        "cid": cid.to_string(),
+        "nullified": false,
+        "createdAt": "2024-01-01T00:00:00.000Z",
+    }))
+    .unwrap()
+}
+
+/// A genesis entry rotated by `rotation_keys` and signed by `key`, for the DID it creates.
+pub fn signed_plc_genesis(
+    rotation_keys: &[&TestKey],
+    handle: &str,
+    key: &TestKey,
+) -> bsky_timemachine::identity::did::plc_operation::AuditLogEntry {
+    use bsky_timemachine::dag_cbor;
+    use bsky_timemachine::identity::did::plc_operation;
+    let mut entry = signed_plc_entry("", unsigned_plc_operation(rotation_keys, handle, None), key);
+    entry.did = plc_operation::genesis_did(&dag_cbor::to_vec(&entry.operation).unwrap());
+    entry
+}
+
+/// An unsigned `plc_operation` rotated by `rotation_keys`, the first of which is also the
+/// atproto signing key.
+pub fn unsigned_plc_operation(
+    rotation_keys: &[&TestKey],
+    handle: &str,
+    prev: Option<&str>,
+) -> serde_json::Value {
+    serde_json::json!({
+        "type": "plc_operation",
+        "rotationKeys": rotation_keys.iter().map(|key| &key.did_key).collect::<Vec<_>>(),
+        "verificationMethods": { "atproto": rotation_keys[0].did_key },
+        "alsoKnownAs": [format!("at://{handle}")],
+        "services": {
+            "atproto_pds": {
+                "type": "AtprotoPersonalDataServer",
+                "endpoint": "https://pds.example.com",
+            },
+        },
+        "prev": prev,
+    })
+}
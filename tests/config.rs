@@ -0,0 +1,265 @@
+mod common;
+
+use bsky_timemachine::config::{Config, Error};
+use bsky_timemachine::did_doc::ServiceIdentity;
+use bsky_timemachine::manifest::Manifest;
+use common::TestKey;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const MANIFEST: &str = r#"
+publisher = "did:plc:publisher"
+
+[[feeds]]
+rkey = "timemachine"
+displayName = "Time Machine"
+rule = { type = "offset", months = 6 }
+"#;
+
+/// A P-256 `did:key`, from the did:key method specification.
+const P256_DID_KEY: &str = "did:key:zDnaerDaTF5BXEavCrfRZEk316dpbLsfPDZ3WJ5hRTPFU2169";
+
+/// The minimal service configuration with `vars` added.
+fn source(vars: &[(&str, &str)]) -> HashMap<String, String> {
+    [
+        ("SERVICE_DID", "did:web:feeds.example.com"),
+        ("SERVICE_ENDPOINT", "https://feeds.example.com"),
+        ("FEEDS_MANIFEST", MANIFEST),
+    ]
+    .iter()
+    .chain(vars)
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect()
+}
+
+#[test]
+fn parses_verification_methods() {
+    let key = TestKey::new(1);
+    assert_eq!(
+        ServiceIdentity::parse_verification_methods(&format!(
+            " atproto = {}, atproto_label={},",
+            key.did_key, key.did_key
+        )),
+        Ok(vec![
+            (String::from("atproto"), key.did_key.clone()),
+            (String::from("atproto_label"), key.did_key.clone()),
+        ])
+    );
+    assert_eq!(
+        ServiceIdentity::parse_verification_methods(&format!("atproto={},label", key.did_key)),
+        Err(String::from("label"))
+    );
+    assert_eq!(
+        ServiceIdentity::parse_verification_methods("=did:key:z"),
+        Err(String::from("=did:key:z"))
+    );
+}
+
+#[test]
+fn loads_verification_methods_into_the_document() {
+    let key = TestKey::new(1);
+    let config = Config::load(&source(&[(
+        "SERVICE_VERIFICATION_METHODS",
+        &format!("atproto={}", key.did_key),
+    )]))
+    .unwrap();
+    let did_doc = config.service.document().unwrap();
+    let (key_type, multibase) = did_doc.get_verification_material("atproto").unwrap();
+    assert_eq!(key_type, "Multikey");
+    assert_eq!(multibase, key.multikey());
+}
+
+#[test]
+fn rejects_invalid_verification_methods() {
+    let key = TestKey::new(1);
+    let invalid =
+        |value: &str| match Config::load(&source(&[("SERVICE_VERIFICATION_METHODS", value)])) {
+            Err(Error::Invalid(key, reason)) => {
+                assert_eq!(key, "SERVICE_VERIFICATION_METHODS");
+                reason
+            }
+            other => panic!("expected an invalid config: {other:?}"),
+        };
+    let reason = invalid(&format!("atproto={},{}", key.did_key, key.did_key));
+    assert!(reason.contains(&key.did_key), "{reason}");
+    let reason = invalid("atproto=did:key:zNotAKey");
+    assert!(reason.contains("#atproto"), "{reason}");
+    // P-256 keys cannot be published in the DID document
+    let reason = invalid(&format!("atproto_label={P256_DID_KEY}"));
+    assert!(reason.contains("#atproto_label"), "{reason}");
+}
+
+/// The key that `vars` are rejected for.
+fn invalid_key(vars: &[(&str, &str)]) -> &'static str {
+    match Config::load(&source(vars)) {
+        Err(Error::Invalid(key, _)) => key,
+        other => panic!("expected an invalid config: {other:?}"),
+    }
+}
+
+#[test]
+fn loads_defaults() {
+    let config = Config::load(&source(&[])).unwrap();
+    assert_eq!(config.service.did, "did:web:feeds.example.com");
+    assert_eq!(config.upstream.appview_url, "https://api.bsky.app");
+    assert_eq!(config.upstream.plc_url, "https://plc.directory");
+    assert_eq!(config.resolver.max_document_size, Some(64 * 1024));
+    assert!(config.resolver.cache.is_none());
+    assert!(config.skeleton_cache.is_none());
+    assert!(config.index.is_none());
+    let config = Config::load(&source(&[
+        ("APPVIEW_URL", "http://localhost:2584/"),
+        ("DID_RESOLVER_TIMEOUT_MS", "0"),
+        ("HTTP_MAX_ATTEMPTS", "5"),
+    ]))
+    .unwrap();
+    assert_eq!(config.upstream.appview_url, "http://localhost:2584");
+    assert_eq!(config.resolver.timeout, None);
+    assert_eq!(config.upstream.retry.max_attempts, 5);
+    // repository downloads get longer than the upstream requests of a page
+    let config = Config::load(&source(&[("INDEX_D1", "INDEX")])).unwrap();
+    let index = config.index.unwrap();
+    assert_eq!(index.backfill_timeout, Duration::from_secs(120));
+    assert!(Some(index.backfill_timeout) > config.upstream.retry.deadline);
+}
+
+#[test]
+fn names_missing_keys() {
+    let mut vars = source(&[]);
+    vars.remove("SERVICE_ENDPOINT");
+    assert_eq!(
+        Config::load(&vars).unwrap_err(),
+        Error::Missing("SERVICE_ENDPOINT")
+    );
+    // empty is missing
+    vars.insert(String::from("SERVICE_DID"), String::new());
+    assert_eq!(
+        Config::load(&vars).unwrap_err(),
+        Error::Missing("SERVICE_DID")
+    );
+}
+
+#[test]
+fn names_keys_with_invalid_urls() {
+    assert_eq!(
+        invalid_key(&[("SERVICE_DID", "feeds.example.com")]),
+        "SERVICE_DID"
+    );
+    assert_eq!(
+        invalid_key(&[("SERVICE_ENDPOINT", "feeds.example.com")]),
+        "SERVICE_ENDPOINT"
+    );
+    assert_eq!(
+        invalid_key(&[("APPVIEW_URL", "ftp://api.bsky.app")]),
+        "APPVIEW_URL"
+    );
+    assert_eq!(invalid_key(&[("PLC_URL", "plc.directory")]), "PLC_URL");
+    assert_eq!(
+        invalid_key(&[
+            ("INDEX_D1", "INDEX"),
+            ("JETSTREAM_URL", "https://jetstream.example.com"),
+        ]),
+        "JETSTREAM_URL"
+    );
+}
+
+#[test]
+fn names_keys_with_invalid_numbers() {
+    assert_eq!(
+        invalid_key(&[("DID_RESOLVER_TIMEOUT_MS", "3s")]),
+        "DID_RESOLVER_TIMEOUT_MS"
+    );
+    assert_eq!(
+        invalid_key(&[("DID_MAX_DOCUMENT_SIZE", "-1")]),
+        "DID_MAX_DOCUMENT_SIZE"
+    );
+    assert_eq!(
+        invalid_key(&[("DID_CACHE_KV", "DID_CACHE"), ("DID_CACHE_TTL", "1h")]),
+        "DID_CACHE_TTL"
+    );
+    assert_eq!(
+        invalid_key(&[("HTTP_MAX_ATTEMPTS", "0")]),
+        "HTTP_MAX_ATTEMPTS"
+    );
+    assert_eq!(
+        invalid_key(&[("HTTP_MAX_ATTEMPTS", "many")]),
+        "HTTP_MAX_ATTEMPTS"
+    );
+    assert_eq!(
+        invalid_key(&[("HTTP_DEADLINE_MS", "1.5")]),
+        "HTTP_DEADLINE_MS"
+    );
+    assert_eq!(
+        invalid_key(&[("HTTP_CACHE_MIN_TTL", "app.bsky.feed.searchPosts=soon")]),
+        "HTTP_CACHE_MIN_TTL"
+    );
+    assert_eq!(
+        invalid_key(&[
+            ("SKELETON_CACHE_KV", "SKELETONS"),
+            ("SKELETON_ACTIVE_TTL", "30")
+        ]),
+        "SKELETON_ACTIVE_TTL"
+    );
+    assert_eq!(
+        invalid_key(&[("INDEX_D1", "INDEX"), ("INDEX_BACKFILL_LIMIT", "two")]),
+        "INDEX_BACKFILL_LIMIT"
+    );
+    assert_eq!(
+        invalid_key(&[
+            ("INDEX_D1", "INDEX"),
+            ("INDEX_BACKFILL_TIMEOUT_SECONDS", "2m")
+        ]),
+        "INDEX_BACKFILL_TIMEOUT_SECONDS"
+    );
+}
+
+#[test]
+fn requires_an_account_publisher_to_serve_feeds() {
+    let config = Config::load(&source(&[])).unwrap();
+    assert_eq!(config.publisher(), Ok("did:plc:publisher"));
+    // loads without one, so that the DID document is served, but serves no feeds
+    let without = MANIFEST.replace("publisher = \"did:plc:publisher\"", "");
+    let config = Config::load(&source(&[("FEEDS_MANIFEST", &without)])).unwrap();
+    assert_eq!(config.publisher(), Err(Error::Missing("publisher")));
+    assert!(config
+        .feed_by_uri("at://did:plc:publisher/app.bsky.feed.generator/timemachine")
+        .is_none());
+    let service = MANIFEST.replace("did:plc:publisher", "did:web:feeds.example.com");
+    assert_eq!(
+        invalid_key(&[("FEEDS_MANIFEST", &service)]),
+        "FEEDS_MANIFEST"
+    );
+    let replaced = Config::load(&source(&[]))
+        .unwrap()
+        .with_manifest(Manifest::parse(&service).unwrap(), "FEEDS_MANIFEST_KV");
+    assert!(matches!(
+        replaced,
+        Err(Error::Invalid("FEEDS_MANIFEST_KV", _))
+    ));
+}
+
+#[test]
+fn loads_the_embedded_manifest() {
+    let mut source = source(&[]);
+    source.remove("FEEDS_MANIFEST");
+    let config = Config::load(&source).unwrap();
+    assert_eq!(config.manifest, Manifest::embedded().unwrap());
+}
+
+#[test]
+fn routes_feed_uris_of_the_publisher() {
+    let config = Config::load(&source(&[])).unwrap();
+    let feed = config
+        .feed_by_uri("at://did:plc:publisher/app.bsky.feed.generator/timemachine")
+        .unwrap();
+    assert_eq!(feed.rkey, "timemachine");
+    for uri in [
+        "at://did:plc:someone/app.bsky.feed.generator/timemachine",
+        "at://did:web:feeds.example.com/app.bsky.feed.generator/timemachine",
+        "at://did:plc:publisher/app.bsky.feed.post/timemachine",
+        "at://did:plc:publisher/app.bsky.feed.generator/unknown",
+        "timemachine",
+    ] {
+        assert!(config.feed_by_uri(uri).is_none(), "{uri}");
+    }
+}
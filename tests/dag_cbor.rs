@@ -0,0 +1,218 @@
+mod common;
+
+use atrium_api::app::bsky::actor::profile;
+use atrium_api::app::bsky::feed::post;
+use bsky_timemachine::dag_cbor::{self, Cid, Value, DAG_CBOR, RAW};
+use bsky_timemachine::identity::did::plc_operation;
+use common::{signed_plc_entry, signed_plc_genesis, unsigned_plc_operation, TestKey};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+/// `{"a": 1, "c": h'000102', "l": <raw link>, "ab": "text", "bb": [true, null, -1, 1.5,
+/// 24, 1000000]}`, encoded with the reference algorithm.
+const VALUE: &str = "a5616101616343000102616cd82a5825000155122087bbe879c7a5f5784a70384bb49fa951\
+                     3a6a3fbe4c2d388635e3c87611c03fae626162647465787462626286f5f620fb3ff8000000\
+                     00000018181a000f4240";
+const VALUE_CID: &str = "bafyreibt52wwju64qbypfkibptmpwljjlvxmyeistvxtwvwzqxzvq4sdxm";
+const LINK: &str = "bafkreiehxpuhtr5f6v4eu4byjo2j7kkrhjvd7psmfu4imnpdzb3bdqb7vy";
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn computes_cids() {
+    assert_eq!(
+        Cid::compute(RAW, b"").to_string(),
+        "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+    );
+    assert_eq!(
+        Value::Map(BTreeMap::new()).cid().to_string(),
+        "bafyreigbtj4x7ip5legnfznufuopl4sg4knzc2cof6duas4b3q2fy6swua"
+    );
+    let hello = dag_cbor::to_vec(&json!({ "hello": "world" })).unwrap();
+    assert_eq!(hello, hex("a16568656c6c6f65776f726c64"));
+    let cid = Cid::compute(DAG_CBOR, &hello);
+    assert_eq!(
+        cid.to_string(),
+        "bafyreidykglsfhoixmivffc5uwhcgshx4j465xwqntbmu43nb2dzqwfvae"
+    );
+    assert_eq!((cid.version(), cid.codec()), (1, DAG_CBOR));
+}
+
+#[test]
+fn parses_cids() {
+    for s in [VALUE_CID, LINK] {
+        let cid = s.parse::<Cid>().unwrap();
+        assert_eq!(cid.to_string(), s);
+        assert_eq!(Cid::from_bytes(cid.as_bytes()).unwrap(), cid);
+    }
+    let v0 = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+        .parse::<Cid>()
+        .unwrap();
+    assert_eq!((v0.version(), v0.codec()), (0, 0x70));
+    assert_eq!(
+        v0.to_string(),
+        "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+    );
+    for invalid in [
+        "",
+        "zb2rhe5P4gXftAwvA4eXQ5HJwsER2owDyS9sKaQRRVQPn93bA",
+        "bafy",
+        "b!",
+    ] {
+        assert!(invalid.parse::<Cid>().is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn verifies_blocks() {
+    let block = dag_cbor::to_vec(&json!({ "hello": "world" })).unwrap();
+    let cid = Cid::compute(DAG_CBOR, &block);
+    cid.verify(&block).unwrap();
+    let mut tampered = block.clone();
+    tampered[12] ^= 1;
+    assert!(matches!(
+        cid.verify(&tampered),
+        Err(dag_cbor::Error::HashMismatch(mismatched)) if mismatched == cid
+    ));
+}
+
+#[test]
+fn round_trips_values() {
+    let bytes = hex(VALUE);
+    let value = dag_cbor::decode(&bytes).unwrap();
+    assert_eq!(value.get("a"), Some(&Value::Integer(1)));
+    assert_eq!(value.get("c"), Some(&Value::Bytes(vec![0, 1, 2])));
+    assert_eq!(value.get("l"), Some(&Value::Link(LINK.parse().unwrap())));
+    assert_eq!(
+        value.get("bb"),
+        Some(&Value::Array(vec![
+            Value::Bool(true),
+            Value::Null,
+            Value::Integer(-1),
+            Value::Float(1.5),
+            Value::Integer(24),
+            Value::Integer(1_000_000),
+        ]))
+    );
+    assert_eq!(dag_cbor::encode(&value), bytes);
+    assert_eq!(value.cid().to_string(), VALUE_CID);
+    let json = serde_json::Value::from(value.clone());
+    assert_eq!(json["l"], json!({ "$link": LINK }));
+    assert_eq!(json["c"], json!({ "$bytes": "AAEC" }));
+    assert_eq!(Value::try_from(json).unwrap(), value);
+}
+
+#[test]
+fn rejects_non_canonical_encodings() {
+    for (vector, reason) in [
+        ("1817", "integer with a longer length than needed"),
+        ("a2616201616101", "map keys out of order"),
+        ("a2616101616101", "duplicate map keys"),
+        ("a2626161016162", "longer key before a shorter one"),
+        ("9f01ff", "indefinite length"),
+        ("fa3fc00000", "32-bit float"),
+        ("fb7ff8000000000000", "NaN"),
+        ("f7", "undefined"),
+        ("c11a00000000", "tag other than 42"),
+        ("d82a4101", "link without the multibase prefix"),
+        ("a1010a", "integer map key"),
+        ("0001", "trailing bytes"),
+        ("62ff", "truncated string"),
+    ] {
+        assert!(dag_cbor::decode(&hex(vector)).is_err(), "{reason}");
+    }
+}
+
+#[test]
+fn encodes_typed_records() {
+    let record = serde_json::from_value::<post::Record>(json!({
+        "$type": "app.bsky.feed.post",
+        "text": "hello",
+        "createdAt": "2024-01-05T00:00:00.000Z",
+        "langs": ["en"],
+        "reply": {
+            "root": {
+                "uri": "at://did:plc:friend/app.bsky.feed.post/3kiakvpaaaa2a",
+                "cid": "bafyreibopuwahkkqplrgl3hvwu2wrbnfgoj2eau5eqjzjglsmwq2ewxpyy",
+            },
+            "parent": {
+                "uri": "at://did:plc:friend/app.bsky.feed.post/3kiakvpaaaa2a",
+                "cid": "bafyreibopuwahkkqplrgl3hvwu2wrbnfgoj2eau5eqjzjglsmwq2ewxpyy",
+            },
+        },
+    }))
+    .unwrap();
+    let block = dag_cbor::to_vec(&record).unwrap();
+    assert_eq!(
+        dag_cbor::from_slice::<post::Record>(&block).unwrap(),
+        record
+    );
+    // strong refs hold CIDs as strings, not links
+    let value = dag_cbor::decode(&block).unwrap();
+    assert!(matches!(
+        value
+            .get("reply")
+            .and_then(|reply| reply.get("root")?.get("cid")),
+        Some(Value::String(_))
+    ));
+
+    let profile = serde_json::from_value::<profile::Record>(json!({
+        "$type": "app.bsky.actor.profile",
+        "avatar": {
+            "$type": "blob",
+            "ref": { "$link": LINK },
+            "mimeType": "image/jpeg",
+            "size": 6,
+        },
+    }))
+    .unwrap();
+    let block = dag_cbor::to_vec(&profile).unwrap();
+    let value = dag_cbor::decode(&block).unwrap();
+    assert_eq!(
+        value.get("avatar").and_then(|avatar| avatar.get("ref")),
+        Some(&Value::Link(LINK.parse().unwrap()))
+    );
+    assert_eq!(
+        dag_cbor::from_slice::<profile::Record>(&block).unwrap(),
+        profile
+    );
+}
+
+#[test]
+fn verifies_audit_logs_signed_over_dag_cbor() {
+    let key = TestKey::new(7);
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &key);
+    let did = genesis.did.clone();
+    let update = signed_plc_entry(
+        &did,
+        unsigned_plc_operation(&[&key], "new.example.com", Some(&genesis.cid)),
+        &key,
+    );
+    let history =
+        plc_operation::verify_audit_log(&did, &[genesis.clone(), update.clone()]).unwrap();
+    assert_eq!(
+        history
+            .iter()
+            .map(|entry| (entry.cid.as_str(), entry.get_handle()))
+            .collect::<Vec<_>>(),
+        [
+            (genesis.cid.as_str(), Some(String::from("old.example.com"))),
+            (update.cid.as_str(), Some(String::from("new.example.com"))),
+        ]
+    );
+    // signed by a key that is not a rotation key
+    let forged = signed_plc_entry(
+        &did,
+        unsigned_plc_operation(&[&key], "forged.example.com", Some(&genesis.cid)),
+        &TestKey::new(8),
+    );
+    assert!(matches!(
+        plc_operation::verify_audit_log(&did, &[genesis, forged]),
+        Err(plc_operation::Error::InvalidSignature(_))
+    ));
+}
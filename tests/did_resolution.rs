@@ -0,0 +1,299 @@
+mod common;
+
+use bsky_timemachine::common_web::did_doc::DidDocument;
+use bsky_timemachine::identity::did::did_cache::{DidCache, NoCache};
+use bsky_timemachine::identity::did::did_resolver::{
+    self, DidResolver, DidResolverOptions, Error, Resolver,
+};
+use bsky_timemachine::identity::did::plc_operation::PlcHistoryEntry;
+use common::{
+    did_document, signed_plc_entry, signed_plc_genesis, unsigned_plc_operation, MockHttpClient,
+    TestKey,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const DID: &str = "did:plc:ewvi7nxzyoun6zhxrhs64oiz";
+
+#[tokio::test]
+async fn resolves_did_plc_from_fixture() {
+    let client = MockHttpClient::default().fixture("plc_did_document");
+    let resolver = DidResolver::new(client.clone(), "https://plc.directory");
+    let did_doc = resolver.ensure_resolve(DID, false).await.unwrap();
+    assert_eq!(did_doc.get_handle().as_deref(), Some("atproto.com"));
+    assert_eq!(
+        did_doc.get_pds_endpoint().as_deref(),
+        Some("https://enoki.us-east.host.bsky.network")
+    );
+    assert_eq!(
+        resolver.resolve_atproto_key(DID, false).await.unwrap(),
+        "did:key:zQ3shunBKsXixLxKtC5qeSG9E4J5RkGN57im31pcTzbNQnm5w"
+    );
+    // without a cache, every resolution goes to the directory
+    let url = format!("https://plc.directory/{DID}");
+    assert_eq!(client.requests(), [url.clone(), url]);
+}
+
+#[tokio::test]
+async fn missing_did_is_none() {
+    let resolver = DidResolver::new(MockHttpClient::default(), "https://plc.directory");
+    assert!(resolver.resolve(DID, false).await.unwrap().is_none());
+    assert!(matches!(
+        resolver.ensure_resolve(DID, false).await,
+        Err(Error::DidNotFoundError(did)) if did == DID
+    ));
+}
+
+#[tokio::test]
+async fn resolves_did_web() {
+    let key = TestKey::new(1);
+    let client = MockHttpClient::default()
+        .json(
+            "https://example.com/.well-known/did.json",
+            200,
+            did_document("did:web:example.com", "example.com", &key),
+        )
+        .json(
+            "http://localhost:3000/.well-known/did.json",
+            200,
+            did_document("did:web:localhost%3A3000", "localhost", &key),
+        );
+    let resolver = DidResolver::new(client.clone(), "https://plc.directory");
+    assert_eq!(
+        resolver
+            .resolve_atproto_key("did:web:example.com", false)
+            .await
+            .unwrap(),
+        key.did_key
+    );
+    assert!(resolver
+        .resolve("did:web:localhost%3A3000", false)
+        .await
+        .unwrap()
+        .is_some());
+    assert!(matches!(
+        resolver
+            .resolve("did:web:example.com:user:alice", false)
+            .await,
+        Err(Error::UnsupportedDidWebPath(_))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_hosts_and_documents_outside_the_options() {
+    let key = TestKey::new(1);
+    let client = MockHttpClient::default().json(
+        "https://example.com/.well-known/did.json",
+        200,
+        did_document("did:web:example.com", "example.com", &key),
+    );
+    let resolver = DidResolver::with_options(
+        client.clone(),
+        DidResolverOptions {
+            web_allowed_hosts: Some(vec![String::from("example.org")]),
+            ..Default::default()
+        },
+    );
+    assert!(matches!(
+        resolver.resolve("did:web:example.com", false).await,
+        Err(Error::DidWebHostNotAllowed(_))
+    ));
+    let resolver = DidResolver::with_options(
+        client,
+        DidResolverOptions {
+            max_document_size: Some(16),
+            cache: NoCache,
+            ..Default::default()
+        },
+    );
+    assert!(matches!(
+        resolver.resolve("did:web:example.com", false).await,
+        Err(Error::DocumentTooLarge(_, size)) if size > 16
+    ));
+}
+
+/// A `DidCache` in a map, to check what gets cached.
+#[derive(Default)]
+struct MapCache {
+    documents: Mutex<HashMap<String, DidDocument>>,
+    histories: Mutex<HashMap<String, Vec<PlcHistoryEntry>>>,
+}
+
+impl DidCache for MapCache {
+    async fn get(&self, did: &str) -> did_resolver::Result<Option<DidDocument>> {
+        Ok(self.documents.lock().unwrap().get(did).cloned())
+    }
+    async fn set(&self, did: &str, did_doc: &DidDocument) -> did_resolver::Result<()> {
+        self.documents
+            .lock()
+            .unwrap()
+            .insert(did.into(), did_doc.clone());
+        Ok(())
+    }
+    async fn clear(&self, did: &str) -> did_resolver::Result<()> {
+        self.documents.lock().unwrap().remove(did);
+        self.histories.lock().unwrap().remove(did);
+        Ok(())
+    }
+    async fn get_history(&self, did: &str) -> did_resolver::Result<Option<Vec<PlcHistoryEntry>>> {
+        Ok(self.histories.lock().unwrap().get(did).cloned())
+    }
+    async fn set_history(
+        &self,
+        did: &str,
+        history: &[PlcHistoryEntry],
+    ) -> did_resolver::Result<()> {
+        self.histories
+            .lock()
+            .unwrap()
+            .insert(did.into(), history.to_vec());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn caches_the_history() {
+    let key = TestKey::new(7);
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &key);
+    let did = genesis.did.as_str();
+    let mut update = signed_plc_entry(
+        did,
+        unsigned_plc_operation(&[&key], "new.example.com", Some(&genesis.cid)),
+        &key,
+    );
+    update.created_at = String::from("2024-03-01T00:00:00.000Z");
+    let log = serde_json::json!([
+        { "did": did, "operation": genesis.operation, "cid": genesis.cid,
+          "nullified": false, "createdAt": genesis.created_at },
+        { "did": did, "operation": update.operation, "cid": update.cid,
+          "nullified": false, "createdAt": update.created_at },
+    ]);
+    let url = format!("https://plc.directory/{did}/log/audit");
+    let client = MockHttpClient::default().json(&url, 200, log);
+    let DidResolverOptions {
+        plc_url,
+        web_allowed_hosts,
+        timeout,
+        max_document_size,
+        ..
+    } = DidResolverOptions::default();
+    let resolver = DidResolver::with_options(
+        client.clone(),
+        DidResolverOptions {
+            plc_url,
+            web_allowed_hosts,
+            timeout,
+            max_document_size,
+            cache: MapCache::default(),
+        },
+    );
+    let history = resolver.resolve_history(did).await.unwrap().unwrap();
+    assert_eq!(history.len(), 2);
+    let entry = resolver
+        .resolve_at(did, "2024-02-01T00:00:00Z".parse().unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(entry.get_handle().as_deref(), Some("old.example.com"));
+    // verified once, then read from the cache
+    assert_eq!(client.requests(), [url]);
+    assert!(resolver
+        .resolve_history("did:web:example.com")
+        .await
+        .unwrap()
+        .is_none());
+}
+
+/// A `DidCache` whose backend is down.
+struct FailingCache;
+
+impl DidCache for FailingCache {
+    async fn get(&self, _did: &str) -> did_resolver::Result<Option<DidDocument>> {
+        Err(Error::Cache(String::from("unavailable")))
+    }
+    async fn set(&self, _did: &str, _did_doc: &DidDocument) -> did_resolver::Result<()> {
+        Err(Error::Cache(String::from("unavailable")))
+    }
+    async fn clear(&self, _did: &str) -> did_resolver::Result<()> {
+        Err(Error::Cache(String::from("unavailable")))
+    }
+    async fn get_history(&self, _did: &str) -> did_resolver::Result<Option<Vec<PlcHistoryEntry>>> {
+        Err(Error::Cache(String::from("unavailable")))
+    }
+    async fn set_history(
+        &self,
+        _did: &str,
+        _history: &[PlcHistoryEntry],
+    ) -> did_resolver::Result<()> {
+        Err(Error::Cache(String::from("unavailable")))
+    }
+}
+
+#[tokio::test]
+async fn resolves_past_a_failing_cache() {
+    let key = TestKey::new(7);
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &key);
+    let did = genesis.did.as_str();
+    let log = serde_json::json!([
+        { "did": did, "operation": genesis.operation, "cid": genesis.cid,
+          "nullified": false, "createdAt": genesis.created_at },
+    ]);
+    let client = MockHttpClient::default().fixture("plc_did_document").json(
+        &format!("https://plc.directory/{did}/log/audit"),
+        200,
+        log,
+    );
+    let DidResolverOptions {
+        plc_url,
+        web_allowed_hosts,
+        timeout,
+        max_document_size,
+        ..
+    } = DidResolverOptions::default();
+    let resolver = DidResolver::with_options(
+        client,
+        DidResolverOptions {
+            plc_url,
+            web_allowed_hosts,
+            timeout,
+            max_document_size,
+            cache: FailingCache,
+        },
+    );
+    let did_doc = resolver.ensure_resolve(DID, false).await.unwrap();
+    assert_eq!(did_doc.get_handle().as_deref(), Some("atproto.com"));
+    assert!(resolver
+        .resolve("did:plc:missing", false)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        resolver.resolve_history(did).await.unwrap().unwrap().len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn limits_the_size_of_audit_logs() {
+    let key = TestKey::new(7);
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &key);
+    let did = genesis.did.as_str();
+    let log = serde_json::json!([
+        { "did": did, "operation": genesis.operation, "cid": genesis.cid,
+          "nullified": false, "createdAt": genesis.created_at },
+    ]);
+    let client =
+        MockHttpClient::default().json(&format!("https://plc.directory/{did}/log/audit"), 200, log);
+    let resolver = DidResolver::with_options(
+        client,
+        DidResolverOptions {
+            max_document_size: Some(64),
+            cache: NoCache,
+            ..Default::default()
+        },
+    );
+    assert!(matches!(
+        resolver.resolve_history(did).await,
+        Err(Error::DocumentTooLarge(too_large, size)) if too_large == did && size > 64
+    ));
+}
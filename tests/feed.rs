@@ -0,0 +1,300 @@
+mod common;
+
+use atrium_api::app::bsky::feed::defs::SkeletonFeedPost;
+use atrium_api::client::AtpServiceClient;
+use bsky_timemachine::identity::did::plc_operation;
+use bsky_timemachine::manifest::{Filter, Manifest, TimeRule};
+use bsky_timemachine::post_index::MemoryIndex;
+use bsky_timemachine::post_status::MemoryStatusCache;
+use bsky_timemachine::skeleton::{self, SkeletonQuery, TimeCursor};
+use chrono::{DateTime, Utc};
+use common::{signed_plc_genesis, MockHttpClient, TestKey};
+use serde_json::json;
+
+fn query(cursor: Option<&str>) -> SkeletonQuery {
+    SkeletonQuery {
+        did: String::from("did:plc:viewer"),
+        limit: Some(2.try_into().unwrap()),
+        cursor: cursor.map(String::from),
+        now: "2024-07-10T00:00:00Z".parse().unwrap(),
+        rule: TimeRule::Offset {
+            years: 0,
+            months: 6,
+            days: 0,
+        },
+        lang: None,
+    }
+}
+
+fn appview() -> MockHttpClient {
+    MockHttpClient::new("https://api.bsky.app")
+        .fixture("search_posts")
+        .fixture("get_posts")
+}
+
+fn time(s: &str) -> DateTime<Utc> {
+    s.parse().unwrap()
+}
+
+#[tokio::test]
+async fn searches_posts_before_the_offset() {
+    let client = appview();
+    let skeleton = skeleton::time_machine(&AtpServiceClient::new(client.clone()), query(None), &[])
+        .await
+        .unwrap();
+    assert_eq!(skeleton.cursor.as_deref(), Some("2"));
+    assert_eq!(
+        skeleton
+            .items
+            .iter()
+            .map(|item| item.created_at)
+            .collect::<Vec<_>>(),
+        [time("2024-01-01T00:00:00Z"), time("2023-12-20T12:00:00Z")]
+    );
+    let [request] = client.requests().try_into().unwrap();
+    assert!(request.contains("author=did%3Aplc%3Aviewer"), "{request}");
+    assert!(
+        request.contains("until=2024-01-10T00%3A00%3A00.000000Z"),
+        "{request}"
+    );
+}
+
+#[tokio::test]
+async fn filters_posts_by_metadata() {
+    let appview = AtpServiceClient::new(appview());
+    for (filters, expected) in [
+        (vec![Filter::NoReplies], vec!["3kiakvpp3bs2a"]),
+        (
+            vec![Filter::Links { exclude: false }],
+            vec!["3khqcdqcr2m2a"],
+        ),
+        (vec![Filter::Links { exclude: true }], vec!["3kiakvpp3bs2a"]),
+        (
+            vec![Filter::Lang {
+                langs: vec![String::from("ja")],
+            }],
+            vec!["3khqcdqcr2m2a"],
+        ),
+        (
+            vec![Filter::NoReplies, Filter::Media { exclude: false }],
+            vec![],
+        ),
+    ] {
+        let skeleton = skeleton::time_machine(&appview, query(None), &[])
+            .await
+            .unwrap();
+        let skeleton = skeleton::filter(&appview, &filters, skeleton)
+            .await
+            .unwrap();
+        assert_eq!(
+            skeleton
+                .items
+                .iter()
+                .map(|item| item.uri.rsplit('/').next().unwrap())
+                .collect::<Vec<_>>(),
+            expected,
+            "{filters:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn interleaves_reposts_chronologically() {
+    let pds = MockHttpClient::new("https://pds.example.com").fixture("list_reposts");
+    let skeleton = skeleton::time_machine_with_reposts(
+        &AtpServiceClient::new(appview()),
+        &AtpServiceClient::new(pds.clone()),
+        query(None),
+        &[],
+    )
+    .await
+    .unwrap();
+    let feed = skeleton
+        .items
+        .into_iter()
+        .map(SkeletonFeedPost::from)
+        .collect::<Vec<_>>();
+    assert_eq!(feed.len(), 2);
+    assert!(feed[0].post.ends_with("3kiakvpp3bs2a") && feed[0].reason.is_none());
+    assert!(feed[1].post.ends_with("3kiakvpaaaa2a"));
+    assert_eq!(
+        serde_json::to_value(&feed[1].reason).unwrap(),
+        serde_json::json!({
+            "$type": "app.bsky.feed.defs#skeletonReasonRepost",
+            "repost": "at://did:plc:viewer/app.bsky.feed.repost/3kiakvprrrr2a",
+        })
+    );
+    // the page is full, so it continues from the creation time of the last item
+    assert_eq!(
+        skeleton.cursor.as_deref(),
+        Some("2023-12-25T00:00:00.000000Z::at://did:plc:viewer/app.bsky.feed.repost/3kiakvprrrr2a")
+    );
+    let [request] = pds.requests().try_into().unwrap();
+    assert!(
+        request.contains("collection=app.bsky.feed.repost"),
+        "{request}"
+    );
+}
+
+#[tokio::test]
+async fn pages_through_posts_and_reposts_created_at_the_same_time() {
+    let at = "2023-12-25T00:00:00.000Z";
+    let post = "at://did:plc:viewer/app.bsky.feed.post/3kiakvpp3bs2a";
+    let repost = "at://did:plc:viewer/app.bsky.feed.repost/3kiakvprrrr2a";
+    let reposted = "at://did:plc:friend/app.bsky.feed.post/3kiakvpaaaa2a";
+    let cid = "bafyreibopuwahkkqplrgl3hvwu2wrbnfgoj2eau5eqjzjglsmwq2ewxpyy";
+    let appview = MockHttpClient::new("https://api.bsky.app").json(
+        "https://api.bsky.app/xrpc/app.bsky.feed.searchPosts",
+        200,
+        json!({ "posts": [{
+            "uri": post,
+            "cid": cid,
+            "author": { "did": "did:plc:viewer", "handle": "viewer.example.com" },
+            "record": { "$type": "app.bsky.feed.post", "text": "post", "createdAt": at },
+            "indexedAt": at,
+        }] }),
+    );
+    let pds = MockHttpClient::new("https://pds.example.com").json(
+        "https://pds.example.com/xrpc/com.atproto.repo.listRecords",
+        200,
+        json!({ "records": [{
+            "uri": repost,
+            "cid": cid,
+            "value": {
+                "$type": "app.bsky.feed.repost",
+                "subject": { "uri": reposted, "cid": cid },
+                "createdAt": at,
+            },
+        }] }),
+    );
+    let key = TestKey::new(7);
+    let mut genesis = signed_plc_genesis(&[&key], "viewer.example.com", &key);
+    genesis.created_at = String::from("2023-01-01T00:00:00.000Z");
+    let history = plc_operation::verify_audit_log(&genesis.did.clone(), &[genesis]).unwrap();
+    let (appview, pds) = (AtpServiceClient::new(appview), AtpServiceClient::new(pds));
+    let page = |cursor: Option<String>| {
+        let mut query = query(cursor.as_deref());
+        query.limit = Some(1.try_into().unwrap());
+        skeleton::time_machine_with_reposts(&appview, &pds, query, &history)
+    };
+    let first = page(None).await.unwrap();
+    assert_eq!(first.items[0].repost.as_deref(), Some(repost));
+    // the viewer's handle would read as the handle of the reposted author
+    assert_eq!(first.items[0].feed_context, None);
+    let cursor = first.cursor.unwrap();
+    assert_eq!(
+        cursor.parse::<TimeCursor>().unwrap(),
+        TimeCursor::at(time(at), repost)
+    );
+    // the post created at the same time is not skipped
+    let second = page(Some(cursor)).await.unwrap();
+    assert_eq!(second.items[0].uri, post);
+    assert_eq!(
+        second.items[0].feed_context.as_deref(),
+        Some("viewer.example.com")
+    );
+    let third = page(second.cursor).await.unwrap();
+    assert!(third.items.is_empty());
+    assert_eq!(third.cursor, None);
+}
+
+#[tokio::test]
+async fn lists_likes_before_the_offset() {
+    let pds = MockHttpClient::new("https://pds.example.com").fixture("list_likes");
+    let skeleton = skeleton::liked_back_then(&AtpServiceClient::new(pds.clone()), query(None))
+        .await
+        .unwrap();
+    // deleted posts are left for moderation to drop
+    assert_eq!(
+        skeleton
+            .items
+            .iter()
+            .map(|item| item.uri.as_str())
+            .collect::<Vec<_>>(),
+        [
+            "at://did:plc:friend/app.bsky.feed.post/3kiakvpaaaa2a",
+            "at://did:plc:friend/app.bsky.feed.post/3khqcdqdeleted",
+        ]
+    );
+    assert_eq!(skeleton.cursor.as_deref(), Some("3khqcdqbbbb2a"));
+    let [request] = pds.requests().try_into().unwrap();
+    assert!(
+        request.contains("collection=app.bsky.feed.like"),
+        "{request}"
+    );
+    // the first page starts at the TID of the offset
+    assert!(request.contains("cursor=3kilkifb22222"), "{request}");
+}
+
+#[tokio::test]
+async fn clamps_likes_cursor_to_the_offset() {
+    let pds = MockHttpClient::new("https://pds.example.com").fixture("list_likes");
+    for cursor in ["3zzzzzzzzzzzz", "3khqcdqbbbb2a"] {
+        skeleton::liked_back_then(&AtpServiceClient::new(pds.clone()), query(Some(cursor)))
+            .await
+            .unwrap();
+    }
+    let requests = pds.requests();
+    assert!(
+        requests[0].contains("cursor=3kilkifb22222"),
+        "{}",
+        requests[0]
+    );
+    assert!(
+        requests[1].contains("cursor=3khqcdqbbbb2a"),
+        "{}",
+        requests[1]
+    );
+}
+
+#[tokio::test]
+async fn assembles_feeds_from_their_source() {
+    let manifest = Manifest::parse(
+        r#"
+        [[feeds]]
+        rkey = "liked-back-then"
+        displayName = "What I liked back then"
+        rule = { type = "offset", months = 6 }
+        source = "likes"
+        "#,
+    )
+    .unwrap();
+    let client = appview().relationships(Vec::new());
+    let pds =
+        AtpServiceClient::new(MockHttpClient::new("https://pds.example.com").fixture("list_likes"));
+    let upstream = skeleton::Upstream {
+        appview: &AtpServiceClient::new(client.clone()),
+        relationships: &client,
+        pds: Some(&pds),
+        index: None::<&MemoryIndex>,
+        statuses: &MemoryStatusCache::new(16),
+    };
+    let mut query = query(None);
+    query.limit = Some(1.try_into().unwrap());
+    let skeleton = skeleton::assemble(&upstream, &manifest.feeds[0], query, &[])
+        .await
+        .unwrap();
+    // moderated, so the deleted post is left out and the others are hydrated
+    assert_eq!(
+        skeleton
+            .items
+            .iter()
+            .map(|item| item.uri.as_str())
+            .collect::<Vec<_>>(),
+        ["at://did:plc:friend/app.bsky.feed.post/3kiakvpaaaa2a"]
+    );
+    assert!(skeleton.items[0].meta.is_some());
+    assert_eq!(skeleton.cursor.as_deref(), Some("3khqcdqbbbb2a"));
+    // looked up once, by moderation
+    let requests = client.requests();
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|request| request.contains("app.bsky.feed.getPosts"))
+            .count(),
+        1
+    );
+    assert!(requests
+        .iter()
+        .any(|request| request.contains("app.bsky.graph.getRelationships")));
+}
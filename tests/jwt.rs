@@ -0,0 +1,109 @@
+mod common;
+
+use bsky_timemachine::auth::{self, verify_jwt, JwtError, SigningKeyProvider};
+use bsky_timemachine::identity::did::did_resolver::{DidResolver, Resolver};
+use chrono::{Duration, Utc};
+use common::{did_document, MockHttpClient, TestKey};
+use serde_json::json;
+
+const ISS: &str = "did:plc:viewer";
+const AUD: &str = "did:web:feed.example.com";
+const LXM: &str = "app.bsky.feed.getFeedSkeleton";
+
+struct KeyProvider(DidResolver<MockHttpClient>);
+
+impl SigningKeyProvider for KeyProvider {
+    async fn get_signing_key(&self, iss: &str, force_refresh: bool) -> auth::Result<String> {
+        self.0
+            .resolve_atproto_key(iss, force_refresh)
+            .await
+            .map_err(auth::Error::DidResolver)
+    }
+}
+
+fn key_provider(key: &TestKey) -> KeyProvider {
+    let client = MockHttpClient::default().json(
+        &format!("https://plc.directory/{ISS}"),
+        200,
+        did_document(ISS, "viewer.example.com", key),
+    );
+    KeyProvider(DidResolver::new(client, "https://plc.directory"))
+}
+
+fn payload(aud: &str, exp: i64) -> serde_json::Value {
+    json!({ "iss": ISS, "aud": aud, "exp": exp, "lxm": LXM })
+}
+
+fn in_a_minute() -> i64 {
+    (Utc::now() + Duration::minutes(1)).timestamp()
+}
+
+#[tokio::test]
+async fn verifies_jwt_signed_by_the_issuer() {
+    let key = TestKey::new(1);
+    let jwt = key.sign_jwt(payload(AUD, in_a_minute()));
+    let verified = verify_jwt(&jwt, Some(AUD), Some(LXM), key_provider(&key))
+        .await
+        .unwrap();
+    assert_eq!(verified.iss, ISS);
+    assert_eq!(verified.lxm.as_deref(), Some(LXM));
+}
+
+#[tokio::test]
+async fn rejects_jwt_signed_by_another_key() {
+    let jwt = TestKey::new(2).sign_jwt(payload(AUD, in_a_minute()));
+    assert!(matches!(
+        verify_jwt(&jwt, Some(AUD), None, key_provider(&TestKey::new(1))).await,
+        Err(auth::Error::Crypto(_))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_expired_jwt() {
+    let key = TestKey::new(1);
+    let jwt = key.sign_jwt(payload(
+        AUD,
+        (Utc::now() - Duration::minutes(1)).timestamp(),
+    ));
+    assert!(matches!(
+        verify_jwt(&jwt, Some(AUD), None, key_provider(&key)).await,
+        Err(auth::Error::AuthRequiredError(JwtError::Expired, _))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_jwt_for_another_service_or_method() {
+    let key = TestKey::new(1);
+    let jwt = key.sign_jwt(payload("did:web:other.example.com", in_a_minute()));
+    assert!(matches!(
+        verify_jwt(&jwt, Some(AUD), None, key_provider(&key)).await,
+        Err(auth::Error::AuthRequiredError(JwtError::BadAudience, _))
+    ));
+    let jwt = key.sign_jwt(payload(AUD, in_a_minute()));
+    assert!(matches!(
+        verify_jwt(
+            &jwt,
+            Some(AUD),
+            Some("app.bsky.feed.getFeed"),
+            key_provider(&key)
+        )
+        .await,
+        Err(auth::Error::AuthRequiredError(
+            JwtError::BadLexiconMethod,
+            _
+        ))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_jwt_of_unknown_issuer() {
+    let jwt = TestKey::new(1).sign_jwt(payload(AUD, in_a_minute()));
+    let provider = KeyProvider(DidResolver::new(
+        MockHttpClient::default(),
+        "https://plc.directory",
+    ));
+    assert!(matches!(
+        verify_jwt(&jwt, Some(AUD), None, provider).await,
+        Err(auth::Error::DidResolver(_))
+    ));
+}
@@ -0,0 +1,313 @@
+mod common;
+
+use atrium_api::client::AtpServiceClient;
+use bsky_timemachine::manifest::Manifest;
+use bsky_timemachine::moderation::{self, Moderation, Relationship, Relationships};
+use bsky_timemachine::post_status::{MemoryStatusCache, PostStatus, PostStatusCache};
+use bsky_timemachine::skeleton::{self, FeedItem};
+use common::MockHttpClient;
+use serde_json::{json, Value};
+
+const CID: &str = "bafyreibopuwahkkqplrgl3hvwu2wrbnfgoj2eau5eqjzjglsmwq2ewxpyy";
+
+fn uri(did: &str, rkey: &str) -> String {
+    format!("at://{did}/app.bsky.feed.post/{rkey}")
+}
+
+fn label(uri: &str, val: &str, neg: bool) -> Value {
+    json!({
+        "src": "did:plc:labeler",
+        "uri": uri,
+        "val": val,
+        "neg": neg,
+        "cts": "2024-02-01T00:00:00.000Z",
+    })
+}
+
+/// A post view of `did`, with `record` and `extra` merged into the defaults.
+fn view(did: &str, rkey: &str, record: Value, extra: Value) -> Value {
+    let mut view = json!({
+        "uri": uri(did, rkey),
+        "cid": CID,
+        "author": { "did": did, "handle": "someone.example.com" },
+        "record": {
+            "$type": "app.bsky.feed.post",
+            "text": rkey,
+            "createdAt": "2024-01-01T00:00:00.000Z",
+        },
+        "indexedAt": "2024-01-01T00:00:01.000Z",
+    });
+    for (key, value) in record.as_object().unwrap() {
+        view["record"][key] = value.clone();
+    }
+    for (key, value) in extra.as_object().unwrap() {
+        view[key] = value.clone();
+    }
+    view
+}
+
+fn reply_to(parent: &str) -> Value {
+    let parent = json!({ "uri": parent, "cid": CID });
+    json!({ "reply": { "root": parent, "parent": parent } })
+}
+
+fn item(uri: &str) -> FeedItem {
+    FeedItem {
+        uri: uri.into(),
+        created_at: "2024-01-01T00:00:00Z".parse().unwrap(),
+        feed_context: None,
+        repost: None,
+        meta: None,
+    }
+}
+
+fn kept(items: &[FeedItem]) -> Vec<&str> {
+    items.iter().map(|item| item.uri.as_str()).collect()
+}
+
+#[tokio::test]
+async fn drops_labeled_posts() {
+    let plain = uri("did:plc:alice", "plain");
+    let porn = uri("did:plc:alice", "porn");
+    let negated = uri("did:plc:alice", "negated");
+    let hidden_author = uri("did:plc:hidden", "post");
+    let client = MockHttpClient::new("https://api.bsky.app")
+        .relationships(Vec::new())
+        .posts(vec![
+            view("did:plc:alice", "plain", json!({}), json!({})),
+            view(
+                "did:plc:alice",
+                "porn",
+                json!({}),
+                json!({ "labels": [label(&porn, "porn", false)] }),
+            ),
+            view(
+                "did:plc:alice",
+                "negated",
+                json!({}),
+                json!({ "labels": [label(&negated, "porn", true)] }),
+            ),
+            view(
+                "did:plc:hidden",
+                "post",
+                json!({}),
+                json!({ "author": {
+                "did": "did:plc:hidden",
+                "handle": "hidden.example.com",
+                "labels": [label("did:plc:hidden", "!hide", false)],
+            } }),
+            ),
+        ]);
+    let appview = AtpServiceClient::new(client.clone());
+    let cache = MemoryStatusCache::new(16);
+    let items = || {
+        [&plain, &porn, &negated, &hidden_author]
+            .map(|uri| item(uri))
+            .to_vec()
+    };
+    let kept_items = skeleton::moderate(
+        &appview,
+        &client,
+        &cache,
+        &moderation::default_labels(),
+        "did:plc:viewer",
+        items(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(kept(&kept_items), [plain.as_str(), negated.as_str()]);
+    // the labels are cached, so feeds hiding other labels need no getPosts
+    let requests = client.requests().len();
+    let kept_items = skeleton::moderate(
+        &appview,
+        &client,
+        &cache,
+        &[String::from("!hide")],
+        "did:plc:viewer",
+        items(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        kept(&kept_items),
+        [plain.as_str(), porn.as_str(), negated.as_str()]
+    );
+    // only the blocks, which depend on the viewer
+    assert_eq!(client.requests().len(), requests + 1);
+}
+
+#[tokio::test]
+async fn drops_blocked_posts_and_threads() {
+    let plain = uri("did:plc:alice", "plain");
+    let blocked_by = uri("did:plc:blocker", "post");
+    let quote = uri("did:plc:alice", "quote");
+    let reply = uri("did:plc:alice", "reply");
+    let parent = uri("did:plc:blocked", "parent");
+    let listed = uri("did:plc:listed", "post");
+    // as served without the viewer's credentials, with no viewer state
+    let client = MockHttpClient::new("https://api.bsky.app")
+        .posts(vec![
+            view("did:plc:alice", "plain", json!({}), json!({})),
+            view("did:plc:blocker", "post", json!({}), json!({})),
+            view(
+                "did:plc:alice",
+                "quote",
+                json!({}),
+                json!({ "embed": {
+                    "$type": "app.bsky.embed.record#view",
+                    "record": {
+                        "$type": "app.bsky.embed.record#viewRecord",
+                        "uri": blocked_by,
+                        "cid": CID,
+                        "author": { "did": "did:plc:blocker", "handle": "blocker.example.com" },
+                        "value": {
+                            "$type": "app.bsky.feed.post",
+                            "text": "post",
+                            "createdAt": "2024-01-01T00:00:00.000Z",
+                        },
+                        "indexedAt": "2024-01-01T00:00:01.000Z",
+                    },
+                } }),
+            ),
+            view("did:plc:alice", "reply", reply_to(&parent), json!({})),
+            view("did:plc:listed", "post", json!({}), json!({})),
+        ])
+        .relationships(vec![
+            json!({
+                "did": "did:plc:blocker",
+                "blockedBy": "at://did:plc:blocker/app.bsky.graph.block/3kiablockaaa2",
+            }),
+            json!({
+                "did": "did:plc:blocked",
+                "blocking": "at://did:plc:viewer/app.bsky.graph.block/3kiablockaaa2",
+            }),
+            json!({
+                "did": "did:plc:listed",
+                "blockingByList": "at://did:plc:viewer/app.bsky.graph.list/3kialistaaaa2",
+            }),
+            json!({
+                "did": "did:plc:alice",
+                "following": "at://did:plc:viewer/app.bsky.graph.follow/3kiafollowaa2",
+            }),
+        ]);
+    let appview = AtpServiceClient::new(client.clone());
+    let cache = MemoryStatusCache::new(16);
+    let items = || {
+        [&plain, &blocked_by, &quote, &reply, &listed]
+            .map(|uri| item(uri))
+            .to_vec()
+    };
+    let kept_items = skeleton::moderate(&appview, &client, &cache, &[], "did:plc:viewer", items())
+        .await
+        .unwrap();
+    assert_eq!(kept(&kept_items), [plain.as_str()]);
+    // the thread is known from the reply's record, so only its authors are looked up
+    let requests = client.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].contains("app.bsky.graph.getRelationships?actor=did"));
+    // the cached statuses hold no viewer state
+    let statuses = cache.get(std::slice::from_ref(&reply)).await.unwrap();
+    assert_eq!(
+        statuses[&reply],
+        PostStatus::Live(Moderation {
+            labels: Vec::new(),
+            authors: vec![
+                String::from("did:plc:alice"),
+                String::from("did:plc:blocked")
+            ],
+        })
+    );
+    // so another viewer without those blocks sees all of them without looking them up
+    let relationships = MockHttpClient::new("https://api.bsky.app").relationships(Vec::new());
+    let kept_items = skeleton::moderate(
+        &appview,
+        &relationships,
+        &cache,
+        &[],
+        "did:plc:other",
+        items(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(kept_items.len(), 5);
+    assert_eq!(client.requests().len(), 2);
+    assert_eq!(relationships.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn keeps_only_own_posts_when_blocks_are_unknown() {
+    let own = uri("did:plc:viewer", "own");
+    let other = uri("did:plc:alice", "other");
+    let reply = uri("did:plc:viewer", "reply");
+    // getRelationships is not answered
+    let client = MockHttpClient::new("https://api.bsky.app").posts(vec![
+        view("did:plc:viewer", "own", json!({}), json!({})),
+        view("did:plc:alice", "other", json!({}), json!({})),
+        view("did:plc:viewer", "reply", reply_to(&other), json!({})),
+    ]);
+    let appview = AtpServiceClient::new(client.clone());
+    let cache = MemoryStatusCache::new(16);
+    let items = [&own, &other, &reply].map(|uri| item(uri)).to_vec();
+    let items = skeleton::moderate(&appview, &client, &cache, &[], "did:plc:viewer", items)
+        .await
+        .unwrap();
+    assert_eq!(kept(&items), [own.as_str()]);
+}
+
+#[test]
+fn reads_blocks_from_relationships() {
+    let relationships: Relationships = serde_json::from_value(json!({
+        "actor": "did:plc:viewer",
+        "relationships": [
+            {
+                "$type": "app.bsky.graph.defs#relationship",
+                "did": "did:plc:blocker",
+                "blockedByList": "at://did:plc:list/app.bsky.graph.list/3kialistaaaa2",
+            },
+            {
+                "$type": "app.bsky.graph.defs#relationship",
+                "did": "did:plc:friend",
+                "following": "at://did:plc:viewer/app.bsky.graph.follow/3kiafollowaa2",
+                "followedBy": "at://did:plc:friend/app.bsky.graph.follow/3kiafollowaa2",
+            },
+            {
+                "$type": "app.bsky.graph.defs#notFoundActor",
+                "actor": "did:plc:gone",
+                "notFound": true,
+            },
+        ],
+    }))
+    .unwrap();
+    assert_eq!(
+        relationships
+            .relationships
+            .iter()
+            .map(Relationship::blocked)
+            .collect::<Vec<_>>(),
+        [Some("did:plc:blocker"), None, None]
+    );
+}
+
+#[test]
+fn configures_labels_per_feed() {
+    let manifest = Manifest::parse(
+        r#"
+        [[feeds]]
+        rkey = "default"
+        displayName = "Default"
+        rule = { type = "offset", months = 6 }
+
+        [[feeds]]
+        rkey = "custom"
+        displayName = "Custom"
+        rule = { type = "offset", months = 6 }
+        labels = ["!hide", "spam"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        manifest.feed("default").unwrap().labels,
+        moderation::default_labels()
+    );
+    assert_eq!(manifest.feed("custom").unwrap().labels, ["!hide", "spam"]);
+}
@@ -0,0 +1,223 @@
+mod common;
+
+use bsky_timemachine::dag_cbor::{self, Cid, DAG_CBOR};
+use bsky_timemachine::identity::did::plc_operation::{
+    self, AuditLogEntry, Error, PlcHistoryEntry, Result,
+};
+use common::{signed_plc_entry, signed_plc_genesis, unsigned_plc_operation, TestKey};
+use serde_json::json;
+
+fn entry(
+    rotation_keys: &[&TestKey],
+    handle: &str,
+    prev: &AuditLogEntry,
+    signer: &TestKey,
+) -> AuditLogEntry {
+    signed_plc_entry(
+        &prev.did,
+        unsigned_plc_operation(rotation_keys, handle, Some(&prev.cid)),
+        signer,
+    )
+}
+
+/// Verify `log` as the audit log of the DID of its first entry.
+fn verify(log: &[AuditLogEntry]) -> Result<Vec<PlcHistoryEntry>> {
+    plc_operation::verify_audit_log(&log[0].did, log)
+}
+
+fn handles(log: &[AuditLogEntry]) -> Vec<Option<String>> {
+    verify(log)
+        .unwrap()
+        .iter()
+        .map(|entry| entry.get_handle())
+        .collect()
+}
+
+#[test]
+fn verifies_a_valid_chain() {
+    let key = TestKey::new(7);
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &key);
+    let mut update = entry(&[&key], "new.example.com", &genesis, &key);
+    update.created_at = String::from("2024-03-01T00:00:00.000Z");
+    let history = verify(&[genesis, update]).unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[1].get_signing_key(), Some(key.did_key.clone()));
+    assert_eq!(
+        history[1].get_pds_endpoint().as_deref(),
+        Some("https://pds.example.com")
+    );
+    let handle_at = |at: &str| {
+        plc_operation::entry_at(&history, at.parse().unwrap()).and_then(|entry| entry.get_handle())
+    };
+    assert_eq!(handle_at("2023-12-31T00:00:00Z"), None);
+    assert_eq!(
+        handle_at("2024-02-01T00:00:00Z").as_deref(),
+        Some("old.example.com")
+    );
+    assert_eq!(
+        handle_at("2024-03-01T00:00:00Z").as_deref(),
+        Some("new.example.com")
+    );
+}
+
+#[test]
+fn rejects_bad_signatures() {
+    let key = TestKey::new(7);
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &key);
+    // signed by a key that is not a rotation key
+    let forged = entry(&[&key], "forged.example.com", &genesis, &TestKey::new(8));
+    assert!(matches!(
+        verify(&[genesis.clone(), forged.clone()]),
+        Err(Error::InvalidSignature(cid)) if cid == forged.cid
+    ));
+    // changed after signing, and addressed by its new CID
+    let mut tampered = entry(&[&key], "new.example.com", &genesis, &key);
+    tampered.operation["alsoKnownAs"] = json!(["at://tampered.example.com"]);
+    tampered.cid =
+        Cid::compute(DAG_CBOR, &dag_cbor::to_vec(&tampered.operation).unwrap()).to_string();
+    assert!(matches!(
+        verify(&[genesis, tampered.clone()]),
+        Err(Error::InvalidSignature(cid)) if cid == tampered.cid
+    ));
+    // a genesis operation must be signed by one of its own rotation keys
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &TestKey::new(8));
+    assert!(matches!(
+        verify(&[genesis]),
+        Err(Error::InvalidSignature(_))
+    ));
+}
+
+#[test]
+fn rejects_prev_mismatches() {
+    let key = TestKey::new(7);
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &key);
+    let update = entry(&[&key], "new.example.com", &genesis, &key);
+    let other = entry(&[&key], "other.example.com", &update, &key);
+    // skips `update`
+    let skipping = entry(&[&key], "skipping.example.com", &genesis, &key);
+    assert!(matches!(
+        verify(&[genesis.clone(), update.clone(), skipping.clone()]),
+        Err(Error::MisorderedOperation(cid)) if cid == skipping.cid
+    ));
+    // out of order
+    assert!(matches!(
+        verify(&[genesis.clone(), other, update.clone()]),
+        Err(Error::MisorderedOperation(_))
+    ));
+    // a log must start with a genesis operation
+    assert!(matches!(
+        verify(std::slice::from_ref(&update)),
+        Err(Error::InvalidGenesis(cid)) if cid == update.cid
+    ));
+}
+
+#[test]
+fn follows_rotation_key_changes() {
+    let (old, new) = (TestKey::new(7), TestKey::new(8));
+    let genesis = signed_plc_genesis(&[&old], "old.example.com", &old);
+    // signed by the old key, handing over to the new one
+    let rotation = entry(&[&new], "rotated.example.com", &genesis, &old);
+    let by_new = entry(&[&new], "new.example.com", &rotation, &new);
+    assert_eq!(
+        handles(&[genesis.clone(), rotation.clone(), by_new]),
+        [
+            Some(String::from("old.example.com")),
+            Some(String::from("rotated.example.com")),
+            Some(String::from("new.example.com")),
+        ]
+    );
+    // the old key no longer rotates
+    let by_old = entry(&[&old], "reverted.example.com", &rotation, &old);
+    assert!(matches!(
+        verify(&[genesis, rotation, by_old.clone()]),
+        Err(Error::InvalidSignature(cid)) if cid == by_old.cid
+    ));
+}
+
+#[test]
+fn skips_nullified_operations() {
+    let (key, recovery) = (TestKey::new(7), TestKey::new(8));
+    let genesis = signed_plc_genesis(&[&recovery, &key], "old.example.com", &recovery);
+    // overridden by the recovery key, which forks the log at `genesis`
+    let mut nullified = entry(&[&key], "hijacked.example.com", &genesis, &key);
+    nullified.nullified = true;
+    let recovered = entry(&[&recovery], "new.example.com", &genesis, &recovery);
+    assert_eq!(
+        handles(&[genesis.clone(), nullified.clone(), recovered.clone()]),
+        [
+            Some(String::from("old.example.com")),
+            Some(String::from("new.example.com")),
+        ]
+    );
+    // not skipped, it breaks the chain
+    nullified.nullified = false;
+    assert!(matches!(
+        verify(&[genesis, nullified, recovered.clone()]),
+        Err(Error::MisorderedOperation(cid)) if cid == recovered.cid
+    ));
+}
+
+#[test]
+fn rejects_operations_after_a_tombstone() {
+    let key = TestKey::new(7);
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &key);
+    let tombstone = signed_plc_entry(
+        &genesis.did,
+        json!({ "type": "plc_tombstone", "prev": genesis.cid }),
+        &key,
+    );
+    let history = verify(&[genesis.clone(), tombstone.clone()]).unwrap();
+    assert!(history[1].data.is_none());
+    let revived = entry(&[&key], "new.example.com", &tombstone, &key);
+    assert!(matches!(
+        verify(&[genesis, tombstone, revived]),
+        Err(Error::AfterTombstone(_))
+    ));
+}
+
+#[test]
+fn rejects_entries_that_do_not_match_their_cid() {
+    let key = TestKey::new(7);
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &key);
+    let update = entry(&[&key], "new.example.com", &genesis, &key);
+    // reported with the CID of another operation
+    let mut misaddressed = genesis.clone();
+    misaddressed.cid = update.cid.clone();
+    assert!(matches!(
+        verify(&[misaddressed]),
+        Err(Error::InvalidCid(cid)) if cid == update.cid
+    ));
+    let mut misaddressed = update.clone();
+    misaddressed.cid = genesis.cid.clone();
+    assert!(matches!(
+        verify(&[genesis.clone(), misaddressed]),
+        Err(Error::InvalidCid(cid)) if cid == genesis.cid
+    ));
+    let mut malformed = update;
+    malformed.cid = String::from("not a cid");
+    assert!(matches!(
+        verify(&[genesis, malformed]),
+        Err(Error::InvalidCid(_))
+    ));
+}
+
+#[test]
+fn rejects_a_genesis_that_does_not_create_the_did() {
+    let key = TestKey::new(7);
+    let genesis = signed_plc_genesis(&[&key], "old.example.com", &key);
+    assert!(genesis.did.starts_with("did:plc:"));
+    assert_eq!(genesis.did.len(), "did:plc:".len() + 24);
+    assert_eq!(verify(std::slice::from_ref(&genesis)).unwrap().len(), 1);
+    assert!(matches!(
+        plc_operation::verify_audit_log("did:plc:viewer", std::slice::from_ref(&genesis)),
+        Err(Error::InvalidGenesis(cid)) if cid == genesis.cid
+    ));
+    // a history made up by a mirror, chained and signed with its own keys
+    let mirror = TestKey::new(9);
+    let forged = signed_plc_genesis(&[&mirror], "forged.example.com", &mirror);
+    let update = entry(&[&mirror], "new.example.com", &forged, &mirror);
+    assert!(matches!(
+        plc_operation::verify_audit_log(&genesis.did, &[forged.clone(), update]),
+        Err(Error::InvalidGenesis(cid)) if cid == forged.cid
+    ));
+}
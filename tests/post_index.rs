@@ -0,0 +1,389 @@
+mod common;
+
+use atrium_api::client::AtpServiceClient;
+use bsky_timemachine::backfill;
+use bsky_timemachine::jetstream::{self, IngestStats};
+use bsky_timemachine::manifest::{Filter, Manifest, Source, TimeRule};
+use bsky_timemachine::post_index::{
+    Change, IndexedRecord, MemoryIndex, PendingBackfill, PostIndex, RecordKind,
+    MAX_BACKFILL_ATTEMPTS,
+};
+use bsky_timemachine::post_status::MemoryStatusCache;
+use bsky_timemachine::skeleton::{self, SkeletonQuery, TimeCursor};
+use bsky_timemachine::sqlite_index::SqliteIndex;
+use chrono::{DateTime, Utc};
+use common::MockHttpClient;
+use std::path::Path;
+
+const VIEWER: &str = "did:plc:viewer";
+const FRIEND_POST: &str = "at://did:plc:friend/app.bsky.feed.post/3kiakvpaaaa2a";
+
+async fn replayed<I: PostIndex>(index: I) -> I {
+    jetstream::ingest(&index, replay().lines()).await.unwrap();
+    index
+}
+
+fn sqlite() -> SqliteIndex {
+    SqliteIndex::in_memory().unwrap()
+}
+
+fn replay() -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/jetstream.jsonl");
+    std::fs::read_to_string(path).unwrap()
+}
+
+fn post(rkey: &str) -> String {
+    format!("at://{VIEWER}/app.bsky.feed.post/{rkey}")
+}
+
+fn query(limit: Option<u8>, cursor: Option<&str>) -> SkeletonQuery {
+    SkeletonQuery {
+        did: String::from(VIEWER),
+        limit: limit.map(|limit| limit.try_into().unwrap()),
+        cursor: cursor.map(String::from),
+        now: "2024-07-10T00:00:00Z".parse().unwrap(),
+        rule: TimeRule::Offset {
+            years: 0,
+            months: 6,
+            days: 0,
+        },
+        lang: None,
+    }
+}
+
+fn time(s: &str) -> DateTime<Utc> {
+    s.parse().unwrap()
+}
+
+/// Records created before `s`.
+fn before(s: &str) -> TimeCursor {
+    TimeCursor::at(time(s), "")
+}
+
+#[tokio::test]
+async fn replays_jetstream_events() {
+    replays_jetstream_events_into(MemoryIndex::default()).await;
+    replays_jetstream_events_into(sqlite()).await;
+}
+
+async fn replays_jetstream_events_into<I: PostIndex>(index: I) {
+    let stats = jetstream::ingest(&index, replay().lines()).await.unwrap();
+    assert_eq!(
+        stats,
+        IngestStats {
+            events: 13,
+            changes: 11,
+            invalid: 1,
+        }
+    );
+    assert_eq!(index.cursor().await.unwrap(), Some(1704412800000013));
+    let records = index
+        .records(
+            VIEWER,
+            &RecordKind::ALL,
+            &before("2024-01-10T00:00:00Z"),
+            10,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        records
+            .iter()
+            .map(|record| (record.kind, record.created_at))
+            .collect::<Vec<_>>(),
+        [
+            (RecordKind::Post, time("2024-01-05T00:00:00Z")),
+            (RecordKind::Repost, time("2024-01-04T00:00:00Z")),
+            (RecordKind::Post, time("2024-01-03T00:00:00Z")),
+            (RecordKind::Like, time("2024-01-02T00:00:00Z")),
+            (RecordKind::Like, time("2024-01-01T00:00:00Z")),
+            (RecordKind::Post, time("2023-12-31T00:00:00Z")),
+        ]
+    );
+    // the account deletion removed the records of did:plc:gone
+    assert!(index
+        .records(
+            "did:plc:gone",
+            &RecordKind::ALL,
+            &TimeCursor::at(Utc::now(), ""),
+            10,
+        )
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn indexes_post_metadata() {
+    indexes_post_metadata_in(MemoryIndex::default()).await;
+    indexes_post_metadata_in(sqlite()).await;
+}
+
+async fn indexes_post_metadata_in<I: PostIndex>(index: I) {
+    let index = replayed(index).await;
+    let records = index
+        .records(
+            VIEWER,
+            &[RecordKind::Post],
+            &before("2024-01-04T00:00:00Z"),
+            10,
+        )
+        .await
+        .unwrap();
+    let [reply, link] = records.try_into().unwrap();
+    assert_eq!(reply.uri, post("3kialpost3aaa"));
+    let meta = reply.meta.unwrap();
+    assert!(meta.is_reply && !meta.has_links);
+    assert_eq!(meta.langs, ["ja"]);
+    assert!(link.meta.unwrap().has_links);
+}
+
+#[tokio::test]
+async fn pages_through_posts_and_reposts() {
+    pages_through_posts_and_reposts_of(MemoryIndex::default()).await;
+    pages_through_posts_and_reposts_of(sqlite()).await;
+}
+
+async fn pages_through_posts_and_reposts_of<I: PostIndex>(index: I) {
+    let index = replayed(index).await;
+    let client = MockHttpClient::new("https://api.bsky.app");
+    let appview = AtpServiceClient::new(client.clone());
+    let page = skeleton::indexed(&index, Source::SearchPosts, true, query(Some(2), None), &[])
+        .await
+        .unwrap();
+    assert_eq!(
+        page.items
+            .iter()
+            .map(|item| (item.uri.as_str(), item.repost.is_some()))
+            .collect::<Vec<_>>(),
+        [(post("3kialpost5aaa").as_str(), false), (FRIEND_POST, true)]
+    );
+    assert_eq!(
+        page.cursor.as_deref(),
+        Some(
+            "2024-01-04T00:00:00.000000Z::\
+             at://did:plc:viewer/app.bsky.feed.repost/3kialrepost4a"
+        )
+    );
+    let page = skeleton::indexed(
+        &index,
+        Source::SearchPosts,
+        true,
+        query(Some(2), page.cursor.as_deref()),
+        &[],
+    )
+    .await
+    .unwrap();
+    let page = skeleton::filter(&appview, &[Filter::NoReplies], page)
+        .await
+        .unwrap();
+    assert_eq!(
+        page.items
+            .iter()
+            .map(|item| item.uri.clone())
+            .collect::<Vec<_>>(),
+        [post("3kialpost2aaa")]
+    );
+    // posts carry their metadata, so filtering needs no requests
+    assert!(client.requests().is_empty());
+}
+
+#[tokio::test]
+async fn pages_through_records_created_at_the_same_time() {
+    pages_through_records_created_at_the_same_time_in(MemoryIndex::default()).await;
+    pages_through_records_created_at_the_same_time_in(sqlite()).await;
+}
+
+async fn pages_through_records_created_at_the_same_time_in<I: PostIndex>(index: I) {
+    let index = replayed(index).await;
+    let changes = ["3kialpost6aaa", "3kialpost7aaa", "3kialpost8aaa"]
+        .into_iter()
+        .map(|rkey| {
+            Change::Put(IndexedRecord {
+                uri: post(rkey),
+                did: String::from(VIEWER),
+                kind: RecordKind::Post,
+                created_at: time("2024-01-06T00:00:00Z"),
+                subject: None,
+                meta: None,
+            })
+        })
+        .collect::<Vec<_>>();
+    index.apply(&changes, None).await.unwrap();
+    let mut cursor = None;
+    let mut uris = Vec::new();
+    for _ in 0..2 {
+        let page = skeleton::indexed(
+            &index,
+            Source::SearchPosts,
+            false,
+            query(Some(2), cursor.as_deref()),
+            &[],
+        )
+        .await
+        .unwrap();
+        uris.extend(page.items.into_iter().map(|item| item.uri));
+        cursor = page.cursor;
+    }
+    assert_eq!(
+        uris,
+        [
+            post("3kialpost8aaa"),
+            post("3kialpost7aaa"),
+            post("3kialpost6aaa"),
+            post("3kialpost5aaa"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn migrates_sqlite_indexes_once() {
+    let path = std::env::temp_dir().join(format!("post-index-{}.sqlite", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    let index = replayed(SqliteIndex::open(&path).unwrap()).await;
+    index.request_backfill(VIEWER).await.unwrap();
+    drop(index);
+    // reopening applies no migration twice and keeps the records
+    let index = SqliteIndex::open(&path).unwrap();
+    assert_eq!(index.cursor().await.unwrap(), Some(1704412800000013));
+    assert_eq!(
+        index.pending_backfills(Utc::now(), 10).await.unwrap(),
+        [PendingBackfill {
+            did: String::from(VIEWER),
+            attempts: 0,
+        }]
+    );
+    let records = index
+        .records(
+            VIEWER,
+            &RecordKind::ALL,
+            &before("2024-01-10T00:00:00Z"),
+            10,
+        )
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 6);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn retries_failed_backfills_with_backoff() {
+    retries_failed_backfills_with_backoff_in(MemoryIndex::default()).await;
+    retries_failed_backfills_with_backoff_in(sqlite()).await;
+}
+
+async fn retries_failed_backfills_with_backoff_in<I: PostIndex>(index: I) {
+    let dids = |pending: Vec<PendingBackfill>| {
+        pending
+            .into_iter()
+            .map(|pending| (pending.did, pending.attempts))
+            .collect::<Vec<_>>()
+    };
+    let now = time("2024-01-10T00:00:00Z");
+    for did in ["did:plc:a", "did:plc:b", "did:plc:c"] {
+        index.request_backfill(did).await.unwrap();
+    }
+    index
+        .record_backfill_attempt("did:plc:a", now, backfill::retry_at(1, now))
+        .await
+        .unwrap();
+    let later = now + chrono::Duration::minutes(10);
+    index
+        .record_backfill_attempt("did:plc:b", later, later)
+        .await
+        .unwrap();
+    // the never attempted first, then the least recently attempted, once due
+    assert_eq!(
+        dids(index.pending_backfills(later, 10).await.unwrap()),
+        [
+            (String::from("did:plc:c"), 0),
+            (String::from("did:plc:b"), 1)
+        ]
+    );
+    let due = backfill::retry_at(1, now);
+    assert_eq!(
+        dids(index.pending_backfills(due, 2).await.unwrap()),
+        [
+            (String::from("did:plc:c"), 0),
+            (String::from("did:plc:a"), 1)
+        ]
+    );
+    // requesting the backfill again keeps its attempts
+    index.request_backfill("did:plc:a").await.unwrap();
+    for attempt in 2..=MAX_BACKFILL_ATTEMPTS {
+        index
+            .record_backfill_attempt("did:plc:a", due, due)
+            .await
+            .unwrap();
+        let pending = dids(index.pending_backfills(due, 10).await.unwrap());
+        let a = (String::from("did:plc:a"), attempt);
+        assert_eq!(
+            pending.contains(&a),
+            attempt < MAX_BACKFILL_ATTEMPTS,
+            "{attempt}"
+        );
+    }
+    index.complete_backfill("did:plc:c", "rev").await.unwrap();
+    assert_eq!(
+        dids(index.pending_backfills(due, 10).await.unwrap()),
+        [(String::from("did:plc:b"), 1)]
+    );
+}
+
+#[test]
+fn backs_off_exponentially() {
+    let at = time("2024-01-10T00:00:00Z");
+    let delays = (1..=4)
+        .map(|attempt| (backfill::retry_at(attempt, at) - at).num_hours())
+        .collect::<Vec<_>>();
+    assert_eq!(delays, [1, 2, 4, 8]);
+    assert!(backfill::retry_at(u32::MAX, at) > at);
+}
+
+#[tokio::test]
+async fn skips_deleted_posts_among_likes() {
+    let index = replayed(MemoryIndex::default()).await;
+    let manifest = Manifest::parse(
+        r#"
+        [[feeds]]
+        rkey = "liked-back-then"
+        displayName = "What I liked back then"
+        rule = { type = "offset", months = 6 }
+        source = "likes"
+        index = true
+        "#,
+    )
+    .unwrap();
+    let client = MockHttpClient::new("https://api.bsky.app")
+        .fixture("get_posts")
+        .relationships(Vec::new());
+    let upstream = skeleton::Upstream {
+        appview: &AtpServiceClient::new(client.clone()),
+        relationships: &client,
+        pds: None::<&AtpServiceClient<MockHttpClient>>,
+        index: Some(&index),
+        statuses: &MemoryStatusCache::new(16),
+    };
+    let page = skeleton::assemble(&upstream, &manifest.feeds[0], query(None, None), &[])
+        .await
+        .unwrap();
+    let [item] = page.items.try_into().unwrap();
+    assert_eq!(item.uri, FRIEND_POST);
+    assert_eq!(item.created_at, time("2024-01-02T00:00:00Z"));
+    assert!(item.meta.is_some());
+    assert_eq!(page.cursor, None);
+}
+
+#[test]
+fn subscribes_shortly_before_the_cursor() {
+    let url = jetstream::subscribe_url("wss://jetstream.example.com/", Some(1704412800000000));
+    assert_eq!(
+        url,
+        "wss://jetstream.example.com/subscribe?wantedCollections=app.bsky.feed.post\
+         &wantedCollections=app.bsky.feed.repost&wantedCollections=app.bsky.feed.like\
+         &cursor=1704412795000000&requireHello=true"
+    );
+    let update = jetstream::options_update(&[String::from(VIEWER)]);
+    assert_eq!(update["type"], "options_update");
+    assert_eq!(update["payload"]["wantedDids"][0], VIEWER);
+}
@@ -0,0 +1,335 @@
+mod common;
+
+use async_trait::async_trait;
+use atrium_api::client::AtpServiceClient;
+use bsky_timemachine::manifest::{Filter, TimeRule};
+use bsky_timemachine::moderation::Moderation;
+use bsky_timemachine::post_status::{self, MemoryStatusCache, PostStatus, PostStatusCache};
+use bsky_timemachine::skeleton::{self, FeedItem, Skeleton, SkeletonQuery};
+use chrono::{DateTime, TimeDelta, Utc};
+use common::MockHttpClient;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const VIEWER: &str = "did:plc:viewer";
+const CID: &str = "bafyreibopuwahkkqplrgl3hvwu2wrbnfgoj2eau5eqjzjglsmwq2ewxpyy";
+
+fn post(n: usize) -> String {
+    format!("at://{VIEWER}/app.bsky.feed.post/3kiapost{n:02}aaa")
+}
+
+/// An AppView that knows the posts of the first `count` except `deleted`, the ones in
+/// `replies` being replies.
+fn appview(count: usize, deleted: &[usize], replies: &[usize]) -> MockHttpClient {
+    let posts = (0..count)
+        .filter(|n| !deleted.contains(n))
+        .map(|n| {
+            let mut record = json!({
+                "$type": "app.bsky.feed.post",
+                "text": format!("post {n}"),
+                "createdAt": "2024-01-01T00:00:00.000Z",
+            });
+            if replies.contains(&n) {
+                let parent = json!({ "uri": post(count), "cid": CID });
+                record["reply"] = json!({ "root": parent, "parent": parent });
+            }
+            json!({
+                "uri": post(n),
+                "cid": CID,
+                "author": { "did": VIEWER, "handle": "viewer.example.com" },
+                "record": record,
+                "indexedAt": "2024-01-01T00:00:01.000Z",
+            })
+        })
+        .collect::<Vec<_>>();
+    MockHttpClient::new("https://api.bsky.app").posts(posts)
+}
+
+fn item(n: usize) -> FeedItem {
+    FeedItem {
+        uri: post(n),
+        created_at: time() - TimeDelta::hours(n as i64),
+        feed_context: None,
+        repost: None,
+        meta: None,
+    }
+}
+
+fn time() -> DateTime<Utc> {
+    "2024-01-01T00:00:00Z".parse().unwrap()
+}
+
+fn query(limit: u8) -> SkeletonQuery {
+    SkeletonQuery {
+        did: String::from(VIEWER),
+        limit: Some(limit.try_into().unwrap()),
+        cursor: None,
+        now: "2024-07-01T00:00:00Z".parse().unwrap(),
+        rule: TimeRule::Offset {
+            years: 0,
+            months: 6,
+            days: 0,
+        },
+        lang: None,
+    }
+}
+
+/// Pages over the first `count` posts, with the offset of the next page as the cursor,
+/// recording the cursor and limit of every page that is built.
+struct Pages {
+    count: usize,
+    built: Mutex<Vec<(Option<String>, u8)>>,
+}
+
+impl Pages {
+    fn new(count: usize) -> Self {
+        Self {
+            count,
+            built: Mutex::default(),
+        }
+    }
+    fn build(
+        &self,
+        query: SkeletonQuery,
+    ) -> impl std::future::Future<Output = skeleton::Result<Skeleton>> {
+        let limit = query.limit.map_or(50, u8::from);
+        self.built
+            .lock()
+            .unwrap()
+            .push((query.cursor.clone(), limit));
+        let start = query.cursor.map_or(0, |cursor| cursor.parse().unwrap());
+        let end = self.count.min(start + usize::from(limit));
+        let skeleton = Skeleton {
+            items: (start..end).map(item).collect(),
+            cursor: (end < self.count).then(|| end.to_string()),
+        };
+        async move { Ok(skeleton) }
+    }
+    fn built(&self) -> Vec<(Option<String>, u8)> {
+        self.built.lock().unwrap().clone()
+    }
+}
+
+fn uris(skeleton: &Skeleton) -> Vec<String> {
+    skeleton.items.iter().map(|item| item.uri.clone()).collect()
+}
+
+#[tokio::test]
+async fn drops_deleted_posts_and_remembers_them() {
+    let client = appview(4, &[1, 3], &[]);
+    let appview = AtpServiceClient::new(client.clone());
+    let cache = MemoryStatusCache::new(16);
+    let items = skeleton::moderate(
+        &appview,
+        &client,
+        &cache,
+        &[],
+        VIEWER,
+        (0..4).map(item).collect(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        items
+            .iter()
+            .map(|item| item.uri.clone())
+            .collect::<Vec<_>>(),
+        [post(0), post(2)]
+    );
+    assert!(items.iter().all(|item| item.meta.is_some()));
+    assert_eq!(client.requests().len(), 1);
+    let statuses = cache.get(&[post(0), post(1)]).await.unwrap();
+    assert_eq!(
+        statuses[&post(0)],
+        PostStatus::Live(Moderation {
+            labels: Vec::new(),
+            authors: vec![String::from(VIEWER)],
+        })
+    );
+    assert_eq!(statuses[&post(1)], PostStatus::Gone);
+    // known statuses are not looked up again
+    let items = skeleton::moderate(
+        &appview,
+        &client,
+        &cache,
+        &[],
+        VIEWER,
+        (0..4).map(item).collect(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(client.requests().len(), 1);
+}
+
+/// A `PostStatusCache` that remembers nothing, recording what is put.
+#[derive(Default)]
+struct Recording(Mutex<Vec<(String, PostStatus, Duration)>>);
+
+#[async_trait]
+impl PostStatusCache for Recording {
+    async fn get(&self, _: &[String]) -> post_status::Result<HashMap<String, PostStatus>> {
+        Ok(HashMap::new())
+    }
+    async fn put(&self, uri: &str, status: PostStatus, ttl: Duration) -> post_status::Result<()> {
+        self.0.lock().unwrap().push((uri.into(), status, ttl));
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn remembers_missing_posts_briefly() {
+    let client = appview(2, &[1], &[]);
+    let appview = AtpServiceClient::new(client.clone());
+    let cache = Recording::default();
+    skeleton::moderate(
+        &appview,
+        &client,
+        &cache,
+        &[],
+        VIEWER,
+        (0..2).map(item).collect(),
+    )
+    .await
+    .unwrap();
+    let put = cache.0.into_inner().unwrap();
+    let (_, status, ttl) = put.iter().find(|(uri, ..)| *uri == post(1)).unwrap();
+    assert_eq!(*status, PostStatus::Gone);
+    // the AppView may only be unable to serve it for now
+    assert!(*ttl <= Duration::from_secs(10 * 60), "{ttl:?}");
+}
+
+#[tokio::test]
+async fn serves_unverified_posts_when_they_cannot_be_looked_up() {
+    // every request fails
+    let client = MockHttpClient::new("https://api.bsky.app");
+    let appview = AtpServiceClient::new(client.clone());
+    let cache = MemoryStatusCache::new(16);
+    cache
+        .put(&post(1), PostStatus::Gone, Duration::from_secs(60))
+        .await
+        .unwrap();
+    let items = skeleton::moderate(
+        &appview,
+        &client,
+        &cache,
+        &[],
+        VIEWER,
+        (0..3).map(item).collect(),
+    )
+    .await
+    .unwrap();
+    // known statuses still apply
+    assert_eq!(
+        items
+            .iter()
+            .map(|item| item.uri.clone())
+            .collect::<Vec<_>>(),
+        [post(0), post(2)]
+    );
+    assert!(items.iter().all(|item| item.meta.is_none()));
+    assert_eq!(client.requests().len(), 1);
+    // and unverified ones are looked up again next time
+    assert!(cache.get(&[post(0), post(2)]).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn fills_pages_up_to_the_limit() {
+    let client = appview(20, &[1, 3, 4], &[6]);
+    let appview = AtpServiceClient::new(client.clone());
+    let cache = MemoryStatusCache::new(64);
+    let pages = Pages::new(20);
+    let skeleton = skeleton::fill(
+        &appview,
+        &client,
+        &cache,
+        &[],
+        &[Filter::NoReplies],
+        query(5),
+        |query| pages.build(query),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        uris(&skeleton),
+        [post(0), post(2), post(5), post(7), post(8)]
+    );
+    // the missing items are asked for from where the previous page ended
+    assert_eq!(
+        pages.built(),
+        [
+            (None, 5),
+            (Some(String::from("5")), 3),
+            (Some(String::from("8")), 1)
+        ]
+    );
+    assert_eq!(skeleton.cursor.as_deref(), Some("9"));
+}
+
+#[tokio::test]
+async fn stops_at_the_last_page() {
+    let client = appview(4, &[1, 3], &[]);
+    let appview = AtpServiceClient::new(client.clone());
+    let cache = MemoryStatusCache::new(16);
+    let pages = Pages::new(4);
+    let skeleton = skeleton::fill(&appview, &client, &cache, &[], &[], query(3), |query| {
+        pages.build(query)
+    })
+    .await
+    .unwrap();
+    assert_eq!(uris(&skeleton), [post(0), post(2)]);
+    assert_eq!(skeleton.cursor, None);
+    assert_eq!(pages.built().len(), 2);
+}
+
+#[tokio::test]
+async fn expires_statuses() {
+    let cache = MemoryStatusCache::new(2);
+    cache
+        .put(&post(0), PostStatus::Gone, Duration::ZERO)
+        .await
+        .unwrap();
+    cache
+        .put(
+            &post(1),
+            PostStatus::Live(Default::default()),
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+    cache
+        .put(
+            &post(2),
+            PostStatus::Live(Default::default()),
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+    let statuses = cache.get(&[post(0), post(1), post(2)]).await.unwrap();
+    assert_eq!(statuses.len(), 2);
+    assert!(!statuses.contains_key(&post(0)));
+}
+
+#[tokio::test]
+async fn evicts_the_least_recently_used_status() {
+    let cache = MemoryStatusCache::new(2);
+    let ttl = Duration::from_secs(60);
+    for n in 0..2 {
+        cache.put(&post(n), PostStatus::Gone, ttl).await.unwrap();
+    }
+    cache.get(&[post(0)]).await.unwrap();
+    cache.put(&post(2), PostStatus::Gone, ttl).await.unwrap();
+    let statuses = cache.get(&[post(0), post(1), post(2)]).await.unwrap();
+    assert!(statuses.contains_key(&post(0)) && statuses.contains_key(&post(2)));
+    assert!(!statuses.contains_key(&post(1)));
+    // replacing a status evicts nothing
+    cache
+        .put(&post(2), PostStatus::Live(Default::default()), ttl)
+        .await
+        .unwrap();
+    let statuses = cache.get(&[post(0), post(2)]).await.unwrap();
+    assert_eq!(statuses.len(), 2);
+    assert_eq!(statuses[&post(2)], PostStatus::Live(Default::default()));
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use atrium_api::client::AtpServiceClient;
+use bsky_timemachine::backfill::{self, BackfillStats};
+use bsky_timemachine::dag_cbor;
+use bsky_timemachine::post_index::{MemoryIndex, PostIndex, RecordKind};
+use bsky_timemachine::repo::{self, Repo};
+use bsky_timemachine::skeleton::TimeCursor;
+use chrono::Utc;
+use common::{MockHttpClient, TestKey};
+use http::Response;
+use std::path::Path;
+
+const VIEWER: &str = "did:plc:viewer";
+const REV: &str = "3kilkifb22222";
+
+/// A repository of `VIEWER` signed by `TestKey::new(7)`, holding a profile, a follow,
+/// a repost, a like and 43 posts, one of them without `createdAt`.
+fn car() -> Vec<u8> {
+    std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/repo.car")).unwrap()
+}
+
+/// The DIDs whose backfill is due now.
+async fn pending<I: PostIndex>(index: &I) -> Vec<String> {
+    let pending = index.pending_backfills(Utc::now(), 10).await.unwrap();
+    pending.into_iter().map(|pending| pending.did).collect()
+}
+
+fn pds(car: Vec<u8>) -> AtpServiceClient<MockHttpClient> {
+    AtpServiceClient::new(MockHttpClient::new("https://pds.example.com").route(
+        "https://pds.example.com/xrpc/com.atproto.sync.getRepo",
+        move |_| {
+            Response::builder()
+                .header("content-type", "application/vnd.ipld.car")
+                .body(car.clone())
+                .unwrap()
+        },
+    ))
+}
+
+#[test]
+fn reads_the_repository() {
+    let repo = Repo::from_car(&car()).unwrap();
+    assert_eq!(repo.did, VIEWER);
+    assert_eq!(repo.rev, REV);
+    repo.verify_signature(&TestKey::new(7).did_key).unwrap();
+    let entries = repo.entries().unwrap();
+    assert_eq!(entries.len(), 47);
+    assert!(entries.windows(2).all(|pair| pair[0].key < pair[1].key));
+    let profile = &entries[0];
+    assert_eq!(
+        (profile.collection(), profile.rkey()),
+        ("app.bsky.actor.profile", "self")
+    );
+    let record = repo.record(&profile.cid).unwrap();
+    assert_eq!(record["displayName"], "Viewer");
+    // a raw CID, rendered as in JSON
+    assert!(record["avatar"]["ref"]["$link"]
+        .as_str()
+        .unwrap()
+        .starts_with("bafkrei"));
+}
+
+#[test]
+fn rejects_other_signing_keys() {
+    let repo = Repo::from_car(&car()).unwrap();
+    assert!(matches!(
+        repo.verify_signature(&TestKey::new(8).did_key),
+        Err(repo::Error::InvalidSignature(did)) if did == VIEWER
+    ));
+}
+
+#[test]
+fn rejects_tampered_blocks() {
+    let mut car = car();
+    let at = car
+        .windows(REV.len())
+        .position(|window| window == REV.as_bytes())
+        .unwrap();
+    car[at + REV.len() - 1] = b'3';
+    assert!(matches!(
+        Repo::from_car(&car),
+        Err(repo::Error::DagCbor(dag_cbor::Error::HashMismatch(_)))
+    ));
+}
+
+#[test]
+fn rejects_truncated_files() {
+    let car = car();
+    assert!(matches!(
+        Repo::from_car(&car[..car.len() - 10]),
+        Err(repo::Error::Car(_))
+    ));
+    assert!(Repo::from_car(&car[..100]).is_err());
+}
+
+#[tokio::test]
+async fn backfills_the_index() {
+    let index = MemoryIndex::default();
+    index.request_backfill(VIEWER).await.unwrap();
+    assert_eq!(index.tracked().await.unwrap(), [VIEWER]);
+    assert_eq!(pending(&index).await, [VIEWER]);
+    let stats = backfill::backfill(&pds(car()), &index, VIEWER, &TestKey::new(7).did_key)
+        .await
+        .unwrap();
+    assert_eq!(
+        stats,
+        BackfillStats {
+            rev: String::from(REV),
+            records: 47,
+            indexed: 44,
+            invalid: 1,
+        }
+    );
+    assert!(pending(&index).await.is_empty());
+    let records = index
+        .records(
+            VIEWER,
+            &RecordKind::ALL,
+            &TimeCursor::at(Utc::now(), ""),
+            100,
+        )
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 44);
+    let reply = records
+        .iter()
+        .find(|record| record.uri == format!("at://{VIEWER}/app.bsky.feed.post/3kagpost2aaaa"))
+        .unwrap();
+    assert_eq!(reply.created_at.to_rfc3339(), "2023-09-02T00:00:00+00:00");
+    assert!(reply.meta.as_ref().unwrap().is_reply);
+    let like = records
+        .iter()
+        .find(|record| record.kind == RecordKind::Like)
+        .unwrap();
+    assert_eq!(
+        like.subject.as_deref(),
+        Some("at://did:plc:friend/app.bsky.feed.post/3kiakvpaaaa2a")
+    );
+}
+
+#[tokio::test]
+async fn keeps_unverified_backfills_pending() {
+    let index = MemoryIndex::default();
+    index.request_backfill(VIEWER).await.unwrap();
+    let result = backfill::backfill(&pds(car()), &index, VIEWER, &TestKey::new(8).did_key).await;
+    assert!(matches!(
+        result,
+        Err(backfill::Error::Repo(repo::Error::InvalidSignature(_)))
+    ));
+    let result = backfill::backfill(
+        &pds(car()),
+        &index,
+        "did:plc:other",
+        &TestKey::new(7).did_key,
+    )
+    .await;
+    assert!(matches!(result, Err(backfill::Error::WrongRepo(did)) if did == VIEWER));
+    assert_eq!(pending(&index).await, [VIEWER]);
+    assert!(index
+        .records(
+            VIEWER,
+            &RecordKind::ALL,
+            &TimeCursor::at(Utc::now(), ""),
+            100
+        )
+        .await
+        .unwrap()
+        .is_empty());
+}
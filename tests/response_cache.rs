@@ -0,0 +1,146 @@
+mod common;
+
+use atrium_api::xrpc::HttpClient;
+use bsky_timemachine::response_cache::{
+    cache_key, CacheOptions, CachingHttpClient, MemoryCache, ResponseCache,
+};
+use common::MockHttpClient;
+use http::{Method, Request, Response};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+const SEARCH: &str = "https://api.bsky.app/xrpc/app.bsky.feed.searchPosts";
+const GET_POSTS: &str = "https://api.bsky.app/xrpc/app.bsky.feed.getPosts";
+
+fn upstream(url: &str, cache_control: Option<&'static str>) -> MockHttpClient {
+    MockHttpClient::default().route(url, move |_| {
+        let mut builder = Response::builder().status(200);
+        if let Some(cache_control) = cache_control {
+            builder = builder.header("cache-control", cache_control);
+        }
+        builder.body(b"{}".to_vec()).unwrap()
+    })
+}
+
+fn caching(client: &MockHttpClient) -> CachingHttpClient<MockHttpClient, MemoryCache> {
+    CachingHttpClient::with_options(
+        client.clone(),
+        MemoryCache::new(16),
+        CacheOptions {
+            min_ttl: HashMap::from([(
+                String::from("app.bsky.feed.searchPosts"),
+                Duration::from_secs(60),
+            )]),
+        },
+    )
+}
+
+fn get(url: &str) -> Request<Vec<u8>> {
+    Request::builder().uri(url).body(Vec::new()).unwrap()
+}
+
+#[tokio::test]
+async fn caches_by_method_and_query() {
+    let client = upstream(SEARCH, None);
+    let cached_client = caching(&client);
+    for url in [
+        format!("{SEARCH}?q=from:alice&limit=10"),
+        format!("{SEARCH}?limit=10&q=from:alice"),
+        format!("{SEARCH}?q=from:bob&limit=10"),
+    ] {
+        assert_eq!(
+            cached_client.send_http(get(&url)).await.unwrap().status(),
+            200
+        );
+    }
+    assert_eq!(client.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn honors_cache_control() {
+    let client = upstream(GET_POSTS, Some("public, max-age=30"));
+    let cached_client = caching(&client);
+    cached_client.send_http(get(GET_POSTS)).await.unwrap();
+    let cached = cached_client.send_http(get(GET_POSTS)).await.unwrap();
+    assert_eq!(cached.headers()["cache-control"], "max-age=30");
+    assert_eq!(client.requests().len(), 1);
+
+    let client = upstream(SEARCH, Some("no-store"));
+    let cached_client = caching(&client);
+    cached_client.send_http(get(SEARCH)).await.unwrap();
+    cached_client.send_http(get(SEARCH)).await.unwrap();
+    assert_eq!(client.requests().len(), 2);
+
+    // methods without a minimum TTL are only cached when the response allows it
+    let client = upstream(GET_POSTS, None);
+    let cached_client = caching(&client);
+    cached_client.send_http(get(GET_POSTS)).await.unwrap();
+    cached_client.send_http(get(GET_POSTS)).await.unwrap();
+    assert_eq!(client.requests().len(), 2);
+}
+
+#[test]
+fn does_not_share_authenticated_requests() {
+    let request = Request::builder()
+        .uri(SEARCH)
+        .header("authorization", "Bearer token")
+        .body(Vec::new())
+        .unwrap();
+    assert_eq!(cache_key(&request), None);
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(SEARCH)
+        .body(Vec::new())
+        .unwrap();
+    assert_eq!(cache_key(&request), None);
+}
+
+#[tokio::test]
+async fn evicts_the_least_recently_used_response() {
+    let cache = MemoryCache::new(2);
+    let response = || Response::new(Vec::new());
+    let ttl = Duration::from_secs(60);
+    cache.put("a", &response(), ttl).await.unwrap();
+    cache.put("b", &response(), ttl).await.unwrap();
+    cache.get("a").await.unwrap();
+    cache.put("c", &response(), ttl).await.unwrap();
+    assert!(cache.get("a").await.unwrap().is_some());
+    assert!(cache.get("b").await.unwrap().is_none());
+    assert!(cache.get("c").await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn revalidates_no_cache_responses() {
+    // full responses are numbered, to tell them from the stored one
+    let full = AtomicUsize::new(0);
+    let client = MockHttpClient::default().route(GET_POSTS, move |request| {
+        let builder = Response::builder()
+            .header("cache-control", "no-cache")
+            .header("etag", "\"v1\"");
+        match request.headers().get("if-none-match") {
+            Some(etag) if etag == "\"v1\"" => builder.status(304).body(Vec::new()),
+            _ => {
+                let n = full.fetch_add(1, Ordering::SeqCst);
+                builder.status(200).body(n.to_string().into_bytes())
+            }
+        }
+        .unwrap()
+    });
+    let cached_client = caching(&client);
+    let first = cached_client.send_http(get(GET_POSTS)).await.unwrap();
+    assert_eq!(first.status(), 200);
+    // stored, but served only once the upstream confirms it
+    let second = cached_client.send_http(get(GET_POSTS)).await.unwrap();
+    assert_eq!(second.status(), 200);
+    assert_eq!(second.body(), b"0");
+    assert!(!second.headers().contains_key("x-cache-revalidate"));
+    assert_eq!(client.requests().len(), 2);
+
+    // without a validator there is nothing to revalidate with
+    let client = upstream(SEARCH, Some("no-cache"));
+    let cached_client = caching(&client);
+    cached_client.send_http(get(SEARCH)).await.unwrap();
+    cached_client.send_http(get(SEARCH)).await.unwrap();
+    assert_eq!(client.requests().len(), 2);
+}
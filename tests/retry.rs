@@ -0,0 +1,187 @@
+mod common;
+
+use async_trait::async_trait;
+use atrium_api::xrpc::HttpClient;
+use bsky_timemachine::http_client::{
+    parse_retry_after, DeadlineExceeded, Idempotent, RequestDeadline, RetryOptions,
+    RetryingHttpClient, Sleep,
+};
+use common::MockHttpClient;
+use http::{Method, Request, Response};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const URL: &str = "https://plc.directory/did:plc:viewer";
+
+/// Records the delays instead of waiting.
+#[derive(Clone, Default)]
+struct RecordedSleep(Arc<Mutex<Vec<Duration>>>);
+
+#[async_trait]
+impl Sleep for RecordedSleep {
+    async fn sleep(&self, duration: Duration) {
+        self.0.lock().unwrap().push(duration);
+    }
+}
+
+/// Answers with `statuses` in turn, then 200.
+fn flaky(statuses: &'static [(u16, Option<&'static str>)]) -> MockHttpClient {
+    let calls = AtomicUsize::new(0);
+    MockHttpClient::default().route(URL, move |_| {
+        let mut builder = Response::builder();
+        match statuses.get(calls.fetch_add(1, Ordering::SeqCst)) {
+            Some((status, retry_after)) => {
+                builder = builder.status(*status);
+                if let Some(retry_after) = retry_after {
+                    builder = builder.header("retry-after", *retry_after);
+                }
+            }
+            None => builder = builder.status(200),
+        }
+        builder.body(Vec::new()).unwrap()
+    })
+}
+
+fn request(method: Method) -> Request<Vec<u8>> {
+    Request::builder()
+        .method(method)
+        .uri(URL)
+        .body(Vec::new())
+        .unwrap()
+}
+
+fn retrying(
+    client: &MockHttpClient,
+    sleep: &RecordedSleep,
+) -> RetryingHttpClient<MockHttpClient, RecordedSleep> {
+    RetryingHttpClient::new(client.clone(), sleep.clone())
+}
+
+#[tokio::test]
+async fn retries_idempotent_requests_with_backoff() {
+    let client = flaky(&[(502, None), (504, None)]);
+    let sleep = RecordedSleep::default();
+    let response = retrying(&client, &sleep)
+        .send_http(request(Method::GET))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(client.requests().len(), 3);
+    let delays = sleep.0.lock().unwrap().clone();
+    assert!((Duration::from_millis(100)..=Duration::from_millis(200)).contains(&delays[0]));
+    assert!((Duration::from_millis(200)..=Duration::from_millis(400)).contains(&delays[1]));
+}
+
+#[tokio::test]
+async fn returns_the_last_response_after_max_attempts() {
+    let client = flaky(&[(503, None), (503, None), (503, None), (503, None)]);
+    let response = retrying(&client, &RecordedSleep::default())
+        .send_http(request(Method::GET))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 503);
+    assert_eq!(client.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn retries_other_requests_only_when_asked_to() {
+    let client = flaky(&[(502, None)]);
+    let response = retrying(&client, &RecordedSleep::default())
+        .send_http(request(Method::POST))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 502);
+
+    let client = flaky(&[(502, None)]);
+    let mut idempotent = request(Method::POST);
+    idempotent.extensions_mut().insert(Idempotent);
+    let response = retrying(&client, &RecordedSleep::default())
+        .send_http(idempotent)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let client = flaky(&[(429, Some("2"))]);
+    let sleep = RecordedSleep::default();
+    let response = retrying(&client, &sleep)
+        .send_http(request(Method::POST))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(*sleep.0.lock().unwrap(), [Duration::from_secs(2)]);
+}
+
+#[tokio::test]
+async fn does_not_wait_past_the_deadline() {
+    let client = flaky(&[(429, Some("60"))]);
+    let sleep = RecordedSleep::default();
+    let response = RetryingHttpClient::with_options(
+        client.clone(),
+        sleep.clone(),
+        RetryOptions {
+            max_delay: Duration::from_secs(120),
+            deadline: Some(Duration::from_secs(10)),
+            ..Default::default()
+        },
+    )
+    .send_http(request(Method::GET))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 429);
+    assert!(sleep.0.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn does_not_retry_before_retry_after() {
+    // over the default max_delay of 5 seconds
+    let statuses: [&'static [(u16, Option<&str>)]; 2] =
+        [&[(429, Some("30"))], &[(503, Some("30"))]];
+    for statuses in statuses {
+        let client = flaky(statuses);
+        let sleep = RecordedSleep::default();
+        let response = retrying(&client, &sleep)
+            .send_http(request(Method::GET))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), statuses[0].0);
+        assert_eq!(client.requests().len(), 1);
+        assert!(sleep.0.lock().unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn fails_with_a_typed_error_past_the_deadline() {
+    let client = flaky(&[]);
+    let mut request = request(Method::GET);
+    request
+        .extensions_mut()
+        .insert(RequestDeadline(Duration::ZERO));
+    let err = retrying(&client, &RecordedSleep::default())
+        .send_http(request)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<DeadlineExceeded>(),
+        Some(&DeadlineExceeded(String::from(URL)))
+    );
+    assert!(client.requests().is_empty());
+}
+
+#[test]
+fn parses_retry_after() {
+    let now = "2024-01-01T00:00:00Z".parse().unwrap();
+    assert_eq!(
+        parse_retry_after("120", now),
+        Some(Duration::from_secs(120))
+    );
+    assert_eq!(
+        parse_retry_after("Mon, 01 Jan 2024 00:00:30 GMT", now),
+        Some(Duration::from_secs(30))
+    );
+    assert_eq!(
+        parse_retry_after("Sun, 31 Dec 2023 23:00:00 GMT", now),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon", now), None);
+}